@@ -0,0 +1,146 @@
+//! An append-only JSONL audit log of queries and their answers.
+//!
+//! Each answered query adds one line. Lines are written with a single
+//! `write` on a file opened in append mode, so concurrent runs sharing a log
+//! don't interleave partial lines. Only the request body and response are
+//! recorded, never headers, so the API key can't end up in the log.
+//!
+//! Requests given an [`AuditLog`] in their options are logged by
+//! [`send_request`] and [`send_streaming`] themselves, so every command that
+//! reaches the API is covered.
+//!
+//! [`send_request`]: crate::send_request
+//! [`send_streaming`]: crate::stream::send_streaming
+
+use crate::{extract_answer_text, unix_now, GrokRequest, GrokResponse, Usage};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the log file when `--log-file` isn't given
+pub const LOG_FILE_ENV: &str = "GROK_LOG_FILE";
+
+/// Answers longer than this many characters are cut short in the log
+pub const MAX_LOGGED_CHARS: usize = 4000;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    /// Unix seconds when the answer was received
+    pub timestamp: u64,
+    pub command: String,
+    pub model: String,
+    pub query: String,
+    pub response_id: Option<String>,
+    pub response_text: String,
+    pub usage: Option<Usage>,
+}
+
+impl AuditEntry {
+    pub fn new(command: &str, query: &str, request: &GrokRequest, response: &GrokResponse) -> Self {
+        Self {
+            timestamp: unix_now(),
+            command: command.to_string(),
+            model: request.model.clone(),
+            query: query.to_string(),
+            response_id: response.id.clone(),
+            response_text: truncate(&extract_answer_text(response), MAX_LOGGED_CHARS),
+            usage: response.usage.clone(),
+        }
+    }
+
+    /// Appends the entry to `path` as one line, creating the file if needed
+    pub fn append(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("Failed to write audit log {}", path.display()))
+    }
+}
+
+/// Where sent requests are logged, and the command they're filed under
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditLog {
+    pub path: PathBuf,
+    pub command: String,
+}
+
+impl AuditLog {
+    pub fn record(
+        &self,
+        query: &str,
+        request: &GrokRequest,
+        response: &GrokResponse,
+    ) -> Result<()> {
+        AuditEntry::new(&self.command, query, request, response).append(&self.path)
+    }
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}\u{2026}", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_request, RequestOptions};
+    use serde_json::json;
+
+    fn response(text: &str) -> GrokResponse {
+        serde_json::from_value(json!({
+            "id": "resp_1",
+            "status": "completed",
+            "output": [{
+                "type": "message",
+                "content": [{"type": "output_text", "text": text}]
+            }],
+            "usage": {"input_tokens": 12, "output_tokens": 34}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_entries_are_appended_one_per_line() {
+        let dir = std::env::temp_dir().join("grok-ask-audit-test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("logs").join("audit.jsonl");
+        let request = build_request("What is xAI?", &RequestOptions::default());
+
+        for text in ["xAI builds Grok.", &"a".repeat(MAX_LOGGED_CHARS + 10)] {
+            AuditEntry::new("ask", "What is xAI?", &request, &response(text))
+                .append(&path)
+                .unwrap();
+        }
+
+        let log = fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["command"], "ask");
+        assert_eq!(lines[0]["model"], crate::MODEL);
+        assert_eq!(lines[0]["query"], "What is xAI?");
+        assert_eq!(lines[0]["response_id"], "resp_1");
+        assert_eq!(lines[0]["response_text"], "xAI builds Grok.");
+        assert_eq!(lines[0]["usage"]["output_tokens"], 34);
+        assert!(lines[0]["timestamp"].as_u64().unwrap() > 0);
+        assert_eq!(
+            lines[1]["response_text"].as_str().unwrap().chars().count(),
+            MAX_LOGGED_CHARS + 1
+        );
+        assert!(!log.to_lowercase().contains("authorization"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Previously seen source URLs, for showing only what's new in a run.
+//!
+//! The baseline file holds one normalized URL per line.
+
+use crate::normalize_url;
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SourceBaseline {
+    urls: BTreeSet<String>,
+}
+
+impl SourceBaseline {
+    /// Loads a baseline, starting empty if the file doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read baseline {}", path.display()))?;
+        Ok(Self {
+            urls: data
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(normalize_url)
+                .collect(),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut data = String::new();
+        for url in &self.urls {
+            data.push_str(url);
+            data.push('\n');
+        }
+        fs::write(path, data)
+            .with_context(|| format!("Failed to write baseline {}", path.display()))
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.urls.contains(&normalize_url(url))
+    }
+
+    /// Sources whose URL isn't in the baseline, in their original order
+    pub fn new_sources(&self, sources: &[(String, String)]) -> Vec<(String, String)> {
+        sources
+            .iter()
+            .filter(|(_, url)| !self.contains(url))
+            .cloned()
+            .collect()
+    }
+
+    /// Adds every source URL; returns how many were new
+    pub fn extend(&mut self, sources: &[(String, String)]) -> usize {
+        sources
+            .iter()
+            .filter(|(_, url)| self.urls.insert(normalize_url(url)))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn source(url: &str) -> (String, String) {
+        ("Title".to_string(), url.to_string())
+    }
+
+    #[test]
+    fn test_baseline_diff_and_update() {
+        let path = env::temp_dir().join("grok-ask-baseline-test.txt");
+        fs::write(&path, "https://a.com/\nhttps://B.com/page\n").unwrap();
+
+        let mut baseline = SourceBaseline::load(&path).unwrap();
+        let current = vec![
+            source("https://a.com"),
+            source("https://c.com/new"),
+            source("https://b.com/page#section"),
+        ];
+
+        assert_eq!(
+            baseline.new_sources(&current),
+            vec![source("https://c.com/new")]
+        );
+
+        assert_eq!(baseline.extend(&current), 1);
+        baseline.save(&path).unwrap();
+
+        let reloaded = SourceBaseline::load(&path).unwrap();
+        assert!(reloaded.new_sources(&current).is_empty());
+        assert_eq!(reloaded, baseline);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_baseline_treats_all_as_new() {
+        let path = env::temp_dir().join("grok-ask-baseline-missing.txt");
+        let baseline = SourceBaseline::load(&path).unwrap();
+        let current = vec![source("https://a.com")];
+        assert_eq!(baseline.new_sources(&current), current);
+    }
+}
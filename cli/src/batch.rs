@@ -0,0 +1,249 @@
+//! Running a file of queries, one per line, as `ask` requests.
+
+use crate::cost::{token_cost, Pricing};
+use crate::{
+    estimate_tokens, format_response_with, send_request, FormatOptions, GrokResponse, OutputFormat,
+    RequestOptions,
+};
+use futures_util::stream::{self, StreamExt};
+use serde::Serialize;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// The queries in a batch file: every non-blank line, trimmed
+pub fn parse_batch(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// One query's outcome; a failed request or API error is kept as its message
+#[derive(Debug)]
+pub struct BatchResult {
+    pub query: String,
+    pub outcome: Result<GrokResponse, String>,
+    /// Wall-clock time for the request, including retries
+    pub latency: Duration,
+}
+
+impl BatchResult {
+    pub fn is_err(&self) -> bool {
+        self.outcome.is_err()
+    }
+}
+
+/// Sends every query with at most `concurrency` in flight, keeping input order
+pub async fn run_batch(
+    client: &reqwest::Client,
+    queries: &[String],
+    options: &RequestOptions,
+    concurrency: usize,
+) -> Vec<BatchResult> {
+    stream::iter(queries)
+        .map(|query| async move {
+            let start = Instant::now();
+            let outcome = match send_request(client, query, options).await {
+                Ok(response) => match &response.error {
+                    Some(error) => Err(error
+                        .message
+                        .clone()
+                        .unwrap_or_else(|| "Unknown error".to_string())),
+                    None => Ok(response),
+                },
+                Err(err) => Err(format!("{:#}", err)),
+            };
+            BatchResult {
+                query: query.clone(),
+                outcome,
+                latency: start.elapsed(),
+            }
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Renders the results as a JSON array of `{query, response}` or `{query, error}`
+pub fn batch_json(results: &[BatchResult]) -> serde_json::Value {
+    results
+        .iter()
+        .map(|result| match &result.outcome {
+            Ok(response) => json!({ "query": result.query, "response": response }),
+            Err(error) => json!({ "query": result.query, "error": error }),
+        })
+        .collect()
+}
+
+/// Renders each query as a `## ` header followed by its formatted answer or error
+pub fn batch_text(
+    results: &[BatchResult],
+    format: &OutputFormat,
+    options: &FormatOptions,
+) -> String {
+    let mut out = String::new();
+    for (i, result) in results.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        out.push_str(&format!("## {}\n\n", result.query));
+        match &result.outcome {
+            Ok(response) => out.push_str(&format_response_with(response, format, options)),
+            Err(error) => out.push_str(&format!("Error: {}\n", error)),
+        }
+    }
+    out
+}
+
+/// Projected tokens and cost for one query, counting output at its cap
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QueryEstimate {
+    pub query: String,
+    pub input_tokens: u64,
+    pub max_output_tokens: u64,
+    pub cost: f64,
+}
+
+/// Per-query estimates and their totals for a whole batch
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BatchEstimate {
+    pub queries: Vec<QueryEstimate>,
+    pub input_tokens: u64,
+    pub max_output_tokens: u64,
+    pub cost: f64,
+}
+
+/// Estimates a batch without sending it. Input counts the system instruction
+/// and query; output is the full `options.max_tokens`, so the cost is an upper
+/// bound apart from search tool charges, which can't be predicted.
+pub fn estimate_batch(
+    queries: &[String],
+    options: &RequestOptions,
+    pricing: &Pricing,
+) -> BatchEstimate {
+    let system_tokens = options
+        .system_instruction
+        .as_deref()
+        .map(estimate_tokens)
+        .unwrap_or(0);
+    let estimates: Vec<QueryEstimate> = queries
+        .iter()
+        .map(|query| {
+            let input_tokens = u64::from(system_tokens + estimate_tokens(query));
+            let max_output_tokens = u64::from(options.max_tokens);
+            QueryEstimate {
+                query: query.clone(),
+                input_tokens,
+                max_output_tokens,
+                cost: token_cost(input_tokens, max_output_tokens, pricing),
+            }
+        })
+        .collect();
+
+    BatchEstimate {
+        input_tokens: estimates.iter().map(|e| e.input_tokens).sum(),
+        max_output_tokens: estimates.iter().map(|e| e.max_output_tokens).sum(),
+        cost: estimates.iter().map(|e| e.cost).sum(),
+        queries: estimates,
+    }
+}
+
+/// One line per query, then the total
+pub fn format_batch_estimate(estimate: &BatchEstimate) -> String {
+    let mut out = String::new();
+    for (i, query) in estimate.queries.iter().enumerate() {
+        out.push_str(&format!(
+            "{}. ${:.4} ({} in / up to {} out) {}\n",
+            i + 1,
+            query.cost,
+            query.input_tokens,
+            query.max_output_tokens,
+            query.query
+        ));
+    }
+    out.push_str(&format!(
+        "Total for {} queries: up to ${:.4} ({} in / up to {} out tokens, excluding search tool fees)\n",
+        estimate.queries.len(),
+        estimate.cost,
+        estimate.input_tokens,
+        estimate.max_output_tokens
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results() -> Vec<BatchResult> {
+        let response: GrokResponse = serde_json::from_str(
+            r#"{"id": "r1", "output": [{"type": "message", "content": [{"type": "output_text", "text": "Yes."}]}]}"#,
+        )
+        .unwrap();
+        vec![
+            BatchResult {
+                query: "Is it?".to_string(),
+                outcome: Ok(response),
+                latency: Duration::from_millis(800),
+            },
+            BatchResult {
+                query: "Is it not?".to_string(),
+                outcome: Err("API returned 500".to_string()),
+                latency: Duration::from_secs(3),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_estimate_sums_per_query_lines() {
+        let queries = parse_batch("abcd\nabcdefgh\n");
+        let options = RequestOptions {
+            system_instruction: Some("sys.".to_string()),
+            max_tokens: 1000,
+            ..Default::default()
+        };
+        let pricing = Pricing {
+            input_per_million: 1.0,
+            output_per_million: 2.0,
+        };
+        let estimate = estimate_batch(&queries, &options, &pricing);
+
+        let inputs: Vec<u64> = estimate.queries.iter().map(|q| q.input_tokens).collect();
+        assert_eq!(inputs, vec![2, 3]);
+        assert_eq!(estimate.input_tokens, 5);
+        assert_eq!(estimate.max_output_tokens, 2000);
+        assert!((estimate.queries[0].cost - 0.002002).abs() < 1e-12);
+        assert!((estimate.cost - 0.004005).abs() < 1e-12);
+
+        let text = format_batch_estimate(&estimate);
+        assert!(text.starts_with("1. $0.0020 (2 in / up to 1000 out) abcd\n"));
+        assert!(text.contains("Total for 2 queries: up to $0.0040 (5 in / up to 2000 out tokens"));
+    }
+
+    #[test]
+    fn test_parse_batch_skips_blank_lines() {
+        assert_eq!(
+            parse_batch("one\n\n  two  \r\n\t\n"),
+            vec!["one".to_string(), "two".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_batch_json_records_errors() {
+        let json = batch_json(&results());
+        assert_eq!(json[0]["query"], "Is it?");
+        assert_eq!(json[0]["response"]["id"], "r1");
+        assert_eq!(json[1]["error"], "API returned 500");
+        assert!(json[1].get("response").is_none());
+    }
+
+    #[test]
+    fn test_batch_text_headers_each_query() {
+        let text = batch_text(&results(), &OutputFormat::Plain, &FormatOptions::default());
+        assert_eq!(
+            text,
+            "## Is it?\n\nYes.\n\n## Is it not?\n\nError: API returned 500\n"
+        );
+    }
+}
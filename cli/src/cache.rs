@@ -0,0 +1,350 @@
+//! On-disk response cache.
+//!
+//! The semantic cache keys on a normalized query (lowercased, trimmed,
+//! whitespace collapsed) so trivially different phrasings share an entry.
+//! The trade-off is that queries differing only in case or spacing always
+//! collide, even where case matters ("US" vs "us").
+//!
+//! The exact cache keys on the whole serialized request, so the model,
+//! messages, tools, previous response id, sampling and output settings all
+//! take part, and a follow-up never reuses the answer from another thread.
+//!
+//! Entries are written to a uniquely named temporary file and renamed into
+//! place, so concurrent writers never interleave and a reader always sees
+//! a complete entry: the old one or the new one.
+
+use crate::{unix_now, GrokRequest, GrokResponse};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Lowercases, trims, and collapses runs of whitespace to a single space
+pub fn normalize_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Cache key for a normalized query under a given model and system instruction
+pub fn semantic_key(model: &str, system_instruction: Option<&str>, query: &str) -> String {
+    hash_parts(&[
+        "semantic",
+        model,
+        system_instruction.unwrap_or(""),
+        &normalize_query(query),
+    ])
+}
+
+/// Cache key for an exact request: every field that is sent to the API
+pub fn exact_key(request: &GrokRequest) -> String {
+    let request = serde_json::to_string(request).unwrap_or_default();
+    hash_parts(&["exact", &request])
+}
+
+/// Prompt-caching hint shared by every request with the same model and system
+/// instruction, so the provider can reuse their cached prefix
+pub fn prompt_cache_key(model: &str, system_instruction: &str) -> String {
+    hash_parts(&["prompt", model, system_instruction])
+}
+
+fn hash_parts(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    created: u64,
+    response: GrokResponse,
+}
+
+/// A directory of cached responses, one JSON file per key
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<GrokResponse>> {
+        Ok(self.read_entry(key)?.map(|e| e.response))
+    }
+
+    /// Like [`get`](Self::get), but treats entries older than `max_age` as misses
+    pub fn get_fresh(&self, key: &str, max_age: Duration) -> Result<Option<GrokResponse>> {
+        let now = unix_now();
+        Ok(self
+            .read_entry(key)?
+            .filter(|e| now.saturating_sub(e.created) <= max_age.as_secs())
+            .map(|e| e.response))
+    }
+
+    fn read_entry(&self, key: &str) -> Result<Option<Entry>> {
+        let path = self.entry_path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read cache entry {}", path.display()))?;
+        // A corrupt entry is treated as a miss and overwritten on the next put
+        Ok(serde_json::from_str::<Entry>(&data).ok())
+    }
+
+    /// Deletes every entry, returning how many there were
+    pub fn clear(&self) -> Result<usize> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read cache dir {}", self.dir.display()))
+            }
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Stores a response; responses carrying an API error are never cached.
+    /// The `_meta` status and headers describe the live call, so they are dropped
+    /// rather than replayed on a later hit.
+    pub fn put(&self, key: &str, response: &GrokResponse) -> Result<()> {
+        if response.error.is_some() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache dir {}", self.dir.display()))?;
+        let entry = Entry {
+            created: unix_now(),
+            response: GrokResponse {
+                meta: None,
+                ..response.clone()
+            },
+        };
+        write_entry(&self.entry_path(key), &entry)
+    }
+}
+
+fn write_entry(path: &Path, entry: &Entry) -> Result<()> {
+    static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+    let data = serde_json::to_string(entry)?;
+    let temp = path.with_extension(format!(
+        "{}.{}.tmp",
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&temp, data)
+        .with_context(|| format!("Failed to write cache entry {}", temp.display()))?;
+    fs::rename(&temp, path).with_context(|| {
+        let _ = fs::remove_file(&temp);
+        format!("Failed to write cache entry {}", path.display())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meta::ResponseMeta;
+    use crate::ApiError;
+    use std::env;
+
+    fn response(id: &str) -> GrokResponse {
+        GrokResponse {
+            id: Some(id.to_string()),
+            status: Some("completed".to_string()),
+            finish_reason: None,
+            incomplete_details: None,
+            output: Some(vec![]),
+            usage: None,
+            error: None,
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn test_normalize_query() {
+        assert_eq!(normalize_query("  What   IS\tRust?\n"), "what is rust?");
+        assert_eq!(normalize_query(""), "");
+    }
+
+    #[test]
+    fn test_trivially_different_phrasings_collide() {
+        let a = semantic_key("m", Some("be concise"), "What is Rust?");
+        let b = semantic_key("m", Some("be concise"), "  what  is RUST? ");
+        assert_eq!(a, b);
+
+        // Different instruction (command) or model never shares an entry
+        assert_ne!(a, semantic_key("m", Some("search"), "What is Rust?"));
+        assert_ne!(
+            a,
+            semantic_key("other", Some("be concise"), "What is Rust?")
+        );
+        assert_ne!(a, semantic_key("m", Some("be concise"), "What is Go?"));
+    }
+
+    #[test]
+    fn test_exact_key_covers_model_system_query_and_tools() {
+        use crate::{build_request, RequestOptions};
+
+        let options = RequestOptions {
+            system_instruction: Some("be concise".to_string()),
+            use_web_search: true,
+            ..Default::default()
+        };
+        let key = exact_key(&build_request("What is Rust?", &options));
+        assert_eq!(key, exact_key(&build_request("What is Rust?", &options)));
+
+        // Unlike the semantic key, the query is taken as is
+        assert_ne!(key, exact_key(&build_request("what is rust?", &options)));
+        let variants = [
+            RequestOptions {
+                model: Some("other".to_string()),
+                ..options.clone()
+            },
+            RequestOptions {
+                system_instruction: None,
+                ..options.clone()
+            },
+            RequestOptions {
+                use_web_search: false,
+                ..options.clone()
+            },
+        ];
+        for variant in &variants {
+            assert_ne!(key, exact_key(&build_request("What is Rust?", variant)));
+        }
+    }
+
+    #[test]
+    fn test_exact_key_differs_by_previous_response_id() {
+        use crate::{build_request, RequestOptions};
+
+        let follow_up = |id: &str| RequestOptions {
+            previous_response_id: Some(id.to_string()),
+            ..Default::default()
+        };
+        assert_ne!(
+            exact_key(&build_request("summarize", &follow_up("resp_a"))),
+            exact_key(&build_request("summarize", &follow_up("resp_b")))
+        );
+    }
+
+    #[test]
+    fn test_ttl_and_clear() {
+        let dir = env::temp_dir().join("grok-ask-cache-ttl");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = ResponseCache::new(&dir);
+        assert_eq!(cache.clear().unwrap(), 0);
+
+        cache.put("k", &response("resp_1")).unwrap();
+        let stale = Entry {
+            created: unix_now() - 7200,
+            response: response("resp_old"),
+        };
+        write_entry(&cache.entry_path("old"), &stale).unwrap();
+
+        let hour = Duration::from_secs(3600);
+        assert!(cache.get_fresh("k", hour).unwrap().is_some());
+        assert!(cache.get_fresh("old", hour).unwrap().is_none());
+        assert!(cache.get("old").unwrap().is_some());
+
+        assert_eq!(cache.clear().unwrap(), 2);
+        assert!(cache.get("k").unwrap().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writes_stay_consistent() {
+        let dir = env::temp_dir().join("grok-ask-cache-concurrent");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = ResponseCache::new(&dir);
+
+        let handles: Vec<_> = (0..16)
+            .map(|i| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for round in 0..20 {
+                        cache
+                            .put("shared", &response(&format!("resp_{}_{}", i, round)))
+                            .unwrap();
+                        // Every read sees some writer's complete entry, never a torn one
+                        let read = cache.get("shared").unwrap().expect("entry present");
+                        assert!(read.id.unwrap().starts_with("resp_"));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert!(cache.get("shared").unwrap().is_some());
+        let leftovers: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .filter(|name| name != "shared.json")
+            .collect();
+        assert!(leftovers.is_empty(), "temp files left: {:?}", leftovers);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_put_get_round_trip_skips_errors() {
+        let dir = env::temp_dir().join("grok-ask-cache-roundtrip");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = ResponseCache::new(&dir);
+
+        assert!(cache.get("k").unwrap().is_none());
+        cache.put("k", &response("resp_cached")).unwrap();
+        assert_eq!(
+            cache.get("k").unwrap().unwrap().id.as_deref(),
+            Some("resp_cached")
+        );
+
+        let mut failed = response("resp_failed");
+        failed.error = Some(ApiError {
+            message: Some("boom".to_string()),
+            code: None,
+        });
+        cache.put("err", &failed).unwrap();
+        assert!(cache.get("err").unwrap().is_none());
+
+        let mut live = response("resp_live");
+        live.meta = Some(ResponseMeta::default());
+        cache.put("live", &live).unwrap();
+        assert!(cache.get("live").unwrap().unwrap().meta.is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
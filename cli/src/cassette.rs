@@ -0,0 +1,111 @@
+//! VCR-style record/replay of a single request/response exchange.
+//!
+//! The first run against a missing cassette file performs the real request
+//! and saves both sides; later runs replay the saved response without
+//! touching the network, provided they'd send the same request.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cassette {
+    pub request: Value,
+    pub response: Value,
+}
+
+impl Cassette {
+    /// Loads a cassette, returning `None` if nothing has been recorded yet
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cassette {}", path.display()))?;
+        let cassette = serde_json::from_str(&data)
+            .with_context(|| format!("Invalid cassette file {}", path.display()))?;
+        Ok(Some(cassette))
+    }
+
+    /// Fails if the cassette was recorded for a different request, so a stale
+    /// cassette can't answer a query it never saw
+    pub fn check_request(&self, request: &Value, path: &Path) -> Result<()> {
+        if self.request != *request {
+            bail!(
+                "Cassette {} was recorded for a different request; delete it to record this one",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let data = serde_json::to_string_pretty(self)?;
+        fs::write(path, data)
+            .with_context(|| format!("Failed to write cassette {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_request, create_request, format_response, GrokResponse, OutputFormat, RequestOptions,
+    };
+    use std::env;
+
+    #[tokio::test]
+    async fn test_recorded_cassette_replays_same_output() {
+        let body = serde_json::json!({
+            "id": "resp_cassette",
+            "status": "completed",
+            "output": [
+                {
+                    "type": "web_search_result",
+                    "results": [{"url": "https://example.com", "title": "Example"}]
+                },
+                {
+                    "type": "message",
+                    "content": [{"type": "output_text", "text": "Recorded answer."}]
+                }
+            ]
+        });
+        let recorded: GrokResponse = serde_json::from_value(body.clone()).unwrap();
+
+        let path = env::temp_dir().join("grok-ask-cassette-test.json");
+        let options = RequestOptions {
+            cassette: Some(path.clone()),
+            ..Default::default()
+        };
+        Cassette {
+            request: serde_json::to_value(build_request("anything", &options)).unwrap(),
+            response: body,
+        }
+        .save(&path)
+        .unwrap();
+
+        let replayed = create_request("anything", &options).await.unwrap();
+        let err = create_request("something else", &options)
+            .await
+            .unwrap_err()
+            .to_string();
+        fs::remove_file(&path).unwrap();
+        assert!(err.contains("was recorded for a different request"));
+
+        assert_eq!(
+            format_response(&replayed, &OutputFormat::Text),
+            format_response(&recorded, &OutputFormat::Text)
+        );
+    }
+
+    #[test]
+    fn test_missing_cassette_loads_none() {
+        let path = env::temp_dir().join("grok-ask-cassette-missing.json");
+        assert!(Cassette::load(&path).unwrap().is_none());
+    }
+}
@@ -0,0 +1,70 @@
+//! Terminal colors for text output.
+//!
+//! Color is only used when writing text straight to a terminal: never for
+//! JSON, `--output-file`, or piped output, and never when `NO_COLOR` is set
+//! or `--no-color` is passed.
+
+use owo_colors::OwoColorize;
+
+/// Whether to color output, given `--no-color`, the value of `NO_COLOR`, and
+/// whether text output is going straight to a terminal
+pub fn color_enabled(no_color_flag: bool, no_color_env: Option<&str>, text_to_tty: bool) -> bool {
+    // Per no-color.org, an empty NO_COLOR doesn't count
+    let env_disabled = no_color_env.is_some_and(|value| !value.is_empty());
+    text_to_tty && !no_color_flag && !env_disabled
+}
+
+/// Styles for the parts of text output; a no-op when disabled
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Palette {
+    pub enabled: bool,
+}
+
+impl Palette {
+    /// List numbers, dimmed
+    pub fn number(self, text: &str) -> String {
+        self.paint(text, |t| t.dimmed().to_string())
+    }
+
+    /// Source URLs, in cyan
+    pub fn link(self, text: &str) -> String {
+        self.paint(text, |t| t.cyan().to_string())
+    }
+
+    /// Error lines, in red
+    pub fn error(self, text: &str) -> String {
+        self.paint(text, |t| t.red().to_string())
+    }
+
+    fn paint(self, text: &str, style: impl Fn(&str) -> String) -> String {
+        if self.enabled {
+            style(text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_only_on_a_terminal_without_opt_outs() {
+        assert!(color_enabled(false, None, true));
+        assert!(color_enabled(false, Some(""), true));
+        assert!(!color_enabled(false, Some("1"), true));
+        assert!(!color_enabled(true, None, true));
+        assert!(!color_enabled(false, None, false));
+    }
+
+    #[test]
+    fn test_disabled_palette_is_plain() {
+        let palette = Palette { enabled: false };
+        assert_eq!(palette.link("https://x.ai"), "https://x.ai");
+        assert_eq!(
+            Palette { enabled: true }.error("Error: boom"),
+            "\x1b[31mError: boom\x1b[39m"
+        );
+    }
+}
@@ -0,0 +1,55 @@
+//! Side-by-side answers from two models, optionally scored by a judge model.
+
+/// Asks the judge to pick between two candidate answers to `query`.
+///
+/// The reply is expected to open with a `Verdict:` line naming `A`, `B`, or
+/// `Tie`, followed by the reasoning.
+pub fn build_judge_prompt(query: &str, answer_a: &str, answer_b: &str) -> String {
+    format!(
+        "You are judging two answers to the same question. Decide which answer \
+         better serves the question: consider accuracy, completeness, and use \
+         of sources.\n\n\
+         Question:\n{query}\n\n\
+         Answer A:\n{a}\n\n\
+         Answer B:\n{b}\n\n\
+         Reply with a first line of exactly `Verdict: A`, `Verdict: B`, or \
+         `Verdict: Tie`, then explain your reasoning.",
+        query = query.trim(),
+        a = answer_a.trim(),
+        b = answer_b.trim(),
+    )
+}
+
+/// Renders one labelled answer, e.g. `=== A (grok-4-1-fast) ===`
+pub fn format_candidate(label: &str, model: &str, answer: &str) -> String {
+    format!("=== {} ({}) ===\n{}\n", label, model, answer.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_judge_prompt_embeds_query_and_both_answers() {
+        let prompt = build_judge_prompt(
+            "  What is xAI?\n",
+            "xAI is an AI company.",
+            "It builds Grok.",
+        );
+
+        assert!(prompt.contains("Question:\nWhat is xAI?\n"));
+        assert!(prompt.contains("Answer A:\nxAI is an AI company.\n"));
+        assert!(prompt.contains("Answer B:\nIt builds Grok.\n"));
+        assert!(prompt.find("Answer A:").unwrap() < prompt.find("Answer B:").unwrap());
+        assert!(prompt.contains("Verdict: A"));
+        assert!(prompt.contains("Verdict: Tie"));
+    }
+
+    #[test]
+    fn test_format_candidate() {
+        assert_eq!(
+            format_candidate("A", "grok-4-1-fast", "Answer\n"),
+            "=== A (grok-4-1-fast) ===\nAnswer\n"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! The user config file (`config.toml` in the config directory).
+//!
+//! ```toml
+//! # Run bare `grok-ask "QUERY"` as this command
+//! default_command = "ask"
+//!
+//! [auth]
+//! api_key = "xai-..."
+//! ```
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    /// Command for a query given without one, e.g. `ask`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_command: Option<String>,
+    #[serde(default, skip_serializing_if = "Auth::is_empty")]
+    pub auth: Auth,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Auth {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+}
+
+impl Auth {
+    fn is_empty(&self) -> bool {
+        self.api_key.is_none()
+    }
+}
+
+impl Config {
+    /// Loads the config, starting empty if the file doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        toml::from_str(&data).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Writes the config, readable only by the owner on Unix since it may hold the API key
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let data = toml::to_string(self)?;
+        write_private(path, data.as_bytes())
+            .with_context(|| format!("Failed to write config {}", path.display()))
+    }
+
+    /// The configured API key, ignoring blank values
+    pub fn api_key(&self) -> Option<&str> {
+        self.auth
+            .api_key
+            .as_deref()
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // mode() only applies on creation; tighten an existing file too
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(data)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    fs::write(path, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_round_trip_and_missing_file() {
+        let dir = env::temp_dir().join("grok-ask-config-test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("config.toml");
+
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+        let mut config = Config::default();
+        config.auth.api_key = Some("xai-secret".to_string());
+        config.save(&path).unwrap();
+
+        let data = fs::read_to_string(&path).unwrap();
+        assert!(data.contains("[auth]"));
+        assert!(data.contains("api_key = \"xai-secret\""));
+        assert_eq!(Config::load(&path).unwrap().api_key(), Some("xai-secret"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_blank_key_is_ignored() {
+        let config: Config = toml::from_str("[auth]\napi_key = \"  \"\n").unwrap();
+        assert_eq!(config.api_key(), None);
+        let empty: Config = toml::from_str("").unwrap();
+        assert_eq!(empty.api_key(), None);
+        assert_eq!(empty.default_command, None);
+    }
+
+    #[test]
+    fn test_default_command_round_trips() {
+        let mut config: Config = toml::from_str("default_command = \"ask\"\n").unwrap();
+        assert_eq!(config.default_command.as_deref(), Some("ask"));
+
+        config.auth.api_key = Some("xai-secret".to_string());
+        let data = toml::to_string(&config).unwrap();
+        assert!(data.starts_with("default_command = \"ask\"\n"));
+        assert_eq!(toml::from_str::<Config>(&data).unwrap(), config);
+    }
+}
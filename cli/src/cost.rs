@@ -0,0 +1,182 @@
+//! Token pricing and spend tracking.
+
+use crate::Usage;
+use anyhow::{Context, Result};
+use std::env;
+
+/// Overrides for [`DEFAULT_PRICING`], in USD per million tokens
+pub const INPUT_COST_ENV: &str = "GROK_INPUT_COST";
+pub const OUTPUT_COST_ENV: &str = "GROK_OUTPUT_COST";
+
+/// USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+/// Published grok-4-1-fast pricing
+pub const DEFAULT_PRICING: Pricing = Pricing {
+    input_per_million: 0.20,
+    output_per_million: 0.50,
+};
+
+impl Default for Pricing {
+    fn default() -> Self {
+        DEFAULT_PRICING
+    }
+}
+
+impl Pricing {
+    /// Default pricing with any rates set in `GROK_INPUT_COST` / `GROK_OUTPUT_COST`
+    pub fn from_env() -> Result<Self> {
+        Self::from_rates(
+            env::var(INPUT_COST_ENV).ok().as_deref(),
+            env::var(OUTPUT_COST_ENV).ok().as_deref(),
+        )
+    }
+
+    /// Default pricing with the given per-million rates overriding it
+    pub fn from_rates(input: Option<&str>, output: Option<&str>) -> Result<Self> {
+        let parse = |name: &str, value: Option<&str>, default: f64| -> Result<f64> {
+            match value {
+                Some(value) => value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|rate: &f64| rate.is_finite() && *rate >= 0.0)
+                    .with_context(|| {
+                        format!("{} must be a non-negative number, got `{}`", name, value)
+                    }),
+                None => Ok(default),
+            }
+        };
+        Ok(Self {
+            input_per_million: parse(INPUT_COST_ENV, input, DEFAULT_PRICING.input_per_million)?,
+            output_per_million: parse(OUTPUT_COST_ENV, output, DEFAULT_PRICING.output_per_million)?,
+        })
+    }
+}
+
+/// Estimated USD cost of a response's token usage
+pub fn estimate_cost(usage: &Usage, pricing: &Pricing) -> f64 {
+    token_cost(
+        u64::from(usage.input_tokens.unwrap_or(0)),
+        u64::from(usage.output_tokens.unwrap_or(0)),
+        pricing,
+    )
+}
+
+/// Estimated USD cost of the given token counts
+pub fn token_cost(input_tokens: u64, output_tokens: u64, pricing: &Pricing) -> f64 {
+    (input_tokens as f64 * pricing.input_per_million
+        + output_tokens as f64 * pricing.output_per_million)
+        / 1_000_000.0
+}
+
+/// Cumulative spend against a fixed limit across a multi-query run
+#[derive(Debug, Clone)]
+pub struct CostBudget {
+    pub limit: f64,
+    pub pricing: Pricing,
+    pub spent: f64,
+    pub completed: usize,
+}
+
+impl CostBudget {
+    pub fn new(limit: f64, pricing: Pricing) -> Self {
+        Self {
+            limit,
+            pricing,
+            spent: 0.0,
+            completed: 0,
+        }
+    }
+
+    /// Records one finished query and its cost
+    pub fn record(&mut self, cost: f64) {
+        self.spent += cost;
+        self.completed += 1;
+    }
+
+    /// Whether another query fits, assuming it costs the average so far
+    pub fn allows_next(&self) -> bool {
+        self.allows(1)
+    }
+
+    /// Whether `queries` more fit, assuming each costs the average so far
+    pub fn allows(&self, queries: usize) -> bool {
+        let average = if self.completed == 0 {
+            0.0
+        } else {
+            self.spent / self.completed as f64
+        };
+        self.spent + average * queries as f64 <= self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(input: u32, output: u32) -> Usage {
+        Usage {
+            input_tokens: Some(input),
+            output_tokens: Some(output),
+        }
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        let cost = estimate_cost(&usage(1_000_000, 1_000_000), &DEFAULT_PRICING);
+        assert!((cost - 0.70).abs() < 1e-9);
+
+        let missing = Usage {
+            input_tokens: None,
+            output_tokens: None,
+        };
+        assert_eq!(estimate_cost(&missing, &DEFAULT_PRICING), 0.0);
+    }
+
+    #[test]
+    fn test_rates_override_defaults() {
+        assert_eq!(Pricing::from_rates(None, None).unwrap(), DEFAULT_PRICING);
+
+        let pricing = Pricing::from_rates(Some("3"), None).unwrap();
+        assert_eq!(pricing.input_per_million, 3.0);
+        assert_eq!(
+            pricing.output_per_million,
+            DEFAULT_PRICING.output_per_million
+        );
+
+        let err = Pricing::from_rates(None, Some("cheap"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("GROK_OUTPUT_COST"));
+        assert!(Pricing::from_rates(Some("-1"), None).is_err());
+    }
+
+    #[test]
+    fn test_budget_aborts_before_exceeding_limit() {
+        let mut budget = CostBudget::new(0.10, DEFAULT_PRICING);
+        assert!(budget.allows_next());
+
+        budget.record(0.03);
+        assert!(budget.allows_next()); // 0.03 + 0.03 <= 0.10
+        assert!(!budget.allows(3)); // 0.03 + 3 * 0.03 > 0.10
+        budget.record(0.03);
+        assert!(budget.allows_next()); // 0.06 + 0.03 <= 0.10
+        budget.record(0.03);
+        assert!(!budget.allows_next()); // 0.09 + 0.03 > 0.10
+
+        assert_eq!(budget.completed, 3);
+        assert!((budget.spent - 0.09).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_budget_stops_once_spent_exceeds_limit() {
+        let mut budget = CostBudget::new(0.01, DEFAULT_PRICING);
+        budget.record(0.05);
+        assert!(!budget.allows_next());
+    }
+}
@@ -0,0 +1,375 @@
+//! Calendar date parsing for the X search date filters.
+//!
+//! The API wants `YYYY-MM-DD`. Without an explicit [`DateFormat`], ISO dates,
+//! `MM/DD/YYYY` and `DD.MM.YYYY` are all accepted; a slashed date that reads
+//! as a different valid day either way round (`03/04/2025`) is rejected as
+//! ambiguous.
+
+use crate::{unix_now, XSearchConfig};
+use anyhow::{bail, Result};
+use std::fmt;
+
+/// A calendar date, ordered chronologically
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl Date {
+    /// The UTC date `secs` seconds after the Unix epoch
+    pub fn from_unix_secs(secs: u64) -> Date {
+        Date::from_days((secs / 86_400) as i64)
+    }
+
+    /// Today's date in UTC
+    pub fn today() -> Date {
+        Date::from_unix_secs(unix_now())
+    }
+
+    /// The date `days` later, or earlier when negative
+    pub fn add_days(self, days: i64) -> Date {
+        Date::from_days(self.to_days() + days)
+    }
+
+    /// Days since 1970-01-01, using Howard Hinnant's `days_from_civil`
+    fn to_days(self) -> i64 {
+        let month = i64::from(self.month);
+        let year = i64::from(self.year) - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// The inverse of [`Date::to_days`] (`civil_from_days`)
+    fn from_days(days: i64) -> Date {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        Date {
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+        }
+    }
+}
+
+fn is_leap_year(year: u32) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// How to read a date argument
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum DateFormat {
+    /// YYYY-MM-DD
+    Iso,
+    /// MM/DD/YYYY
+    Us,
+    /// DD.MM.YYYY or DD/MM/YYYY
+    Eu,
+}
+
+/// Splits `value` on `sep` into three numbers whose digit counts fall in the given ranges
+fn split_fields(value: &str, sep: char, lens: [(usize, usize); 3]) -> Option<[u32; 3]> {
+    let parts: Vec<&str> = value.trim().split(sep).collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let mut fields = [0; 3];
+    for ((field, part), (min, max)) in fields.iter_mut().zip(&parts).zip(lens) {
+        if !(min..=max).contains(&part.len()) || !part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        *field = part.parse().ok()?;
+    }
+    Some(fields)
+}
+
+/// Parses a `YYYY-MM-DD` date, rejecting impossible ones like `2025-02-30`
+pub fn parse_date(value: &str) -> Result<Date> {
+    let Some([year, month, day]) = split_fields(value, '-', [(4, 4), (2, 2), (2, 2)]) else {
+        bail!("Invalid date `{}`: expected YYYY-MM-DD", value);
+    };
+    checked_date(value, year, month, day)
+}
+
+/// Parses a date in `format`, or in any unambiguous common format when `None`
+pub fn parse_date_as(value: &str, format: Option<DateFormat>) -> Result<Date> {
+    const DAY_MONTH_YEAR: [(usize, usize); 3] = [(1, 2), (1, 2), (4, 4)];
+    let trimmed = value.trim();
+
+    match format {
+        Some(DateFormat::Iso) => parse_date(trimmed),
+        Some(DateFormat::Us) => match split_fields(trimmed, '/', DAY_MONTH_YEAR) {
+            Some([month, day, year]) => checked_date(value, year, month, day),
+            None => bail!("Invalid date `{}`: expected MM/DD/YYYY", value),
+        },
+        Some(DateFormat::Eu) => match split_fields(trimmed, '.', DAY_MONTH_YEAR)
+            .or_else(|| split_fields(trimmed, '/', DAY_MONTH_YEAR))
+        {
+            Some([day, month, year]) => checked_date(value, year, month, day),
+            None => bail!("Invalid date `{}`: expected DD.MM.YYYY", value),
+        },
+        None => {
+            if let Some([year, month, day]) = split_fields(trimmed, '/', [(4, 4), (1, 2), (1, 2)])
+                .or_else(|| split_fields(trimmed, '-', [(4, 4), (1, 2), (1, 2)]))
+            {
+                return checked_date(value, year, month, day);
+            }
+            if let Some([day, month, year]) = split_fields(trimmed, '.', DAY_MONTH_YEAR) {
+                return checked_date(value, year, month, day);
+            }
+            if let Some([first, second, year]) = split_fields(trimmed, '/', DAY_MONTH_YEAR) {
+                let us = checked_date(value, year, first, second);
+                let eu = checked_date(value, year, second, first);
+                return match (us, eu) {
+                    (Ok(us), Ok(eu)) if us != eu => bail!(
+                        "Ambiguous date `{}`: could be {} or {}; pass --date-format us or eu",
+                        value,
+                        us,
+                        eu
+                    ),
+                    (Ok(date), _) | (_, Ok(date)) => Ok(date),
+                    (Err(e), Err(_)) => Err(e),
+                };
+            }
+            bail!(
+                "Invalid date `{}`: expected YYYY-MM-DD, MM/DD/YYYY or DD.MM.YYYY",
+                value
+            )
+        }
+    }
+}
+
+fn checked_date(value: &str, year: u32, month: u32, day: u32) -> Result<Date> {
+    if !(1..=12).contains(&month) {
+        bail!("Invalid date `{}`: month must be 01-12", value);
+    }
+    if !(1..=days_in_month(year, month)).contains(&day) {
+        bail!(
+            "Invalid date `{}`: {:04}-{:02} has {} days",
+            value,
+            year,
+            month,
+            days_in_month(year, month)
+        );
+    }
+    Ok(Date { year, month, day })
+}
+
+/// Checks both dates parse and that the range isn't reversed
+pub fn validate_date_range(from: Option<&str>, to: Option<&str>) -> Result<()> {
+    let from = from.map(parse_date).transpose()?;
+    let to = to.map(parse_date).transpose()?;
+    if let (Some(from), Some(to)) = (from, to) {
+        if from > to {
+            bail!("--from-date {} is after --to-date {}", from, to);
+        }
+    }
+    Ok(())
+}
+
+/// Sets the window to the last `days` days up to `today`, keeping an explicit
+/// `to_date`. Fails if `from_date` is already set.
+pub fn apply_since_days(config: &mut XSearchConfig, days: u32, today: Date) -> Result<()> {
+    if config.from_date.is_some() {
+        bail!("--since-days can't be combined with --from-date");
+    }
+    config.from_date = Some(today.add_days(-i64::from(days)).to_string());
+    config.to_date.get_or_insert_with(|| today.to_string());
+    validate_date_range(config.from_date.as_deref(), config.to_date.as_deref())
+}
+
+/// Moves `from_date` back and `to_date` forward by `days`, returning false
+/// when there's no window to widen
+pub fn widen_window(config: &mut XSearchConfig, days: u32) -> Result<bool> {
+    let days = i64::from(days);
+    let mut widened = false;
+    if let Some(from) = &mut config.from_date {
+        *from = parse_date(from)?.add_days(-days).to_string();
+        widened = true;
+    }
+    if let Some(to) = &mut config.to_date {
+        *to = parse_date(to)?.add_days(days).to_string();
+        widened = true;
+    }
+    Ok(widened)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_dates() {
+        assert_eq!(
+            parse_date("2025-01-15").unwrap(),
+            Date {
+                year: 2025,
+                month: 1,
+                day: 15
+            }
+        );
+        assert!(parse_date("2024-02-29").is_ok());
+        assert!(validate_date_range(Some("2025-01-01"), Some("2025-01-01")).is_ok());
+        assert!(validate_date_range(None, Some("2025-01-01")).is_ok());
+    }
+
+    #[test]
+    fn test_malformed_and_impossible_dates_rejected() {
+        for bad in [
+            "2025-13-40",
+            "2025-02-29",
+            "2025-04-31",
+            "2025-1-5",
+            "25-01-01",
+            "2025/01/01",
+            "yesterday",
+            "2025-0a-01",
+        ] {
+            assert!(parse_date(bad).is_err(), "{bad} should be rejected");
+        }
+        let err = parse_date("2025-13-40").unwrap_err().to_string();
+        assert!(err.contains("month must be 01-12"));
+    }
+
+    #[test]
+    fn test_common_formats_normalize_to_iso() {
+        let iso = |value: &str, format| parse_date_as(value, format).unwrap().to_string();
+
+        assert_eq!(iso("2025-01-15", None), "2025-01-15");
+        assert_eq!(iso("2025/1/5", None), "2025-01-05");
+        assert_eq!(iso("12/25/2025", None), "2025-12-25");
+        assert_eq!(iso("25.12.2025", None), "2025-12-25");
+        assert_eq!(iso("7/7/2025", None), "2025-07-07");
+        assert_eq!(iso("03/04/2025", Some(DateFormat::Us)), "2025-03-04");
+        assert_eq!(iso("03/04/2025", Some(DateFormat::Eu)), "2025-04-03");
+        assert_eq!(iso("3.4.2025", Some(DateFormat::Eu)), "2025-04-03");
+    }
+
+    #[test]
+    fn test_ambiguous_and_mismatched_input_rejected() {
+        let err = parse_date_as("03/04/2025", None).unwrap_err().to_string();
+        assert!(err.contains("Ambiguous"));
+        assert!(err.contains("2025-03-04 or 2025-04-03"));
+
+        assert!(parse_date_as("13/13/2025", None).is_err());
+        assert!(parse_date_as("12/25/2025", Some(DateFormat::Eu)).is_err());
+        assert!(parse_date_as("25.12.2025", Some(DateFormat::Iso)).is_err());
+        assert!(parse_date_as("next tuesday", None).is_err());
+    }
+
+    #[test]
+    fn test_reversed_range_rejected() {
+        let err = validate_date_range(Some("2025-02-01"), Some("2025-01-15"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("--from-date 2025-02-01 is after --to-date 2025-01-15"));
+    }
+
+    #[test]
+    fn test_add_days_across_months_and_years() {
+        let date = parse_date("2024-03-01").unwrap();
+        assert_eq!(date.add_days(-1).to_string(), "2024-02-29");
+        assert_eq!(date.add_days(0), date);
+        assert_eq!(
+            parse_date("2025-12-28").unwrap().add_days(7).to_string(),
+            "2026-01-04"
+        );
+        assert_eq!(
+            parse_date("2025-01-03").unwrap().add_days(-7).to_string(),
+            "2024-12-27"
+        );
+        // Century years are leap years only when divisible by 400
+        assert_eq!(
+            parse_date("2100-02-28").unwrap().add_days(1).to_string(),
+            "2100-03-01"
+        );
+        assert_eq!(
+            parse_date("2000-02-28").unwrap().add_days(1).to_string(),
+            "2000-02-29"
+        );
+    }
+
+    #[test]
+    fn test_widen_window() {
+        let mut config = XSearchConfig {
+            from_date: Some("2025-01-10".to_string()),
+            to_date: Some("2025-01-12".to_string()),
+            ..Default::default()
+        };
+        assert!(widen_window(&mut config, 7).unwrap());
+        assert_eq!(config.from_date.as_deref(), Some("2025-01-03"));
+        assert_eq!(config.to_date.as_deref(), Some("2025-01-19"));
+
+        assert!(!widen_window(&mut XSearchConfig::default(), 7).unwrap());
+    }
+
+    #[test]
+    fn test_today_from_unix_secs() {
+        assert_eq!(Date::from_unix_secs(0).to_string(), "1970-01-01");
+        // 2024-02-29T23:59:59Z
+        assert_eq!(
+            Date::from_unix_secs(1_709_251_199).to_string(),
+            "2024-02-29"
+        );
+        // 2262-04-12T00:00:00Z
+        assert_eq!(
+            Date::from_unix_secs(9_223_372_800).to_string(),
+            "2262-04-12"
+        );
+    }
+
+    #[test]
+    fn test_since_days_from_fixed_clock() {
+        let today = parse_date("2025-03-03").unwrap();
+        let mut config = XSearchConfig::default();
+        apply_since_days(&mut config, 7, today).unwrap();
+        assert_eq!(config.from_date.as_deref(), Some("2025-02-24"));
+        assert_eq!(config.to_date.as_deref(), Some("2025-03-03"));
+
+        let mut config = XSearchConfig {
+            to_date: Some("2025-03-01".to_string()),
+            ..Default::default()
+        };
+        apply_since_days(&mut config, 30, today).unwrap();
+        assert_eq!(config.from_date.as_deref(), Some("2025-02-01"));
+        assert_eq!(config.to_date.as_deref(), Some("2025-03-01"));
+
+        let mut config = XSearchConfig {
+            from_date: Some("2025-01-01".to_string()),
+            ..Default::default()
+        };
+        let err = apply_since_days(&mut config, 7, today).unwrap_err();
+        assert!(err.to_string().contains("--from-date"));
+    }
+}
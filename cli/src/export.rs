@@ -0,0 +1,326 @@
+//! Transcripts of saved threads, as standalone HTML or plain text.
+
+use crate::priming::PriorMessage;
+use crate::{collect_sources, extract_answer_text, GrokResponse};
+use pulldown_cmark::{html, Event, Options, Parser, Tag, TagEnd};
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:46rem;margin:2rem auto;padding:0 1rem;line-height:1.55;color:#1f2328}\
+h1{font-size:1.4rem}\
+.turn{border-top:1px solid #d0d7de;padding:1rem 0}\
+.query{font-weight:600;background:#f6f8fa;border-radius:6px;padding:.6rem .8rem}\
+.role{font-size:.8rem;text-transform:uppercase;color:#59636e;margin:.6rem 0 .3rem}\
+.sources{font-size:.9rem}\
+.error{color:#b42318}\
+pre,code{background:#f6f8fa;border-radius:4px}";
+
+/// One exchange in a transcript: the user's query and the stored response
+pub struct TranscriptTurn<'a> {
+    pub query: &'a str,
+    pub response: &'a GrokResponse,
+}
+
+/// Who a part of a transcript came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    /// The role of a message, from its API name (`system`, `user`, `assistant`)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "system" => Some(Role::System),
+            "user" => Some(Role::User),
+            "assistant" => Some(Role::Assistant),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Role::System => "System",
+            Role::User => "User",
+            Role::Assistant => "Assistant",
+        }
+    }
+}
+
+/// Renders a thread as plain text, optionally prefixing each part with its role
+pub fn render_text(turns: &[TranscriptTurn], show_roles: bool) -> String {
+    let prefix = |role: Role| {
+        if show_roles {
+            format!("{}: ", role.label())
+        } else {
+            String::new()
+        }
+    };
+
+    let mut out = String::new();
+    for (i, turn) in turns.iter().enumerate() {
+        if i > 0 {
+            out.push_str("\n---\n\n");
+        }
+        out.push_str(&format!("{}{}\n\n", prefix(Role::User), turn.query));
+        match &turn.response.error {
+            Some(error) => out.push_str(&format!(
+                "{}Error: {}\n",
+                prefix(Role::Assistant),
+                error.message.as_deref().unwrap_or("Unknown error")
+            )),
+            None => {
+                out.push_str(&format!(
+                    "{}{}\n",
+                    prefix(Role::Assistant),
+                    extract_answer_text(turn.response).trim_end()
+                ));
+                let sources = collect_sources(turn.response);
+                if !sources.is_empty() {
+                    out.push_str("\nSources:\n");
+                    for (n, (title, url)) in sources.iter().enumerate() {
+                        out.push_str(&format!("{}. [{}]({})\n", n + 1, title, url));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Renders the priming messages (from `--messages-file` and `--example`), then
+/// the query and its formatted answer, each labeled with its role
+pub fn render_primed(messages: &[PriorMessage], query: &str, answer: &str) -> String {
+    let mut out = String::new();
+    for message in messages {
+        let label =
+            Role::from_name(&message.role).map_or(message.role.as_str(), |role| role.label());
+        out.push_str(&format!("{}: {}\n\n", label, message.content.trim_end()));
+    }
+    out.push_str(&format!(
+        "---\n\n{}: {}\n\n{}: {}",
+        Role::User.label(),
+        query,
+        Role::Assistant.label(),
+        answer
+    ));
+    out
+}
+
+/// Renders a thread as a self-contained HTML page with linked sources
+pub fn render_html(title: &str, turns: &[TranscriptTurn], show_roles: bool) -> String {
+    let role = |role: Role| {
+        if show_roles {
+            format!("<div class=\"role\">{}</div>\n", role.label())
+        } else {
+            String::new()
+        }
+    };
+
+    let mut body = String::new();
+    for turn in turns {
+        body.push_str("<section class=\"turn\">\n");
+        body.push_str(&role(Role::User));
+        body.push_str(&format!(
+            "<div class=\"query\">{}</div>\n",
+            escape(turn.query)
+        ));
+        body.push_str(&role(Role::Assistant));
+        match &turn.response.error {
+            Some(error) => body.push_str(&format!(
+                "<p class=\"error\">Error: {}</p>\n",
+                escape(error.message.as_deref().unwrap_or("Unknown error"))
+            )),
+            None => {
+                body.push_str(&markdown_to_html(&extract_answer_text(turn.response)));
+                let sources = collect_sources(turn.response);
+                if !sources.is_empty() {
+                    body.push_str("<ol class=\"sources\">\n");
+                    for (source_title, url) in sources {
+                        if is_safe_url(&url) {
+                            body.push_str(&format!(
+                                "<li><a href=\"{}\">{}</a></li>\n",
+                                escape(&url),
+                                escape(&source_title)
+                            ));
+                        } else {
+                            body.push_str(&format!("<li>{}</li>\n", escape(&source_title)));
+                        }
+                    }
+                    body.push_str("</ol>\n");
+                }
+            }
+        }
+        body.push_str("</section>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <h1>{title}</h1>\n{body}</body>\n</html>\n",
+        title = escape(title),
+    )
+}
+
+/// Renders the model's markdown. Raw HTML is shown as text and links to
+/// anything but http(s) or mailto keep their text but lose the anchor, so a
+/// prompt-injected answer can't run script in the exported page.
+fn markdown_to_html(markdown: &str) -> String {
+    let parser = Parser::new_ext(
+        markdown,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    );
+    let mut dropped_link = false;
+    let events = parser.filter_map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Some(Event::Text(html)),
+        Event::Start(Tag::Link { ref dest_url, .. }) => {
+            dropped_link = !is_safe_url(dest_url);
+            (!dropped_link).then_some(event)
+        }
+        Event::End(TagEnd::Link) => (!std::mem::take(&mut dropped_link)).then_some(event),
+        event => Some(event),
+    });
+    let mut out = String::new();
+    html::push_html(&mut out, events);
+    out
+}
+
+/// Whether a link target is safe to put in an `href`: http(s) or mailto only
+fn is_safe_url(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_contains_answer_and_source_anchors() {
+        let response: GrokResponse = serde_json::from_str(
+            r#"{
+                "id": "resp_1",
+                "output": [{
+                    "type": "message",
+                    "content": [{
+                        "type": "output_text",
+                        "text": "xAI builds **Grok**.",
+                        "annotations": [{"url": "https://x.ai/grok", "title": "Grok"}]
+                    }]
+                }]
+            }"#,
+        )
+        .unwrap();
+        let turns = [TranscriptTurn {
+            query: "What does <xAI> build?",
+            response: &response,
+        }];
+
+        let html = render_html("research", &turns, false);
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>research</title>"));
+        assert!(html.contains("What does &lt;xAI&gt; build?"));
+        assert!(html.contains("<p>xAI builds <strong>Grok</strong>.</p>"));
+        assert!(html.contains("<a href=\"https://x.ai/grok\">Grok</a>"));
+        assert!(!html.contains("<div class=\"role\">"));
+        assert!(render_html("research", &turns, true).contains("<div class=\"role\">User</div>"));
+    }
+
+    #[test]
+    fn test_html_neutralizes_raw_html_and_script_links() {
+        let response: GrokResponse = serde_json::from_str(
+            r#"{
+                "id": "resp_1",
+                "output": [
+                    {"type": "web_search_result", "results": [
+                        {"url": "javascript:alert(2)", "title": "Bad source"},
+                        {"url": "https://x.ai", "title": "Good source"}
+                    ]},
+                    {"type": "message", "content": [{
+                        "type": "output_text",
+                        "text": "<script>alert(0)</script>\n\nSee [x](javascript:alert(1)), <b>this</b> and [docs](https://docs.x.ai) or [mail](mailto:a@x.ai)."
+                    }]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let turns = [TranscriptTurn {
+            query: "q",
+            response: &response,
+        }];
+
+        let html = render_html("t", &turns, false);
+
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;alert(0)&lt;/script&gt;"));
+        assert!(html.contains("&lt;b&gt;this&lt;/b&gt;"));
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("See x, "));
+        assert!(html.contains("<a href=\"https://docs.x.ai\">docs</a>"));
+        assert!(html.contains("<a href=\"mailto:a@x.ai\">mail</a>"));
+        assert!(html.contains("<li>Bad source</li>"));
+        assert!(html.contains("<li><a href=\"https://x.ai\">Good source</a></li>"));
+    }
+
+    #[test]
+    fn test_text_transcript_labels_roles() {
+        let first: GrokResponse = serde_json::from_str(
+            r#"{"id": "r1", "output": [{"type": "message", "content": [{"type": "output_text", "text": "Hi there."}]}]}"#,
+        )
+        .unwrap();
+        let second: GrokResponse =
+            serde_json::from_str(r#"{"id": "r2", "error": {"message": "rate limited"}}"#).unwrap();
+        let turns = [
+            TranscriptTurn {
+                query: "Hello",
+                response: &first,
+            },
+            TranscriptTurn {
+                query: "Again",
+                response: &second,
+            },
+        ];
+
+        assert_eq!(
+            render_text(&turns, true),
+            "User: Hello\n\nAssistant: Hi there.\n\n---\n\nUser: Again\n\nAssistant: Error: rate limited\n"
+        );
+        assert_eq!(
+            render_text(&turns, false),
+            "Hello\n\nHi there.\n\n---\n\nAgain\n\nError: rate limited\n"
+        );
+    }
+
+    #[test]
+    fn test_primed_transcript_labels_roles() {
+        let messages = [
+            PriorMessage::new("system", "Answer in one word."),
+            PriorMessage::new("user", "Capital of France?"),
+            PriorMessage::new("assistant", "Paris"),
+        ];
+        assert_eq!(
+            render_primed(&messages, "Capital of Italy?", "Rome\n"),
+            "System: Answer in one word.\n\nUser: Capital of France?\n\nAssistant: Paris\n\n\
+             ---\n\nUser: Capital of Italy?\n\nAssistant: Rome\n"
+        );
+    }
+}
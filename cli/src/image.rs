@@ -0,0 +1,85 @@
+//! Image attachments for vision queries, sent inline as base64 data URLs.
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::fs;
+use std::io::{self, Read};
+
+/// The image MIME type from its leading magic bytes
+pub fn detect_mime(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// Encodes image bytes as a `data:` URL, rejecting formats the API can't take
+pub fn data_url(bytes: &[u8]) -> Result<String> {
+    let Some(mime) = detect_mime(bytes) else {
+        bail!("Unrecognized image format; expected PNG, JPEG, GIF, or WebP");
+    };
+    Ok(format!("data:{};base64,{}", mime, STANDARD.encode(bytes)))
+}
+
+/// Reads an image from `path`, or from stdin when `path` is `-`, as a data URL
+pub fn load_image(path: &str) -> Result<String> {
+    let bytes = if path == "-" {
+        let mut bytes = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .context("Failed to read image from stdin")?;
+        if bytes.is_empty() {
+            bail!("No image data on stdin");
+        }
+        bytes
+    } else {
+        fs::read(path).with_context(|| format!("Failed to read image {}", path))?
+    };
+    data_url(&bytes).with_context(|| format!("Can't attach {}", display_name(path)))
+}
+
+fn display_name(path: &str) -> &str {
+    if path == "-" {
+        "stdin"
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_png_and_jpeg_headers() {
+        assert_eq!(
+            detect_mime(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            Some("image/png")
+        );
+        assert_eq!(
+            detect_mime(&[0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10]),
+            Some("image/jpeg")
+        );
+        assert_eq!(detect_mime(b"GIF89a..."), Some("image/gif"));
+        assert_eq!(detect_mime(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(detect_mime(b"%PDF-1.7"), None);
+        assert_eq!(detect_mime(b""), None);
+    }
+
+    #[test]
+    fn test_data_url_encoding() {
+        assert_eq!(
+            data_url(&[0xFF, 0xD8, 0xFF]).unwrap(),
+            "data:image/jpeg;base64,/9j/"
+        );
+        assert!(data_url(b"plain text").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! Interrupting an in-flight answer to return to the prompt.
+//!
+//! The interactive loop moves between [`TurnState`]s: a submitted prompt
+//! starts an answer, and an interrupt cancels it and goes straight back to
+//! prompting instead of waiting for the answer to finish.
+
+use std::future::Future;
+use tokio::sync::mpsc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TurnState {
+    /// Waiting for the user to type a prompt
+    Prompting,
+    /// An answer is arriving
+    Answering,
+    /// The answer was cancelled; the next prompt is shown immediately
+    Interrupted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TurnEvent {
+    Submit,
+    Interrupt,
+    Finished,
+}
+
+impl TurnState {
+    pub fn next(self, event: TurnEvent) -> TurnState {
+        match (self, event) {
+            (TurnState::Prompting, TurnEvent::Submit) => TurnState::Answering,
+            (TurnState::Answering, TurnEvent::Interrupt) => TurnState::Interrupted,
+            (TurnState::Answering, TurnEvent::Finished) => TurnState::Prompting,
+            (TurnState::Interrupted, _) => TurnState::Prompting,
+            // Interrupts while prompting and stray completions are no-ops
+            (state, _) => state,
+        }
+    }
+
+    /// Whether the in-flight request should be dropped
+    pub fn cancels_request(self) -> bool {
+        self == TurnState::Interrupted
+    }
+}
+
+/// How an answer stream ended, with whatever text arrived before that
+#[derive(Debug, PartialEq, Eq)]
+pub enum StreamOutcome {
+    Completed(String),
+    Interrupted(String),
+}
+
+/// Accumulates chunks until the stream closes or `interrupt` resolves,
+/// passing each chunk to `on_chunk` as it arrives.
+pub async fn collect_until_interrupt(
+    mut chunks: mpsc::UnboundedReceiver<String>,
+    interrupt: impl Future<Output = ()>,
+    mut on_chunk: impl FnMut(&str),
+) -> StreamOutcome {
+    tokio::pin!(interrupt);
+    let mut text = String::new();
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = &mut interrupt => return StreamOutcome::Interrupted(text),
+            chunk = chunks.recv() => match chunk {
+                Some(chunk) => {
+                    on_chunk(&chunk);
+                    text.push_str(&chunk);
+                }
+                None => return StreamOutcome::Completed(text),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn test_turn_state_transitions() {
+        let state = TurnState::Prompting.next(TurnEvent::Submit);
+        assert_eq!(state, TurnState::Answering);
+
+        let interrupted = state.next(TurnEvent::Interrupt);
+        assert_eq!(interrupted, TurnState::Interrupted);
+        assert!(interrupted.cancels_request());
+        assert_eq!(interrupted.next(TurnEvent::Finished), TurnState::Prompting);
+
+        assert_eq!(state.next(TurnEvent::Finished), TurnState::Prompting);
+        assert_eq!(
+            TurnState::Prompting.next(TurnEvent::Interrupt),
+            TurnState::Prompting
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_completes_without_interrupt() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tx.send("Hello, ".to_string()).unwrap();
+        tx.send("world".to_string()).unwrap();
+        drop(tx);
+
+        let outcome = collect_until_interrupt(rx, std::future::pending(), |_| {}).await;
+        assert_eq!(
+            outcome,
+            StreamOutcome::Completed("Hello, world".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_stops_mock_stream() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (interrupt_tx, interrupt_rx) = oneshot::channel::<()>();
+        let mut seen = Vec::new();
+
+        let producer = tokio::spawn(async move {
+            tx.send("partial ".to_string()).unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            interrupt_tx.send(()).unwrap();
+            // Keeps the stream open, as a slow answer would
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            drop(tx);
+        });
+
+        let outcome = collect_until_interrupt(
+            rx,
+            async {
+                let _ = interrupt_rx.await;
+            },
+            |chunk| seen.push(chunk.to_string()),
+        )
+        .await;
+        producer.abort();
+
+        assert_eq!(outcome, StreamOutcome::Interrupted("partial ".to_string()));
+        assert_eq!(seen, vec!["partial "]);
+    }
+}
@@ -0,0 +1,3690 @@
+//! Client library for the xAI Grok Responses API.
+//!
+//! The `grok-ask` binary is a thin CLI over this crate. Embedders can call
+//! [`ask`] for a ready-made [`Answer`], or [`create_request`] and
+//! [`extract_answer_text`] to work with the raw [`GrokResponse`].
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub mod audit;
+pub mod baseline;
+pub mod batch;
+pub mod cache;
+pub mod cassette;
+pub mod color;
+pub mod compare;
+pub mod config;
+pub mod cost;
+pub mod dates;
+pub mod export;
+pub mod image;
+pub mod interrupt;
+pub mod manifest;
+pub mod meta;
+pub mod metrics;
+pub mod paths;
+pub mod priming;
+pub mod repl;
+pub mod retry;
+pub mod schema;
+pub mod sections;
+pub mod serve;
+pub mod spinner;
+pub mod ssml;
+pub mod stream;
+pub mod thread;
+pub mod tool_context;
+pub mod wrap;
+
+use baseline::SourceBaseline;
+use cassette::Cassette;
+use color::Palette;
+use config::Config;
+use cost::{estimate_cost, Pricing};
+use meta::ResponseMeta;
+use retry::{parse_retry_after, RetryPolicy};
+use schema::{JsonSchema, TextConfig};
+use spinner::Spinner;
+
+pub const API_ENDPOINT: &str = "https://api.x.ai/v1/responses";
+/// Overrides the API base URL, e.g. for a gateway mirroring the xAI API
+pub const BASE_URL_ENV: &str = "XAI_BASE_URL";
+pub const MODEL: &str = "grok-4-1-fast-non-reasoning";
+pub const REASONING_MODEL: &str = "grok-4-1-fast";
+/// Total time allowed for one HTTP request unless overridden
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+/// How often a still-running response is re-fetched
+pub const POLL_INTERVAL: Duration = Duration::from_secs(3);
+/// How long a still-running response is polled before giving up
+pub const POLL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Clone, Debug, clap::ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    /// Markdown with a Sources heading and a footer list
+    Markdown,
+    /// Just the answer and sources, with no separator or footer
+    Plain,
+}
+
+impl OutputFormat {
+    /// Whether this is one of the human-readable formats
+    pub fn is_text(&self) -> bool {
+        !matches!(self, OutputFormat::Json)
+    }
+
+    /// The format a file's extension calls for: JSON for `.json`, Markdown
+    /// for `.md`, text for anything else
+    pub fn for_path(path: &Path) -> OutputFormat {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("json") => OutputFormat::Json,
+            Some("md" | "markdown") => OutputFormat::Markdown,
+            _ => OutputFormat::Text,
+        }
+    }
+}
+
+// Request structures
+#[derive(Serialize)]
+pub struct Message {
+    pub role: String,
+    pub content: MessageContent,
+}
+
+/// A message's content: plain text, or parts mixing text and images
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+impl MessageContent {
+    /// The text, with any text parts joined and images left out
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::InputText { text } => Some(text.as_str()),
+                    ContentPart::InputImage { .. } => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    InputText {
+        text: String,
+    },
+    /// `image_url` is an `https:` URL or a base64 `data:` URL
+    InputImage {
+        image_url: String,
+    },
+}
+
+/// Whether the model may, must, or must not call the configured tools
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolChoice {
+    Auto,
+    Required,
+    None,
+}
+
+/// How the API key is attached to requests
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum AuthScheme {
+    /// `Bearer <key>`
+    #[default]
+    Bearer,
+    /// `Token <key>`
+    Token,
+    /// The key alone
+    Raw,
+}
+
+/// The header name and value carrying `api_key`; `Authorization` unless `header` is given
+pub fn auth_header(scheme: AuthScheme, header: Option<&str>, api_key: &str) -> (String, String) {
+    let value = match scheme {
+        AuthScheme::Bearer => format!("Bearer {}", api_key),
+        AuthScheme::Token => format!("Token {}", api_key),
+        AuthScheme::Raw => api_key.to_string(),
+    };
+    (header.unwrap_or("Authorization").to_string(), value)
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Tool {
+    WebSearch(WebSearchTool),
+    XSearch(XSearchTool),
+}
+
+#[derive(Serialize)]
+pub struct WebSearchTool {
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_image_understanding: Option<bool>,
+    /// ISO 3166-1 alpha-2 code to localize results to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// Most results a search may return
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_search_results: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct XSearchTool {
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_x_handles: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excluded_x_handles: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_image_understanding: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_video_understanding: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct GrokRequest {
+    pub model: String,
+    pub input: Vec<Message>,
+    pub store: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// How hard the reasoning model thinks: `low`, `medium`, or `high`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_response_id: Option<String>,
+    /// Groups requests sharing a prompt prefix so the provider can cache it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_cache_key: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "is_auto_tool_choice")]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tool_calls: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<bool>,
+    /// Send the answer as server-sent events; see [`stream`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// Structured output format; see [`schema`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<TextConfig>,
+}
+
+/// `auto` is the API default, so it is never sent explicitly
+fn is_auto_tool_choice(choice: &Option<ToolChoice>) -> bool {
+    matches!(choice, None | Some(ToolChoice::Auto))
+}
+
+// Response structures
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GrokResponse {
+    pub id: Option<String>,
+    pub status: Option<String>,
+    /// Why generation stopped (e.g. `stop`, `length`, `content_filter`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    /// Set on `incomplete` responses, saying what cut them short
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub incomplete_details: Option<IncompleteDetails>,
+    pub output: Option<Vec<Output>>,
+    pub usage: Option<Usage>,
+    #[serde(default)]
+    pub error: Option<ApiError>,
+    /// HTTP status and rate-limit headers, when asked for with `show_headers`
+    #[serde(default, rename = "_meta", skip_serializing_if = "Option::is_none")]
+    pub meta: Option<meta::ResponseMeta>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Output {
+    pub r#type: String,
+    pub content: Option<Vec<Content>>,
+    pub results: Option<Vec<WebSearchResult>>,
+    /// Summary parts of a `reasoning` block
+    pub summary: Option<Vec<Content>>,
+    /// What a `web_search_call` block did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<ToolAction>,
+    /// JSON-encoded arguments of a function or custom tool call
+    #[serde(default, alias = "input", skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ToolAction {
+    pub r#type: Option<String>,
+    pub query: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Content {
+    pub r#type: String,
+    pub text: Option<String>,
+    pub annotations: Option<Vec<Annotation>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Annotation {
+    pub url: Option<String>,
+    pub title: Option<String>,
+    /// Character offsets of the cited span in the content's text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_index: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct WebSearchResult {
+    pub url: Option<String>,
+    pub title: Option<String>,
+    /// Handle of an X post's author
+    #[serde(default, alias = "username", skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// When an X post was made, as an RFC 3339 timestamp
+    #[serde(default, alias = "created_at", skip_serializing_if = "Option::is_none")]
+    pub posted_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub like_count: Option<u64>,
+    #[serde(
+        default,
+        alias = "retweet_count",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub repost_count: Option<u64>,
+    /// Images and videos attached to an X post, when media understanding is on
+    #[serde(
+        default,
+        alias = "attachments",
+        deserialize_with = "null_as_empty",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub media: Vec<Media>,
+}
+
+/// Reads an explicit `null` list the same as a missing one
+fn null_as_empty<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(Option::<Vec<T>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// An image or video attached to an X post
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Media {
+    /// `image`, `video`, `animated_gif`, ...
+    #[serde(default, alias = "media_type")]
+    pub r#type: Option<String>,
+    #[serde(default, alias = "media_url")]
+    pub url: Option<String>,
+}
+
+impl WebSearchResult {
+    /// `@handle · 2025-01-03` for an X post, from whichever parts are present
+    pub fn post_byline(&self) -> Option<String> {
+        let handle = self
+            .author
+            .as_deref()
+            .map(|a| format!("@{}", a.trim_start_matches('@')));
+        let date = self
+            .posted_at
+            .as_deref()
+            .map(|t| t.split('T').next().unwrap_or(t).to_string());
+        match (handle, date) {
+            (Some(handle), Some(date)) => Some(format!("{} \u{b7} {}", handle, date)),
+            (handle, date) => handle.or(date),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct IncompleteDetails {
+    pub reason: Option<String>,
+}
+
+impl GrokResponse {
+    /// The stop reason, falling back to why an incomplete response ended
+    pub fn finish_reason(&self) -> Option<&str> {
+        self.finish_reason.as_deref().or_else(|| {
+            self.incomplete_details
+                .as_ref()
+                .and_then(|d| d.reason.as_deref())
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub input_tokens: Option<u32>,
+    pub output_tokens: Option<u32>,
+}
+
+impl Usage {
+    /// The combined usage of two requests, e.g. a query and its retry; a
+    /// count either one reports is kept
+    pub fn sum(a: Option<&Usage>, b: Option<&Usage>) -> Option<Usage> {
+        let add = |a: Option<u32>, b: Option<u32>| match (a, b) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0).saturating_add(b.unwrap_or(0))),
+        };
+        match (a, b) {
+            (Some(a), Some(b)) => Some(Usage {
+                input_tokens: add(a.input_tokens, b.input_tokens),
+                output_tokens: add(a.output_tokens, b.output_tokens),
+            }),
+            (a, b) => a.or(b).cloned(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ApiError {
+    pub message: Option<String>,
+    pub code: Option<String>,
+}
+
+pub(crate) fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub const API_KEY_ENV: &str = "XAI_API_KEY";
+
+/// The API key from `XAI_API_KEY`, falling back to `[auth] api_key` in the config file
+pub fn get_api_key() -> Result<String> {
+    let config_file = paths::StatePaths::resolve()
+        .ok()
+        .map(|paths| paths.config_file());
+    api_key_from(env::var(API_KEY_ENV).ok(), config_file.as_deref())
+}
+
+fn api_key_from(env_key: Option<String>, config_file: Option<&Path>) -> Result<String> {
+    if let Some(key) = env_key.filter(|key| !key.trim().is_empty()) {
+        return Ok(key);
+    }
+    if let Some(path) = config_file {
+        if let Some(key) = Config::load(path)?.api_key() {
+            return Ok(key.to_string());
+        }
+    }
+    bail!(
+        "XAI_API_KEY environment variable not set and no api_key in the config file. \
+         Get your key from https://console.x.ai/ and export it, or run `grok-ask config set-key`"
+    )
+}
+
+/// X search configuration
+#[derive(Default, Clone)]
+pub struct XSearchConfig {
+    pub allowed_handles: Option<Vec<String>>,
+    pub excluded_handles: Option<Vec<String>>,
+    pub from_date: Option<String>,
+    pub to_date: Option<String>,
+    pub enable_images: bool,
+    pub enable_video: bool,
+}
+
+/// Checks a handle against X username rules: 1-15 ASCII letters, digits, or underscores
+pub fn is_valid_x_handle(handle: &str) -> bool {
+    (1..=15).contains(&handle.len())
+        && handle
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Trims whitespace and one leading `@` from each handle, dropping any left empty
+pub fn normalize_handles(handles: &[String]) -> Vec<String> {
+    handles
+        .iter()
+        .map(|h| {
+            let h = h.trim();
+            h.strip_prefix('@').unwrap_or(h).trim().to_string()
+        })
+        .filter(|h| !h.is_empty())
+        .collect()
+}
+
+/// Pieces of the system message, composed in field order
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemLayers<'a> {
+    /// The command's built-in instruction
+    pub preset: Option<&'a str>,
+    /// Replaces the preset (`--system`, `--system-file`)
+    pub replace: Option<&'a str>,
+    /// User-supplied guidance added after the preset (`--append-system`)
+    pub append: Option<&'a str>,
+    /// Approximate answer length (`--target-words`)
+    pub target_words: Option<usize>,
+    /// Language to answer in (`--lang`)
+    pub lang: Option<&'a str>,
+}
+
+/// Joins the non-empty layers into one system message: preset, then user
+/// guidance, then the length and language directives. Returns `None` if all
+/// are empty.
+pub fn build_system_instruction(layers: &SystemLayers) -> Option<String> {
+    let length = layers
+        .target_words
+        .map(|words| format!("Aim for about {} words.", words));
+    let lang = layers
+        .lang
+        .map(str::trim)
+        .filter(|lang| !lang.is_empty())
+        .map(|lang| format!("Respond in {}.", lang));
+    let parts: Vec<&str> = [
+        layers.replace.or(layers.preset),
+        layers.append,
+        length.as_deref(),
+        lang.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(str::trim)
+    .filter(|part| !part.is_empty())
+    .collect();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("\n\n"))
+    }
+}
+
+/// Smallest and largest budgets [`adaptive_token_budget`] will pick
+pub const ADAPTIVE_MIN_TOKENS: u32 = 1024;
+pub const ADAPTIVE_MAX_TOKENS: u32 = 16384;
+/// Smallest budget for reasoning models, whose reasoning tokens count
+/// against it before any of the answer is written
+pub const ADAPTIVE_REASONING_MIN_TOKENS: u32 = 8192;
+
+/// Rough token count of `text`, at 4 characters per token
+pub fn estimate_tokens(text: &str) -> u32 {
+    (text.chars().count() as u32).div_ceil(4)
+}
+
+/// How [`estimate_tokens`] counts, for output that shows its estimates
+pub const TOKEN_ESTIMATE_METHOD: &str =
+    "about 4 characters per token; the model's tokenizer can differ noticeably, especially for code and non-English text";
+
+/// Estimated input tokens of a request, by the part of the prompt they come from
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TokenEstimate {
+    pub system_tokens: u32,
+    pub prior_message_tokens: u32,
+    pub query_tokens: u32,
+    pub total_tokens: u32,
+    pub method: &'static str,
+}
+
+/// Estimates the input tokens of the request `query` would send with
+/// `options`, offline. Attached images aren't counted.
+pub fn estimate_input_tokens(query: &str, options: &RequestOptions) -> TokenEstimate {
+    let request = build_request(query, options);
+    // The query is always the last message
+    let (query_tokens, earlier) = match request.input.split_last() {
+        Some((last, earlier)) => (estimate_tokens(&last.content.text()), earlier),
+        None => (0, &[][..]),
+    };
+    let tokens_where = |system: bool| -> u32 {
+        earlier
+            .iter()
+            .filter(|message| (message.role == "system") == system)
+            .map(|message| estimate_tokens(&message.content.text()))
+            .sum()
+    };
+    let system_tokens = tokens_where(true);
+    let prior_message_tokens = tokens_where(false);
+    TokenEstimate {
+        system_tokens,
+        prior_message_tokens,
+        query_tokens,
+        total_tokens: system_tokens + prior_message_tokens + query_tokens,
+        method: TOKEN_ESTIMATE_METHOD,
+    }
+}
+
+pub fn format_token_estimate(estimate: &TokenEstimate) -> String {
+    let mut out = format!("Estimated input tokens: ~{}\n", estimate.total_tokens);
+    if estimate.system_tokens > 0 {
+        out.push_str(&format!(
+            "  system instruction: ~{}\n",
+            estimate.system_tokens
+        ));
+    }
+    if estimate.prior_message_tokens > 0 {
+        out.push_str(&format!(
+            "  priming messages: ~{}\n",
+            estimate.prior_message_tokens
+        ));
+    }
+    out.push_str(&format!("  query: ~{}\n", estimate.query_tokens));
+    out.push_str(&format!("Method: {}\n", estimate.method));
+    out
+}
+
+/// Sizes `max_output_tokens` from the query: roughly 8 output tokens per input
+/// token (see [`estimate_tokens`]), rounded up to a power of two and clamped.
+/// With `reasoning` the floor is [`ADAPTIVE_REASONING_MIN_TOKENS`].
+pub fn adaptive_token_budget(query: &str, reasoning: bool) -> u32 {
+    let estimated_input = estimate_tokens(query);
+    let budget = 512u32.saturating_add(estimated_input.saturating_mul(8));
+    let min = if reasoning {
+        ADAPTIVE_REASONING_MIN_TOKENS
+    } else {
+        ADAPTIVE_MIN_TOKENS
+    };
+    budget
+        .checked_next_power_of_two()
+        .unwrap_or(ADAPTIVE_MAX_TOKENS)
+        .clamp(min, ADAPTIVE_MAX_TOKENS)
+}
+
+/// Per-request settings for [`create_request`]
+#[derive(Default, Clone)]
+pub struct RequestOptions {
+    pub previous_response_id: Option<String>,
+    pub system_instruction: Option<String>,
+    pub max_tokens: u32,
+    /// Sampling temperature (0.0-2.0); the API default when unset
+    pub temperature: Option<f32>,
+    pub use_web_search: bool,
+    pub use_x_search: bool,
+    pub x_search_config: Option<XSearchConfig>,
+    pub use_reasoning: bool,
+    /// Reasoning effort; only sent with `use_reasoning`
+    pub reasoning_effort: Option<String>,
+    /// Overrides the model otherwise picked from `use_reasoning`
+    pub model: Option<String>,
+    pub tool_choice: Option<ToolChoice>,
+    /// Earlier turns sent between the system instruction and the query
+    pub prior_messages: Vec<priming::PriorMessage>,
+    /// Image sent alongside the query, as an `https:` or `data:` URL
+    pub image: Option<String>,
+    /// Upper bound on searches and other tool calls the model may make
+    pub max_tool_calls: Option<u32>,
+    /// Country to localize web search results to, as an uppercase ISO code
+    pub country: Option<String>,
+    /// Cap on results per web search, enforced by the API
+    pub max_search_results: Option<u32>,
+    /// Let web search look at images on the pages it finds
+    pub web_images: bool,
+    /// Ask the provider to cache the system instruction prefix
+    pub cache_prompt: bool,
+    /// JSON schema the answer must conform to
+    pub schema: Option<JsonSchema>,
+    /// Record the exchange here on first run, replay it on later runs
+    pub cassette: Option<PathBuf>,
+    /// Return as soon as the response is queued instead of waiting for it
+    pub background: bool,
+    /// Ask the API not to keep the response, so it gets no usable id
+    pub no_store: bool,
+    /// Retries for rate limits and transient server errors
+    pub retry: RetryPolicy,
+    /// Total time allowed per HTTP request: `None` for [`DEFAULT_TIMEOUT`],
+    /// zero to wait indefinitely
+    pub timeout: Option<Duration>,
+    /// Base or full `/responses` URL; `XAI_BASE_URL` or [`API_ENDPOINT`] when unset
+    pub endpoint: Option<String>,
+    pub auth_scheme: AuthScheme,
+    /// Header carrying the key instead of `Authorization`
+    pub auth_header: Option<String>,
+    /// Key to send; looked up with [`get_api_key`] when unset
+    pub api_key: Option<String>,
+    /// Return an `in_progress` response as is instead of polling until it finishes
+    pub no_poll: bool,
+    /// Log the request and raw response body to stderr
+    pub verbose: bool,
+    /// Print the status and rate-limit headers to stderr, and keep them in
+    /// the response's `_meta`
+    pub show_headers: bool,
+    /// Show an elapsed-time spinner on stderr while waiting for the response
+    pub spinner: bool,
+    /// Don't report how long the request took on stderr
+    pub quiet: bool,
+    /// Append each answered request to this log
+    pub audit_log: Option<audit::AuditLog>,
+    /// Client whose connection pool the request shares; a fresh one when unset
+    pub client: Option<reqwest::Client>,
+}
+
+impl RequestOptions {
+    pub fn http_client(&self) -> reqwest::Client {
+        // Cloning shares the pool: a Client is a handle to the same connections
+        self.client.clone().unwrap_or_default()
+    }
+
+    /// [`api_key`](Self::api_key) if given, else the key from [`get_api_key`]
+    pub fn resolve_api_key(&self) -> Result<String> {
+        match &self.api_key {
+            Some(key) => Ok(key.clone()),
+            None => get_api_key(),
+        }
+    }
+}
+
+/// Idle connections kept open per host, enough for a batch at high concurrency
+const POOL_MAX_IDLE_PER_HOST: usize = 16;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The client for a whole run, so its requests reuse pooled connections.
+/// The total timeout is still set per request from [`RequestOptions::timeout`].
+pub fn build_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .pool_idle_timeout(Duration::from_secs(90))
+        .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+        .build()
+        .context("Failed to build HTTP client")
+}
+
+/// The responses URL to call: the override, else `XAI_BASE_URL`, else [`API_ENDPOINT`]
+pub fn endpoint_url(endpoint: Option<&str>) -> String {
+    let from_env = env::var(BASE_URL_ENV).ok();
+    match endpoint.or(from_env.as_deref()).map(str::trim) {
+        Some(url) if !url.is_empty() => responses_url(url),
+        _ => API_ENDPOINT.to_string(),
+    }
+}
+
+/// Accepts a full `.../responses` URL as is, or derives `/v1/responses` from a base
+fn responses_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    if url.ends_with("/responses") {
+        url.to_string()
+    } else if url.ends_with("/v1") {
+        format!("{}/responses", url)
+    } else {
+        format!("{}/v1/responses", url)
+    }
+}
+
+/// The per-request timeout to apply, if any
+pub fn effective_timeout(timeout: Option<Duration>) -> Option<Duration> {
+    match timeout {
+        None => Some(DEFAULT_TIMEOUT),
+        Some(timeout) if timeout.is_zero() => None,
+        Some(timeout) => Some(timeout),
+    }
+}
+
+/// Tells a timeout apart from other network failures
+pub(crate) fn request_error(
+    err: reqwest::Error,
+    timeout: Option<Duration>,
+    action: &str,
+) -> anyhow::Error {
+    match timeout {
+        Some(timeout) if err.is_timeout() => anyhow::Error::new(err).context(format!(
+            "Request timed out after {}s; raise --timeout, or pass --timeout 0 to wait indefinitely",
+            timeout.as_secs()
+        )),
+        _ => anyhow::Error::new(err).context(action.to_string()),
+    }
+}
+
+/// Whether `err` came from failing to reach the API or read its reply, timeouts included
+pub fn is_network_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<reqwest::Error>())
+}
+
+/// An error status whose body isn't a response, e.g. a gateway's HTML page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiStatusError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
+}
+
+impl std::fmt::Display for ApiStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "API returned {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for ApiStatusError {}
+
+/// Whether `err` is the API rejecting a request rather than a usage or network failure
+pub fn is_api_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<ApiStatusError>())
+}
+
+/// Assembles the request body for `query` without sending it
+pub fn build_request(query: &str, options: &RequestOptions) -> GrokRequest {
+    let mut messages = Vec::new();
+
+    // Add system instruction if provided
+    if let Some(instruction) = &options.system_instruction {
+        messages.push(Message {
+            role: "system".to_string(),
+            content: instruction.as_str().into(),
+        });
+    }
+
+    // Add few-shot priming turns
+    for message in &options.prior_messages {
+        messages.push(Message {
+            role: message.role.clone(),
+            content: message.content.as_str().into(),
+        });
+    }
+
+    // Add user query
+    let content = match &options.image {
+        Some(image_url) => MessageContent::Parts(vec![
+            ContentPart::InputText {
+                text: query.to_string(),
+            },
+            ContentPart::InputImage {
+                image_url: image_url.clone(),
+            },
+        ]),
+        None => query.into(),
+    };
+    messages.push(Message {
+        role: "user".to_string(),
+        content,
+    });
+
+    let mut tools = Vec::new();
+
+    if options.use_web_search {
+        tools.push(Tool::WebSearch(WebSearchTool {
+            r#type: "web_search".to_string(),
+            enable_image_understanding: options.web_images.then_some(true),
+            country: options.country.clone(),
+            max_search_results: options.max_search_results,
+        }));
+    }
+
+    if options.use_x_search {
+        let config = options.x_search_config.clone().unwrap_or_default();
+        tools.push(Tool::XSearch(XSearchTool {
+            r#type: "x_search".to_string(),
+            allowed_x_handles: config.allowed_handles,
+            excluded_x_handles: config.excluded_handles,
+            from_date: config.from_date,
+            to_date: config.to_date,
+            enable_image_understanding: if config.enable_images { Some(true) } else { None },
+            enable_video_understanding: if config.enable_video { Some(true) } else { None },
+        }));
+    }
+
+    let model = match &options.model {
+        Some(model) => model.as_str(),
+        None if options.use_reasoning => REASONING_MODEL,
+        None => MODEL,
+    };
+
+    let prompt_cache_key = match &options.system_instruction {
+        Some(instruction) if options.cache_prompt => {
+            Some(cache::prompt_cache_key(model, instruction))
+        }
+        _ => None,
+    };
+
+    GrokRequest {
+        model: model.to_string(),
+        input: messages,
+        store: !options.no_store,
+        max_output_tokens: Some(options.max_tokens),
+        temperature: options.temperature,
+        reasoning_effort: options
+            .reasoning_effort
+            .clone()
+            .filter(|_| options.use_reasoning),
+        previous_response_id: options.previous_response_id.clone(),
+        prompt_cache_key,
+        tools,
+        tool_choice: options.tool_choice,
+        max_tool_calls: options.max_tool_calls,
+        background: options.background.then_some(true),
+        stream: None,
+        text: options.schema.as_ref().map(JsonSchema::text_config),
+    }
+}
+
+/// The `--verbose` log of a request about to be sent, with the key redacted
+pub(crate) fn describe_request(endpoint: &str, auth_name: &str, request: &GrokRequest) -> String {
+    format!(
+        "POST {}\nModel: {}\n{}: [REDACTED]\nContent-Type: application/json\n{}\n",
+        endpoint,
+        request.model,
+        auth_name,
+        serde_json::to_string_pretty(request).unwrap_or_default()
+    )
+}
+
+/// Sends `query` on the client in `options` (a fresh one if it has none); see [`send_request`]
+pub async fn create_request(query: &str, options: &RequestOptions) -> Result<GrokResponse> {
+    send_request(&options.http_client(), query, options).await
+}
+
+/// How [`deliver_request`] obtained a response, for the caller to report
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Delivery {
+    /// Round trip of the final attempt; `None` when replayed from a cassette
+    pub elapsed: Option<Duration>,
+    /// Cassette the response was replayed from
+    pub replayed: Option<PathBuf>,
+    /// Cassette the exchange was recorded to
+    pub recorded: Option<PathBuf>,
+    /// Status and rate-limit headers, captured with `verbose` or `show_headers`
+    pub headers: Option<ResponseMeta>,
+}
+
+impl Delivery {
+    /// Lines for stderr; `quiet` leaves out the timing
+    pub fn describe(&self, quiet: bool) -> String {
+        let mut out = String::new();
+        if let Some(path) = &self.replayed {
+            out.push_str(&format!(
+                "Replaying response from cassette {}\n",
+                path.display()
+            ));
+        }
+        if let Some(elapsed) = self.elapsed.filter(|_| !quiet) {
+            out.push_str(&format!(
+                "Request completed in {:.2}s\n",
+                elapsed.as_secs_f64()
+            ));
+        }
+        if let Some(headers) = &self.headers {
+            out.push_str(&headers.describe());
+        }
+        if let Some(path) = &self.recorded {
+            out.push_str(&format!("Recorded cassette {}\n", path.display()));
+        }
+        out
+    }
+}
+
+/// Sends `query` on `client`, retrying as `options.retry` allows, and parses the response
+pub async fn send_request(
+    client: &reqwest::Client,
+    query: &str,
+    options: &RequestOptions,
+) -> Result<GrokResponse> {
+    Ok(deliver_request(client, query, options).await?.0)
+}
+
+/// [`send_request`], also returning how the response was obtained
+pub async fn deliver_request(
+    client: &reqwest::Client,
+    query: &str,
+    options: &RequestOptions,
+) -> Result<(GrokResponse, Delivery)> {
+    let request = build_request(query, options);
+    let mut delivery = Delivery::default();
+
+    if let Some(path) = &options.cassette {
+        if let Some(cassette) = Cassette::load(path)? {
+            cassette.check_request(&serde_json::to_value(&request)?, path)?;
+            let response = serde_json::from_value(cassette.response)
+                .context("Failed to parse cassette response")?;
+            delivery.replayed = Some(path.clone());
+            return Ok((response, delivery));
+        }
+    }
+
+    let api_key = options.resolve_api_key()?;
+    let timeout = effective_timeout(options.timeout);
+    let endpoint = endpoint_url(options.endpoint.as_deref());
+    let (auth_name, auth_value) = auth_header(
+        options.auth_scheme,
+        options.auth_header.as_deref(),
+        &api_key,
+    );
+
+    if options.verbose {
+        eprint!("{}", describe_request(&endpoint, &auth_name, &request));
+    }
+
+    let mut attempt = 0;
+    let response = loop {
+        let start = Instant::now();
+        let mut builder = client
+            .post(&endpoint)
+            .header(&auth_name, &auth_value)
+            .header("Content-Type", "application/json")
+            .json(&request);
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        let spinner = options.spinner.then(|| Spinner::start("Thinking"));
+        let response = builder.send().await;
+        drop(spinner);
+        let response = response.map_err(|e| request_error(e, timeout, "Failed to send request"))?;
+
+        if options.retry.should_retry(response.status().as_u16())
+            && attempt < options.retry.max_retries
+        {
+            attempt += 1;
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after);
+            let delay = options.retry.delay(attempt, retry_after);
+            eprintln!(
+                "Retrying ({}/{}) after {:.0}s\u{2026}",
+                attempt,
+                options.retry.max_retries,
+                delay.as_secs_f64()
+            );
+            tokio::time::sleep(delay).await;
+            continue;
+        }
+
+        delivery.elapsed = Some(start.elapsed());
+        break response;
+    };
+
+    let status = response.status();
+    // Headers have to be captured before the body consumes the response
+    delivery.headers = (options.verbose || options.show_headers)
+        .then(|| ResponseMeta::new(status.as_u16(), response.headers()));
+    let body = response
+        .text()
+        .await
+        .map_err(|e| request_error(e, timeout, "Failed to read response"))?;
+    if options.verbose {
+        eprintln!("Response body ({}):\n{}", status, body.trim_end());
+    }
+
+    // Parsed once; the raw value is what a cassette records
+    let parsed = serde_json::from_str::<serde_json::Value>(&body)
+        .and_then(|value| Ok((GrokResponse::deserialize(&value)?, value)));
+    let (mut data, mut recorded) = match parsed {
+        Ok(parsed) => parsed,
+        Err(_) if !status.is_success() => {
+            return Err(ApiStatusError {
+                status,
+                body: body.trim().to_string(),
+            }
+            .into())
+        }
+        Err(e) => return Err(e).context("Failed to parse response"),
+    };
+
+    if is_pending(&data) && !options.background && !options.no_poll {
+        if let Some(id) = data.id.clone() {
+            data = poll_response(&id, options, POLL_INTERVAL, POLL_TIMEOUT).await?;
+            recorded = serde_json::to_value(&data)?;
+        }
+    }
+
+    if let Some(path) = &options.cassette {
+        Cassette {
+            request: serde_json::to_value(&request)?,
+            response: recorded,
+        }
+        .save(path)?;
+        delivery.recorded = Some(path.clone());
+    }
+
+    if let Some(log) = &options.audit_log {
+        log.record(query, &request, &data)?;
+    }
+    if options.show_headers {
+        data.meta = delivery.headers.clone();
+    }
+    Ok((data, delivery))
+}
+
+/// A cited source
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub title: String,
+    pub url: String,
+}
+
+/// The collected sources as a JSON array of `{title, url}`, with nothing else
+pub fn citations_json(response: &GrokResponse) -> String {
+    let sources: Vec<Source> = collect_sources(response)
+        .into_iter()
+        .map(|(title, url)| Source { title, url })
+        .collect();
+    serde_json::to_string_pretty(&sources).unwrap_or_default()
+}
+
+/// The useful parts of a successful response
+#[derive(Serialize, Debug, Clone)]
+pub struct Answer {
+    pub text: String,
+    pub sources: Vec<Source>,
+    pub response_id: Option<String>,
+    pub usage: Option<Usage>,
+}
+
+impl Answer {
+    /// Extracts the answer, turning an API error in the response into an `Err`
+    pub fn from_response(response: &GrokResponse) -> Result<Self> {
+        if let Some(error) = &response.error {
+            bail!(
+                "API error: {}",
+                error.message.as_deref().unwrap_or("Unknown error")
+            );
+        }
+        Ok(Self {
+            text: extract_answer_text(response),
+            sources: collect_sources(response)
+                .into_iter()
+                .map(|(title, url)| Source { title, url })
+                .collect(),
+            response_id: response.id.clone(),
+            usage: response.usage.clone(),
+        })
+    }
+}
+
+/// Sends `query` and returns its answer text, sources, id, and usage
+pub async fn ask(
+    client: &reqwest::Client,
+    query: &str,
+    options: &RequestOptions,
+) -> Result<Answer> {
+    Answer::from_response(&send_request(client, query, options).await?)
+}
+
+/// Whether a response is still being generated and can be polled
+pub fn is_pending(response: &GrokResponse) -> bool {
+    matches!(response.status.as_deref(), Some("in_progress" | "queued"))
+}
+
+/// Fetches a stored response by id, using the endpoint, auth, and timeout from `options`
+pub async fn retrieve_response(id: &str, options: &RequestOptions) -> Result<GrokResponse> {
+    let api_key = options.resolve_api_key()?;
+    let client = options.http_client();
+    let timeout = effective_timeout(options.timeout);
+    let (auth_name, auth_value) = auth_header(
+        options.auth_scheme,
+        options.auth_header.as_deref(),
+        &api_key,
+    );
+
+    let mut builder = client
+        .get(format!(
+            "{}/{}",
+            endpoint_url(options.endpoint.as_deref()),
+            id
+        ))
+        .header(auth_name, auth_value);
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    let response = builder
+        .send()
+        .await
+        .map_err(|e| request_error(e, timeout, "Failed to send request"))?;
+
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| request_error(e, timeout, "Failed to read response"))?;
+    match serde_json::from_str(&body) {
+        Ok(data) => Ok(data),
+        Err(_) if !status.is_success() => Err(ApiStatusError {
+            status,
+            body: body.trim().to_string(),
+        }
+        .into()),
+        Err(e) => Err(e).context("Failed to parse response"),
+    }
+}
+
+/// Retrieves a response, re-fetching every `interval` until it is no longer pending
+pub async fn poll_response(
+    id: &str,
+    options: &RequestOptions,
+    interval: Duration,
+    max_wait: Duration,
+) -> Result<GrokResponse> {
+    let start = Instant::now();
+    loop {
+        let response = retrieve_response(id, options).await?;
+        if !is_pending(&response) {
+            eprintln!("Response ready after {:.0}s", start.elapsed().as_secs_f64());
+            return Ok(response);
+        }
+        let status = response.status.as_deref().unwrap_or("unknown");
+        if start.elapsed() >= max_wait {
+            bail!(
+                "Gave up waiting for {} after {}s (status: {}); check again later with: grok-ask get {}",
+                id,
+                max_wait.as_secs(),
+                status,
+                id
+            );
+        }
+        eprintln!(
+            "Waiting for {} (status: {}, {:.0}s elapsed)",
+            id,
+            status,
+            start.elapsed().as_secs_f64()
+        );
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Text shown when a background request has been accepted
+pub fn format_background_started(response: &GrokResponse) -> String {
+    match &response.id {
+        Some(id) => format!(
+            "Started in background (status: {}).\nRetrieve the result with: grok-ask get {}\n",
+            response.status.as_deref().unwrap_or("unknown"),
+            id
+        ),
+        None => "Background request returned no response id\n".to_string(),
+    }
+}
+
+/// Whether a finished response came back without any output, as when the
+/// model declines or all content is filtered
+pub fn is_empty_response(response: &GrokResponse) -> bool {
+    response.error.is_none()
+        && response.output.as_ref().is_none_or(Vec::is_empty)
+        && !matches!(response.status.as_deref(), Some("queued" | "in_progress"))
+}
+
+/// Returns the concatenated text of every message block, without sources or footer.
+pub fn extract_answer_text(response: &GrokResponse) -> String {
+    let mut text = String::new();
+
+    if let Some(outputs) = &response.output {
+        for out in outputs.iter().filter(|o| o.r#type == "message") {
+            if let Some(contents) = &out.content {
+                for content in contents {
+                    if content.r#type == "output_text" || content.r#type == "text" {
+                        if let Some(t) = &content.text {
+                            text.push_str(t);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    text
+}
+
+/// Like [`extract_answer_text`], with a `[n]` marker after each annotated span,
+/// numbered by the annotation's position in `sources`. Annotations without
+/// offsets get their markers at the end of their text block; ones whose URL
+/// isn't in `sources` are left unmarked.
+pub fn extract_cited_answer_text(response: &GrokResponse, sources: &[(String, String)]) -> String {
+    let mut text = String::new();
+
+    for out in response.output.iter().flatten() {
+        if out.r#type != "message" {
+            continue;
+        }
+        for content in out.content.iter().flatten() {
+            if content.r#type == "output_text" || content.r#type == "text" {
+                if let Some(t) = &content.text {
+                    let annotations = content.annotations.as_deref().unwrap_or_default();
+                    text.push_str(&insert_citations(t, annotations, sources));
+                }
+            }
+        }
+    }
+
+    text
+}
+
+fn insert_citations(
+    text: &str,
+    annotations: &[Annotation],
+    sources: &[(String, String)],
+) -> String {
+    let chars = text.chars().count();
+    // (character offset, source number), with unplaced markers at the end
+    let mut markers: Vec<(usize, usize)> = Vec::new();
+    for annotation in annotations {
+        let Some(url) = &annotation.url else {
+            continue;
+        };
+        let Some(number) = sources.iter().position(|(_, u)| u == url).map(|i| i + 1) else {
+            continue;
+        };
+        let at = annotation
+            .end_index
+            .filter(|&end| end <= chars)
+            .unwrap_or(chars);
+        if !markers.contains(&(at, number)) {
+            markers.push((at, number));
+        }
+    }
+    markers.sort();
+
+    let mut cited = String::with_capacity(text.len() + markers.len() * 4);
+    let mut pending = markers.iter().peekable();
+    for (i, c) in text.chars().enumerate() {
+        while let Some((_, number)) = pending.next_if(|(at, _)| *at == i) {
+            cited.push_str(&format!("[{}]", number));
+        }
+        cited.push(c);
+    }
+    for (_, number) in pending {
+        cited.push_str(&format!("[{}]", number));
+    }
+    cited
+}
+
+/// Returns the text of every `reasoning` block (its summary, or its content when
+/// there's no summary). Empty for non-reasoning models.
+pub fn extract_reasoning_text(response: &GrokResponse) -> String {
+    let mut parts = Vec::new();
+
+    if let Some(outputs) = &response.output {
+        for out in outputs.iter().filter(|o| o.r#type == "reasoning") {
+            let blocks = out.summary.as_ref().or(out.content.as_ref());
+            for block in blocks.into_iter().flatten() {
+                if let Some(t) = block.text.as_deref().filter(|t| !t.trim().is_empty()) {
+                    parts.push(t.trim());
+                }
+            }
+        }
+    }
+
+    parts.join("\n\n")
+}
+
+/// The X posts among the search results, keyed by URL
+fn x_posts(response: &GrokResponse) -> HashMap<&str, &WebSearchResult> {
+    response
+        .output
+        .iter()
+        .flatten()
+        .filter(|out| out.r#type == "x_search_result")
+        .flat_map(|out| out.results.iter().flatten())
+        .filter_map(|result| Some((result.url.as_deref()?, result)))
+        .collect()
+}
+
+/// The queries of the searches the model ran, in order, without repeats
+pub fn extract_search_queries(response: &GrokResponse) -> Vec<String> {
+    let mut queries: Vec<String> = Vec::new();
+    let calls = response
+        .output
+        .iter()
+        .flatten()
+        .filter(|out| out.r#type.ends_with("_call"));
+    for call in calls {
+        let from_action = call.action.as_ref().and_then(|a| a.query.clone());
+        let query = from_action.or_else(|| {
+            let args: serde_json::Value = serde_json::from_str(call.arguments.as_deref()?).ok()?;
+            args.get("query")?.as_str().map(str::to_string)
+        });
+        if let Some(query) = query.filter(|q| !queries.contains(q)) {
+            queries.push(query);
+        }
+    }
+    queries
+}
+
+/// Canonical form of a URL for comparing sources: lowercase scheme and host,
+/// no fragment, no trailing slash
+pub fn normalize_url(url: &str) -> String {
+    let url = url.trim();
+    let url = url.split('#').next().unwrap_or(url);
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (format!("{}://", scheme.to_lowercase()), rest),
+        None => (String::new(), url),
+    };
+    let (host, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+
+    let mut normalized = format!("{}{}{}", scheme, host.to_lowercase(), path);
+    while normalized.ends_with('/') {
+        normalized.pop();
+    }
+    normalized
+}
+
+/// Whether a URL points at X (or its old twitter.com domain)
+pub fn is_x_url(url: &str) -> bool {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest[..rest.find(['/', '?', '#']).unwrap_or(rest.len())].to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let host = host.strip_prefix("mobile.").unwrap_or(host);
+    host == "x.com" || host == "twitter.com"
+}
+
+/// Which kinds of source the Sources list shows
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SourceFilter {
+    #[default]
+    All,
+    /// Only web pages
+    Web,
+    /// Only X posts
+    X,
+    /// No Sources list at all
+    None,
+}
+
+impl SourceFilter {
+    pub fn keeps(self, url: &str) -> bool {
+        match self {
+            SourceFilter::All => true,
+            SourceFilter::Web => !is_x_url(url),
+            SourceFilter::X => is_x_url(url),
+            SourceFilter::None => false,
+        }
+    }
+}
+
+/// Collects deduplicated `(title, url)` pairs from annotations and search results,
+/// in the order they appear in the response.
+pub fn collect_sources(response: &GrokResponse) -> Vec<(String, String)> {
+    let mut sources = SourceSet::default();
+    for out in response.output.iter().flatten() {
+        sources.extend_from_output(out);
+    }
+    sources.into_vec()
+}
+
+/// How many X posts the searches returned, counting repeats
+pub fn x_result_count(response: &GrokResponse) -> usize {
+    response
+        .output
+        .iter()
+        .flatten()
+        .filter(|out| out.r#type == "x_search_result")
+        .map(|out| out.results.as_ref().map_or(0, Vec::len))
+        .sum()
+}
+
+/// `(title, url)` sources in first-seen order, deduplicated by URL
+#[derive(Debug, Default, Clone)]
+pub struct SourceSet {
+    sources: Vec<(String, String)>,
+}
+
+impl SourceSet {
+    /// Adds a source unless its URL is already present, returning it if it was new
+    pub fn insert(
+        &mut self,
+        title: Option<&str>,
+        url: &str,
+        fallback: &str,
+    ) -> Option<(String, String)> {
+        if self.sources.iter().any(|(_, u)| u == url) {
+            return None;
+        }
+        let source = (title.unwrap_or(fallback).to_string(), url.to_string());
+        self.sources.push(source.clone());
+        Some(source)
+    }
+
+    /// Adds the sources cited in or found by one output block, returning the new ones
+    pub fn extend_from_output(&mut self, out: &Output) -> Vec<(String, String)> {
+        let mut added = Vec::new();
+        if out.r#type == "message" {
+            for content in out.content.iter().flatten() {
+                if content.r#type == "output_text" || content.r#type == "text" {
+                    for ann in content.annotations.iter().flatten() {
+                        added.extend(self.insert_annotation(ann));
+                    }
+                }
+            }
+        } else if out.r#type == "web_search_result" || out.r#type == "x_search_result" {
+            let fallback = if out.r#type == "x_search_result" { "X Post" } else { "Web Result" };
+            for result in out.results.iter().flatten() {
+                if let Some(url) = &result.url {
+                    added.extend(self.insert(result.title.as_deref(), url, fallback));
+                }
+            }
+        }
+        added
+    }
+
+    /// Adds a citation annotation's source, returning it if it was new
+    pub fn insert_annotation(&mut self, ann: &Annotation) -> Option<(String, String)> {
+        let url = ann.url.as_deref()?;
+        self.insert(ann.title.as_deref(), url, "Source")
+    }
+
+    pub fn into_vec(self) -> Vec<(String, String)> {
+        self.sources
+    }
+}
+
+/// Rendering switches for text output
+#[derive(Debug, Default, Clone)]
+pub struct FormatOptions {
+    /// Skip scanning the response for sources entirely
+    pub skip_sources: bool,
+    /// End with a greppable `X-Response-Id: <id>` line
+    pub id_trailer: bool,
+    /// Only list sources that aren't already in this baseline
+    pub baseline: Option<SourceBaseline>,
+    /// Print the model's reasoning before the answer
+    pub show_reasoning: bool,
+    /// Add an estimated cost at these rates to the footer
+    pub pricing: Option<Pricing>,
+    /// Add why generation stopped to the footer
+    pub show_finish_reason: bool,
+    /// Mark cited spans in the answer with `[n]`, numbered as in the Sources list
+    pub inline_citations: bool,
+    /// Sort sources by normalized URL so identical answers render identically;
+    /// JSON output is the raw response and keeps the API's order
+    pub stable_sources: bool,
+    /// Color the Sources list and error line for a terminal
+    pub color: bool,
+    /// List the searches the model ran before the answer
+    pub show_searches: bool,
+    /// Which kinds of source to list
+    pub sources: SourceFilter,
+    /// Stop after the answer and sources, with no footer
+    pub quiet: bool,
+    /// Print JSON on a single line instead of pretty-printed
+    pub compact: bool,
+    /// The response wasn't stored, so leave out the follow-up hint
+    pub no_store: bool,
+    /// List at most this many sources, in case the model returns more than asked for
+    pub max_sources: Option<usize>,
+    /// Leave the answer text out because it has already been printed (`--stream`)
+    pub omit_answer: bool,
+}
+
+pub fn format_response(response: &GrokResponse, format: &OutputFormat) -> String {
+    format_response_with(response, format, &FormatOptions::default())
+}
+
+pub fn format_response_with(
+    response: &GrokResponse,
+    format: &OutputFormat,
+    options: &FormatOptions,
+) -> String {
+    match format {
+        OutputFormat::Json => {
+            let value = json_output(response, options);
+            if options.compact {
+                serde_json::to_string(&value).unwrap_or_default()
+            } else {
+                serde_json::to_string_pretty(&value).unwrap_or_default()
+            }
+        }
+        _ => format_text(response, format, options, collect_sources),
+    }
+}
+
+/// The response as JSON, plus a `source_count` unless sources are skipped
+fn json_output(response: &GrokResponse, options: &FormatOptions) -> serde_json::Value {
+    let mut value = serde_json::to_value(response).unwrap_or_default();
+    if !options.skip_sources {
+        if let Some(object) = value.as_object_mut() {
+            object.insert(
+                "source_count".to_string(),
+                collect_sources(response).len().into(),
+            );
+        }
+    }
+    value
+}
+
+fn format_text(
+    response: &GrokResponse,
+    format: &OutputFormat,
+    options: &FormatOptions,
+    collect: impl Fn(&GrokResponse) -> Vec<(String, String)>,
+) -> String {
+    let markdown = matches!(format, OutputFormat::Markdown);
+    let palette = Palette {
+        enabled: options.color,
+    };
+
+    // Check for error
+    if let Some(error) = &response.error {
+        let line = format!(
+            "Error: {}",
+            error.message.as_deref().unwrap_or("Unknown error")
+        );
+        return format!("{}\n", palette.error(&line));
+    }
+
+    let mut output = String::new();
+    if options.show_searches {
+        let queries = extract_search_queries(response);
+        for query in &queries {
+            output.push_str(&format!("Searched: '{}'\n", query));
+        }
+        if !queries.is_empty() {
+            output.push('\n');
+        }
+    }
+
+    if options.show_reasoning {
+        let reasoning = extract_reasoning_text(response);
+        if !reasoning.is_empty() && markdown {
+            output.push_str(&format!("## Reasoning\n\n{}\n\n## Answer\n\n", reasoning));
+        } else if !reasoning.is_empty() {
+            output.push_str(&format!("Reasoning:\n{}\n\nAnswer:\n", reasoning));
+        }
+    }
+
+    if is_empty_response(response) {
+        output.push_str(&format!(
+            "No content was returned (status: {})",
+            response.status.as_deref().unwrap_or("unknown")
+        ));
+    }
+
+    let sources = if options.skip_sources || options.sources == SourceFilter::None {
+        Vec::new()
+    } else {
+        let mut sources = collect(response);
+        sources.retain(|(_, url)| options.sources.keeps(url));
+        sources
+    };
+    let mut sources = match &options.baseline {
+        Some(baseline) => baseline.new_sources(&sources),
+        None => sources,
+    };
+    if options.stable_sources {
+        sources.sort_by_cached_key(|(title, url)| (normalize_url(url), title.clone()));
+    }
+    if let Some(max) = options.max_sources {
+        sources.truncate(max);
+    }
+    if !options.omit_answer {
+        output.push_str(&if options.inline_citations {
+            extract_cited_answer_text(response, &sources)
+        } else {
+            extract_answer_text(response)
+        });
+    }
+
+    // Add sources
+    if !sources.is_empty() {
+        output.push_str(if markdown {
+            "\n\n## Sources\n\n"
+        } else {
+            "\n\nSources:\n"
+        });
+        let posts = x_posts(response);
+        for (i, (title, url)) in sources.iter().enumerate() {
+            let number = palette.number(&format!("{}.", i + 1));
+            let link = palette.link(url);
+            if markdown {
+                let title = title.replace('[', "\\[").replace(']', "\\]");
+                output.push_str(&format!("{} [{}](<{}>)", number, title, link));
+            } else {
+                output.push_str(&format!("{} [{}]({})", number, title, link));
+            }
+            let post = posts.get(url.as_str());
+            if let Some(byline) = post.and_then(|p| p.post_byline()) {
+                output.push_str(&format!(" \u{2014} {}", byline));
+            }
+            output.push('\n');
+            let media: Vec<&Media> = post
+                .map(|p| p.media.iter().filter(|m| m.url.is_some()).collect())
+                .unwrap_or_default();
+            if !media.is_empty() {
+                output.push_str("   Media:\n");
+                for item in media {
+                    output.push_str(&format!(
+                        "   - {}: {}\n",
+                        item.r#type.as_deref().unwrap_or("media"),
+                        palette.link(item.url.as_deref().unwrap_or_default())
+                    ));
+                }
+            }
+        }
+    }
+
+    if matches!(format, OutputFormat::Plain) || options.quiet {
+        if !output.ends_with('\n') {
+            output.push('\n');
+        }
+        return output;
+    }
+
+    // Add follow-up instructions
+    let mut footer = Vec::new();
+    if let Some(id) = response.id.as_ref().filter(|_| !options.no_store) {
+        footer.push(if markdown {
+            format!("To follow up, use response_id: `{}`", id)
+        } else {
+            format!("To follow up, use response_id: {}", id)
+        });
+    }
+    if let Some(usage) = &response.usage {
+        footer.push(format!(
+            "Tokens: {} in / {} out",
+            usage.input_tokens.unwrap_or(0),
+            usage.output_tokens.unwrap_or(0)
+        ));
+        if let Some(pricing) = &options.pricing {
+            footer.push(format!(
+                "Estimated cost: ${:.4}",
+                estimate_cost(usage, pricing)
+            ));
+        }
+    }
+    if options.show_finish_reason {
+        if let Some(reason) = response.finish_reason() {
+            footer.push(format!("Finish reason: {}", reason));
+        }
+    }
+
+    output.push_str(if markdown { "\n---\n\n" } else { "\n---\n" });
+    for line in footer {
+        if markdown {
+            output.push_str("- ");
+        }
+        output.push_str(&line);
+        output.push('\n');
+    }
+    if let Some(id) = &response.id {
+        if options.id_trailer {
+            output.push_str(&format!("X-Response-Id: {}\n", id));
+        }
+    }
+
+    output
+}
+
+/// Follow-up prompt sent against a finished answer to get related questions
+pub const SUGGEST_FOLLOWUPS_PROMPT: &str = "Suggest 3 related questions the user might ask next about this topic. Reply with a numbered list, one question per line, and nothing else.";
+
+/// Parses a numbered or bulleted list into its items, dropping the markers
+pub fn parse_question_list(text: &str, limit: usize) -> Vec<String> {
+    text.lines()
+        .map(|line| {
+            let line = line.trim();
+            let unnumbered = line.trim_start_matches(|c: char| c.is_ascii_digit());
+            if unnumbered.len() < line.len() {
+                unnumbered.trim_start_matches(['.', ')', ':'])
+            } else {
+                line.trim_start_matches(['-', '*', '•'])
+            }
+            .trim()
+            .to_string()
+        })
+        .filter(|item| !item.is_empty())
+        .take(limit)
+        .collect()
+}
+
+/// Renders suggested follow-up questions as a numbered list
+pub fn format_followups(questions: &[String]) -> String {
+    let mut output = String::from("Related questions:\n");
+    for (i, question) in questions.iter().enumerate() {
+        output.push_str(&format!("{}. {}\n", i + 1, question));
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test API key lookup
+    mod api_key {
+        use super::*;
+        use std::fs;
+
+        #[test]
+        fn test_env_wins_then_config_then_error() {
+            let dir = env::temp_dir().join("grok-ask-api-key-test");
+            let _ = fs::remove_dir_all(&dir);
+            let path = dir.join("config.toml");
+
+            let err = api_key_from(None, Some(&path)).unwrap_err().to_string();
+            assert!(err.contains("https://console.x.ai/"));
+
+            let mut config = Config::default();
+            config.auth.api_key = Some("from-config".to_string());
+            config.save(&path).unwrap();
+
+            assert_eq!(api_key_from(None, Some(&path)).unwrap(), "from-config");
+            assert_eq!(
+                api_key_from(Some("from-env".to_string()), Some(&path)).unwrap(),
+                "from-env"
+            );
+            assert_eq!(
+                api_key_from(Some(String::new()), Some(&path)).unwrap(),
+                "from-config"
+            );
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    // Test response parsing
+    mod parse_response {
+        use super::*;
+
+        fn make_response(text: &str, sources: Vec<(&str, &str)>) -> GrokResponse {
+            let results: Vec<WebSearchResult> = sources
+                .iter()
+                .map(|(title, url)| WebSearchResult {
+                    title: Some(title.to_string()),
+                    url: Some(url.to_string()),
+                    ..Default::default()
+                })
+                .collect();
+
+            GrokResponse {
+                id: Some("resp_123".to_string()),
+                status: Some("completed".to_string()),
+                finish_reason: None,
+                incomplete_details: None,
+                output: Some(vec![
+                    Output {
+                        r#type: "web_search_result".to_string(),
+                        content: None,
+                        results: Some(results),
+                        summary: None,
+                        action: None,
+                        arguments: None,
+                    },
+                    Output {
+                        r#type: "message".to_string(),
+                        content: Some(vec![Content {
+                            r#type: "output_text".to_string(),
+                            text: Some(text.to_string()),
+                            annotations: None,
+                        }]),
+                        results: None,
+                        summary: None,
+                        action: None,
+                        arguments: None,
+                    },
+                ]),
+                usage: None,
+                error: None,
+                meta: None,
+            }
+        }
+
+        #[test]
+        fn test_citations_json_shape() {
+            let response = make_response(
+                "Answer text.",
+                vec![("A", "https://a.com"), ("B", "https://b.com")],
+            );
+            assert_eq!(
+                citations_json(&response),
+                "[\n  {\n    \"title\": \"A\",\n    \"url\": \"https://a.com\"\n  },\n  {\n    \"title\": \"B\",\n    \"url\": \"https://b.com\"\n  }\n]"
+            );
+            assert_eq!(citations_json(&make_response("No sources.", vec![])), "[]");
+        }
+
+        #[test]
+        fn test_stable_sources_ignore_result_order() {
+            let first = make_response(
+                "Answer.",
+                vec![("B", "https://b.com/x"), ("A", "https://A.com/")],
+            );
+            let second = make_response(
+                "Answer.",
+                vec![("A", "https://A.com/"), ("B", "https://b.com/x")],
+            );
+            let options = FormatOptions {
+                stable_sources: true,
+                ..Default::default()
+            };
+
+            let output = format_response_with(&first, &OutputFormat::Text, &options);
+            assert_eq!(
+                output,
+                format_response_with(&second, &OutputFormat::Text, &options)
+            );
+            assert!(output.contains("1. [A](https://A.com/)\n2. [B](https://b.com/x)\n"));
+            assert_ne!(
+                format_response(&first, &OutputFormat::Text),
+                format_response(&second, &OutputFormat::Text)
+            );
+        }
+
+        #[test]
+        fn test_color_only_when_enabled() {
+            let response = make_response("Answer.", vec![("A", "https://a.com")]);
+            let plain = format_response(&response, &OutputFormat::Text);
+            assert!(!plain.contains('\x1b'));
+
+            let options = FormatOptions {
+                color: true,
+                ..Default::default()
+            };
+            let colored = format_response_with(&response, &OutputFormat::Text, &options);
+            assert!(colored.contains("\x1b[2m1.\x1b[0m [A](\x1b[36mhttps://a.com\x1b[39m)\n"));
+
+            // JSON is never colored, whatever the options say
+            let json = format_response_with(&response, &OutputFormat::Json, &options);
+            assert!(!json.contains('\x1b'));
+        }
+
+        #[test]
+        fn test_format_simple_response() {
+            let response = GrokResponse {
+                id: Some("resp_123".to_string()),
+                status: Some("completed".to_string()),
+                finish_reason: None,
+                incomplete_details: None,
+                output: Some(vec![Output {
+                    r#type: "message".to_string(),
+                    content: Some(vec![Content {
+                        r#type: "output_text".to_string(),
+                        text: Some("Hello, world!".to_string()),
+                        annotations: None,
+                    }]),
+                    results: None,
+                    summary: None,
+                    action: None,
+                    arguments: None,
+                }]),
+                usage: None,
+                error: None,
+                meta: None,
+            };
+
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(output.contains("Hello, world!"));
+            assert!(output.contains("response_id: resp_123"));
+        }
+
+        #[test]
+        fn test_format_response_with_sources() {
+            let response = make_response(
+                "Found results.",
+                vec![("News", "https://news.com"), ("Blog", "https://blog.com")],
+            );
+
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(output.contains("Found results."));
+            assert!(output.contains("Sources:"));
+            assert!(output.contains("[News](https://news.com)"));
+            assert!(output.contains("[Blog](https://blog.com)"));
+        }
+
+        #[test]
+        fn test_format_error_response() {
+            let response = GrokResponse {
+                id: None,
+                status: Some("failed".to_string()),
+                finish_reason: None,
+                incomplete_details: None,
+                output: None,
+                usage: None,
+                error: Some(ApiError {
+                    message: Some("Rate limit exceeded".to_string()),
+                    code: Some("429".to_string()),
+                }),
+                meta: None,
+            };
+
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(output.contains("Error: Rate limit exceeded"));
+        }
+
+        #[test]
+        fn test_format_json_output() {
+            let response = GrokResponse {
+                id: Some("resp_json".to_string()),
+                status: Some("completed".to_string()),
+                finish_reason: None,
+                incomplete_details: None,
+                output: Some(vec![]),
+                usage: None,
+                error: None,
+                meta: None,
+            };
+
+            let output = format_response(&response, &OutputFormat::Json);
+            assert!(output.contains("\"id\": \"resp_json\""));
+            assert!(output.contains("\"status\": \"completed\""));
+        }
+
+        #[test]
+        fn test_x_search_result_parsing() {
+            let response = GrokResponse {
+                id: Some("resp_x".to_string()),
+                status: Some("completed".to_string()),
+                finish_reason: None,
+                incomplete_details: None,
+                output: Some(vec![
+                    Output {
+                        r#type: "x_search_result".to_string(),
+                        content: None,
+                        results: Some(vec![WebSearchResult {
+                            title: Some("@user".to_string()),
+                            url: Some("https://x.com/user/status/123".to_string()),
+                            ..Default::default()
+                        }]),
+                        summary: None,
+                        action: None,
+                        arguments: None,
+                    },
+                    Output {
+                        r#type: "message".to_string(),
+                        content: Some(vec![Content {
+                            r#type: "output_text".to_string(),
+                            text: Some("X post found.".to_string()),
+                            annotations: None,
+                        }]),
+                        results: None,
+                        summary: None,
+                        action: None,
+                        arguments: None,
+                    },
+                ]),
+                usage: None,
+                error: None,
+                meta: None,
+            };
+
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(output.contains("X post found."));
+            assert!(output.contains("[@user](https://x.com/user/status/123)\n"));
+        }
+
+        #[test]
+        fn test_x_post_author_and_date() {
+            let response: GrokResponse = serde_json::from_str(
+                r#"{"id": "resp_x", "output": [
+                    {"type": "x_search_result", "results": [
+                        {"url": "https://x.com/xai/status/1", "title": "Grok 3 is out",
+                         "username": "xai", "created_at": "2025-01-03T17:04:11Z",
+                         "like_count": 5120, "retweet_count": 804},
+                        {"url": "https://x.com/elonmusk/status/2", "title": "Try it",
+                         "author": "@elonmusk"}
+                    ]},
+                    {"type": "web_search_result", "results": [
+                        {"url": "https://x.ai/news", "title": "xAI News"}
+                    ]},
+                    {"type": "message", "content": [{"type": "output_text", "text": "Released."}]}
+                ]}"#,
+            )
+            .unwrap();
+            let post = &response.output.as_ref().unwrap()[0]
+                .results
+                .as_ref()
+                .unwrap()[0];
+            assert_eq!(post.author.as_deref(), Some("xai"));
+            assert_eq!(post.posted_at.as_deref(), Some("2025-01-03T17:04:11Z"));
+            assert_eq!(post.like_count, Some(5120));
+            assert_eq!(post.repost_count, Some(804));
+
+            let output = format_response(&response, &OutputFormat::Plain);
+            assert_eq!(
+                output,
+                "Released.\n\nSources:\n\
+                 1. [Grok 3 is out](https://x.com/xai/status/1) \u{2014} @xai \u{b7} 2025-01-03\n\
+                 2. [Try it](https://x.com/elonmusk/status/2) \u{2014} @elonmusk\n\
+                 3. [xAI News](https://x.ai/news)\n"
+            );
+        }
+
+        #[test]
+        fn test_x_post_media() {
+            let response: GrokResponse = serde_json::from_str(
+                r#"{"id": "resp_x", "output": [
+                    {"type": "x_search_result", "results": [
+                        {"url": "https://x.com/spacex/status/1", "title": "Liftoff",
+                         "media": [
+                            {"type": "photo", "url": "https://pbs.twimg.com/media/a.jpg"},
+                            {"media_type": "video", "media_url": "https://video.twimg.com/b.mp4"}
+                         ]},
+                        {"url": "https://x.com/spacex/status/2", "title": "Text only"},
+                        {"url": "https://x.com/spacex/status/3", "title": "Null", "media": null}
+                    ]},
+                    {"type": "message", "content": [{"type": "output_text", "text": "It flew."}]}
+                ]}"#,
+            )
+            .unwrap();
+            let results = response.output.as_ref().unwrap()[0]
+                .results
+                .as_ref()
+                .unwrap();
+            assert_eq!(
+                results[0].media[0],
+                Media {
+                    r#type: Some("photo".to_string()),
+                    url: Some("https://pbs.twimg.com/media/a.jpg".to_string()),
+                }
+            );
+            assert_eq!(results[0].media[1].r#type.as_deref(), Some("video"));
+            assert!(results[1].media.is_empty());
+            assert!(results[2].media.is_empty());
+
+            let output = format_response(&response, &OutputFormat::Plain);
+            assert_eq!(
+                output,
+                "It flew.\n\nSources:\n\
+                 1. [Liftoff](https://x.com/spacex/status/1)\n   \
+                 Media:\n   \
+                 - photo: https://pbs.twimg.com/media/a.jpg\n   \
+                 - video: https://video.twimg.com/b.mp4\n\
+                 2. [Text only](https://x.com/spacex/status/2)\n\
+                 3. [Null](https://x.com/spacex/status/3)\n"
+            );
+
+            let json = format_response(&response, &OutputFormat::Json);
+            assert!(json.contains("\"media\": ["));
+            assert!(!format_response(
+                &make_response("Hi.", vec![("A", "https://a.com")]),
+                &OutputFormat::Json
+            )
+            .contains("\"media\""));
+        }
+
+        #[test]
+        fn test_inline_citations_at_offsets_and_block_end() {
+            let json = r#"{
+                "id": "resp_cite",
+                "output": [
+                    {"type": "message", "content": [
+                        {"type": "output_text", "text": "Grok is by xAI. It launched in 2023.",
+                         "annotations": [
+                            {"url": "https://x.ai", "title": "xAI", "start_index": 0, "end_index": 15},
+                            {"url": "https://wiki.org/Grok", "title": "Wiki", "start_index": 16, "end_index": 36},
+                            {"url": "https://x.ai", "title": "xAI", "start_index": 16, "end_index": 36}
+                         ]},
+                        {"type": "output_text", "text": " No offsets here.",
+                         "annotations": [{"url": "https://wiki.org/Grok", "title": "Wiki"}]}
+                    ]}
+                ]
+            }"#;
+            let response: GrokResponse = serde_json::from_str(json).unwrap();
+
+            let options = FormatOptions {
+                inline_citations: true,
+                ..Default::default()
+            };
+            let output = format_response_with(&response, &OutputFormat::Text, &options);
+            assert!(output.starts_with(
+                "Grok is by xAI.[1] It launched in 2023.[1][2] No offsets here.[2]\n\nSources:\n1. [xAI](https://x.ai)\n2. [Wiki](https://wiki.org/Grok)\n"
+            ));
+
+            let plain = format_response(&response, &OutputFormat::Text);
+            assert!(plain.starts_with("Grok is by xAI. It launched in 2023. No offsets here."));
+        }
+
+        #[test]
+        fn test_extract_answer_text_multi_block() {
+            let response = GrokResponse {
+                id: Some("resp_multi".to_string()),
+                status: Some("completed".to_string()),
+                finish_reason: None,
+                incomplete_details: None,
+                output: Some(vec![
+                    Output {
+                        r#type: "message".to_string(),
+                        content: Some(vec![
+                            Content {
+                                r#type: "output_text".to_string(),
+                                text: Some("First part. ".to_string()),
+                                annotations: Some(vec![Annotation {
+                                    url: Some("https://a.com".to_string()),
+                                    title: Some("A".to_string()),
+                                    start_index: None,
+                                    end_index: None,
+                                }]),
+                            },
+                            Content {
+                                r#type: "output_text".to_string(),
+                                text: Some("Second part.".to_string()),
+                                annotations: None,
+                            },
+                        ]),
+                        results: None,
+                        summary: None,
+                        action: None,
+                        arguments: None,
+                    },
+                    Output {
+                        r#type: "web_search_result".to_string(),
+                        content: None,
+                        results: Some(vec![WebSearchResult {
+                            title: Some("B".to_string()),
+                            url: Some("https://b.com".to_string()),
+                            ..Default::default()
+                        }]),
+                        summary: None,
+                        action: None,
+                        arguments: None,
+                    },
+                    Output {
+                        r#type: "message".to_string(),
+                        content: Some(vec![Content {
+                            r#type: "text".to_string(),
+                            text: Some(" Third.".to_string()),
+                            annotations: None,
+                        }]),
+                        results: None,
+                        summary: None,
+                        action: None,
+                        arguments: None,
+                    },
+                ]),
+                usage: None,
+                error: None,
+                meta: None,
+            };
+
+            let text = extract_answer_text(&response);
+            assert_eq!(text, "First part. Second part. Third.");
+            assert!(!text.contains("Sources:"));
+            assert!(!text.contains("response_id"));
+        }
+
+        #[test]
+        fn test_reasoning_hidden_unless_requested() {
+            let json = r#"{
+                "id": "resp_reason",
+                "status": "completed",
+                "output": [
+                    {
+                        "type": "reasoning",
+                        "summary": [{"type": "summary_text", "text": "Weigh both options."}]
+                    },
+                    {
+                        "type": "message",
+                        "content": [{"type": "output_text", "text": "Pick the first."}]
+                    }
+                ]
+            }"#;
+            let response: GrokResponse = serde_json::from_str(json).unwrap();
+
+            assert_eq!(extract_answer_text(&response), "Pick the first.");
+            assert_eq!(extract_reasoning_text(&response), "Weigh both options.");
+
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(output.starts_with("Pick the first."));
+            assert!(!output.contains("Weigh both options."));
+
+            let options = FormatOptions {
+                show_reasoning: true,
+                ..Default::default()
+            };
+            let output = format_response_with(&response, &OutputFormat::Text, &options);
+            assert!(
+                output.starts_with("Reasoning:\nWeigh both options.\n\nAnswer:\nPick the first.")
+            );
+        }
+
+        #[test]
+        fn test_skip_sources_bypasses_collection() {
+            let response = make_response("Answer.", vec![("News", "https://news.com")]);
+            let called = std::cell::Cell::new(false);
+            let collect = |r: &GrokResponse| {
+                called.set(true);
+                collect_sources(r)
+            };
+
+            let options = FormatOptions {
+                skip_sources: true,
+                ..Default::default()
+            };
+            let output = format_text(&response, &OutputFormat::Text, &options, collect);
+            assert!(!called.get());
+            assert!(output.contains("Answer."));
+            assert!(!output.contains("Sources:"));
+
+            let output = format_text(
+                &response,
+                &OutputFormat::Text,
+                &FormatOptions::default(),
+                collect,
+            );
+            assert!(called.get());
+            assert!(output.contains("[News](https://news.com)"));
+        }
+
+        #[test]
+        fn test_empty_output_is_explained() {
+            let response = GrokResponse {
+                output: Some(vec![]),
+                ..make_response("", vec![])
+            };
+            assert!(is_empty_response(&response));
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(output
+                .starts_with("No content was returned (status: completed)\n---\nTo follow up"));
+
+            let queued = GrokResponse {
+                output: None,
+                status: Some("queued".to_string()),
+                ..make_response("", vec![])
+            };
+            assert!(!is_empty_response(&queued));
+            assert!(!is_empty_response(&make_response("Answer.", vec![])));
+        }
+
+        #[test]
+        fn test_quiet_drops_footer() {
+            let response = make_response("Answer.", vec![("News", "https://news.com")]);
+            let options = FormatOptions {
+                quiet: true,
+                ..Default::default()
+            };
+            for format in [OutputFormat::Text, OutputFormat::Markdown] {
+                let output = format_response_with(&response, &format, &options);
+                assert!(output.contains("https://news.com"));
+                assert!(!output.contains("---"));
+                assert!(!output.contains("response_id"));
+                assert!(output.ends_with(")\n"), "{}", output);
+            }
+            let json = format_response_with(&response, &OutputFormat::Json, &options);
+            assert!(json.contains("\"id\": \"resp_123\""));
+        }
+
+        #[test]
+        fn test_compact_json_is_one_line() {
+            let response =
+                make_response("Line one.\nLine two.", vec![("News", "https://news.com")]);
+            let options = FormatOptions {
+                compact: true,
+                ..Default::default()
+            };
+            let compact = format_response_with(&response, &OutputFormat::Json, &options);
+            assert!(!compact.contains('\n'));
+
+            let pretty = format_response(&response, &OutputFormat::Json);
+            let a: serde_json::Value = serde_json::from_str(&compact).unwrap();
+            let b: serde_json::Value = serde_json::from_str(&pretty).unwrap();
+            assert_eq!(a, b);
+            let parsed: GrokResponse = serde_json::from_value(a).unwrap();
+            assert_eq!(extract_answer_text(&parsed), "Line one.\nLine two.");
+        }
+
+        #[test]
+        fn test_sources_filtered_by_kind() {
+            let response = make_response(
+                "Answer.",
+                vec![
+                    ("News", "https://news.com/a"),
+                    ("Post", "https://x.com/xai/status/1"),
+                    ("Old post", "https://mobile.twitter.com/xai/status/2"),
+                ],
+            );
+            let render = |sources| {
+                let options = FormatOptions {
+                    sources,
+                    ..Default::default()
+                };
+                format_response_with(&response, &OutputFormat::Text, &options)
+            };
+
+            let web = render(SourceFilter::Web);
+            assert!(web.contains("Sources:\n1. [News](https://news.com/a)\n\n---"));
+            let x = render(SourceFilter::X);
+            assert!(x.contains("1. [Post](https://x.com/xai/status/1)\n2. [Old post]"));
+            assert!(!x.contains("news.com"));
+            let none = render(SourceFilter::None);
+            assert!(none.starts_with("Answer.\n---\n"));
+            assert_eq!(render(SourceFilter::All).matches("](").count(), 3);
+        }
+
+        #[test]
+        fn test_output_format_for_path() {
+            let format = |path: &str| OutputFormat::for_path(Path::new(path));
+            assert!(matches!(format("out/result.json"), OutputFormat::Json));
+            assert!(matches!(format("RESULT.JSON"), OutputFormat::Json));
+            assert!(matches!(format("notes.md"), OutputFormat::Markdown));
+            assert!(matches!(format("notes.txt"), OutputFormat::Text));
+            assert!(matches!(format("answer"), OutputFormat::Text));
+        }
+
+        #[test]
+        fn test_sources_truncated_to_max() {
+            let response = make_response(
+                "Answer.",
+                vec![
+                    ("A", "https://a.com"),
+                    ("B", "https://b.com"),
+                    ("C", "https://c.com"),
+                ],
+            );
+            let options = FormatOptions {
+                max_sources: Some(2),
+                ..Default::default()
+            };
+            let output = format_response_with(&response, &OutputFormat::Text, &options);
+            assert!(
+                output.contains("Sources:\n1. [A](https://a.com)\n2. [B](https://b.com)\n\n---")
+            );
+            assert!(!output.contains("c.com"));
+        }
+
+        #[test]
+        fn test_markdown_format_has_headed_sources_and_footer() {
+            let response = make_response("Answer.", vec![("News [live]", "https://news.com/a(b)")]);
+            let output = format_response(&response, &OutputFormat::Markdown);
+            assert!(output.starts_with("Answer.\n\n## Sources\n\n"));
+            assert!(output.contains("1. [News \\[live\\]](<https://news.com/a(b)>)\n"));
+            assert!(output.contains("\n---\n\n- To follow up, use response_id: `"));
+        }
+
+        #[test]
+        fn test_search_calls_summarized() {
+            let response: GrokResponse = serde_json::from_str(
+                r#"{"id": "r1", "output": [
+                    {"type": "web_search_call", "status": "completed",
+                     "action": {"type": "search", "query": "climate policy 2025"}},
+                    {"type": "custom_tool_call", "name": "x_keyword_search",
+                     "input": "{\"query\": \"COP30 outcomes\", \"limit\": 10}"},
+                    {"type": "web_search_call", "action": {"type": "search", "query": "climate policy 2025"}},
+                    {"type": "message", "content": [{"type": "output_text", "text": "Policies changed."}]}
+                ]}"#,
+            )
+            .unwrap();
+            assert_eq!(
+                extract_search_queries(&response),
+                vec!["climate policy 2025", "COP30 outcomes"]
+            );
+
+            let options = FormatOptions {
+                show_searches: true,
+                ..Default::default()
+            };
+            let output = format_response_with(&response, &OutputFormat::Plain, &options);
+            assert_eq!(
+                output,
+                "Searched: 'climate policy 2025'\nSearched: 'COP30 outcomes'\n\nPolicies changed.\n"
+            );
+        }
+
+        #[test]
+        fn test_plain_format_has_no_footer() {
+            let mut response = make_response("Answer.", vec![("News", "https://news.com")]);
+            response.usage = Some(Usage {
+                input_tokens: Some(1),
+                output_tokens: Some(2),
+            });
+            let options = FormatOptions {
+                id_trailer: true,
+                show_finish_reason: true,
+                ..Default::default()
+            };
+            let output = format_response_with(&response, &OutputFormat::Plain, &options);
+            assert_eq!(output, "Answer.\n\nSources:\n1. [News](https://news.com)\n");
+            assert!(!output.contains("---"));
+            assert!(!output.contains("response_id"));
+
+            let bare = make_response("Just text", vec![]);
+            assert_eq!(format_response(&bare, &OutputFormat::Plain), "Just text\n");
+        }
+
+        #[test]
+        fn test_no_store_drops_follow_up_hint() {
+            let mut response = make_response("Answer.", vec![]);
+            response.usage = Some(Usage {
+                input_tokens: Some(10),
+                output_tokens: Some(5),
+            });
+            let options = FormatOptions {
+                no_store: true,
+                ..Default::default()
+            };
+            for format in [OutputFormat::Text, OutputFormat::Markdown] {
+                let output = format_response_with(&response, &format, &options);
+                assert!(!output.contains("To follow up"));
+                assert!(output.contains("Tokens: 10 in / 5 out"));
+            }
+        }
+
+        #[test]
+        fn test_omit_answer_keeps_sources_and_footer() {
+            let response = make_response("Streamed answer.", vec![("A", "https://a.com")]);
+            let options = FormatOptions {
+                omit_answer: true,
+                ..Default::default()
+            };
+            let output = format_response_with(&response, &OutputFormat::Text, &options);
+            assert!(!output.contains("Streamed answer."));
+            assert!(output.starts_with("\n\nSources:\n1. [A](https://a.com)\n"));
+        }
+
+        #[test]
+        fn test_usage_footer_and_cost() {
+            let mut response = make_response("Answer.", vec![]);
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(!output.contains("Tokens:"));
+
+            response.usage = Some(Usage {
+                input_tokens: Some(1024),
+                output_tokens: Some(512),
+            });
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(output
+                .ends_with("To follow up, use response_id: resp_123\nTokens: 1024 in / 512 out\n"));
+            assert!(!output.contains("Estimated cost"));
+
+            let options = FormatOptions {
+                pricing: Some(Pricing {
+                    input_per_million: 2.0,
+                    output_per_million: 10.0,
+                }),
+                id_trailer: true,
+                ..Default::default()
+            };
+            let output = format_response_with(&response, &OutputFormat::Text, &options);
+            // 1024 * 2.0 / 1M + 512 * 10.0 / 1M = 0.007168
+            assert!(output.contains("Tokens: 1024 in / 512 out\nEstimated cost: $0.0072\n"));
+            assert_eq!(output.lines().last(), Some("X-Response-Id: resp_123"));
+        }
+
+        #[test]
+        fn test_length_finish_reason() {
+            let json = r#"{
+                "id": "resp_cut",
+                "status": "completed",
+                "finish_reason": "length",
+                "output": [{"type": "message", "content": [{"type": "output_text", "text": "Partial"}]}]
+            }"#;
+            let response: GrokResponse = serde_json::from_str(json).unwrap();
+            assert_eq!(response.finish_reason(), Some("length"));
+
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(!output.contains("Finish reason"));
+
+            let options = FormatOptions {
+                show_finish_reason: true,
+                ..Default::default()
+            };
+            let output = format_response_with(&response, &OutputFormat::Text, &options);
+            assert!(
+                output.contains("To follow up, use response_id: resp_cut\nFinish reason: length\n")
+            );
+
+            let incomplete: GrokResponse = serde_json::from_str(
+                r#"{"id": "r", "status": "incomplete", "incomplete_details": {"reason": "max_output_tokens"}}"#,
+            )
+            .unwrap();
+            assert_eq!(incomplete.finish_reason(), Some("max_output_tokens"));
+        }
+
+        #[test]
+        fn test_id_trailer_line() {
+            let response = make_response("Answer.", vec![]);
+            let options = FormatOptions {
+                id_trailer: true,
+                ..Default::default()
+            };
+
+            let output = format_response_with(&response, &OutputFormat::Text, &options);
+            assert!(output.contains("To follow up, use response_id: resp_123"));
+            assert_eq!(output.lines().last(), Some("X-Response-Id: resp_123"));
+
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(!output.contains("X-Response-Id"));
+        }
+
+        #[test]
+        fn test_normalize_url() {
+            assert_eq!(
+                normalize_url("HTTPS://Example.COM/Path/"),
+                "https://example.com/Path"
+            );
+            assert_eq!(normalize_url("https://a.com/x#frag"), "https://a.com/x");
+            assert_eq!(normalize_url("https://a.com/?q=1"), "https://a.com/?q=1");
+            assert_eq!(normalize_url(" https://a.com "), "https://a.com");
+        }
+
+        #[test]
+        fn test_baseline_hides_known_sources() {
+            let response = make_response(
+                "Answer.",
+                vec![("Old", "https://old.com"), ("New", "https://new.com")],
+            );
+            let mut baseline = SourceBaseline::default();
+            baseline.extend(&[("Old".to_string(), "https://old.com/".to_string())]);
+            let options = FormatOptions {
+                baseline: Some(baseline),
+                ..Default::default()
+            };
+
+            let output = format_response_with(&response, &OutputFormat::Text, &options);
+            assert!(!output.contains("old.com"));
+            assert!(output.contains("1. [New](https://new.com)"));
+        }
+
+        #[test]
+        fn test_background_started_returns_id_immediately() {
+            let response: GrokResponse =
+                serde_json::from_str(r#"{"id": "resp_bg", "status": "queued"}"#).unwrap();
+
+            assert!(is_pending(&response));
+            let output = format_background_started(&response);
+            assert!(output.contains("status: queued"));
+            assert!(output.contains("grok-ask get resp_bg"));
+        }
+
+        #[test]
+        fn test_json_output_includes_source_count() {
+            let response = make_response(
+                "Answer.",
+                vec![
+                    ("A", "https://a.com"),
+                    ("B", "https://b.com"),
+                    ("A again", "https://a.com"),
+                ],
+            );
+
+            let output = format_response(&response, &OutputFormat::Json);
+            let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+            assert_eq!(value["source_count"], 2);
+            assert_eq!(value["id"], "resp_123");
+        }
+    }
+
+    // Test request serialization
+    mod serialize_request {
+        use super::*;
+
+        #[test]
+        fn test_web_search_tool_serialization() {
+            let tool = Tool::WebSearch(WebSearchTool {
+                r#type: "web_search".to_string(),
+                enable_image_understanding: None,
+                country: None,
+                max_search_results: None,
+            });
+
+            let json = serde_json::to_string(&tool).unwrap();
+            assert!(json.contains("\"type\":\"web_search\""));
+            assert!(!json.contains("enable_image_understanding"));
+            assert!(!json.contains("country"));
+        }
+
+        #[test]
+        fn test_web_search_country() {
+            let options = RequestOptions {
+                use_web_search: true,
+                country: Some("DE".to_string()),
+                ..Default::default()
+            };
+            let json = serde_json::to_value(build_request("Wetter", &options)).unwrap();
+            assert_eq!(
+                json["tools"][0],
+                serde_json::json!({"type": "web_search", "country": "DE"})
+            );
+        }
+
+        #[test]
+        fn test_web_search_image_understanding() {
+            let mut options = RequestOptions {
+                use_web_search: true,
+                ..Default::default()
+            };
+            let json = serde_json::to_string(&build_request("q", &options)).unwrap();
+            assert!(!json.contains("enable_image_understanding"));
+
+            options.web_images = true;
+            let json = serde_json::to_string(&build_request("q", &options)).unwrap();
+            assert!(json.contains(
+                "\"tools\":[{\"type\":\"web_search\",\"enable_image_understanding\":true}]"
+            ));
+        }
+
+        #[test]
+        fn test_web_search_max_results() {
+            let mut options = RequestOptions {
+                use_web_search: true,
+                ..Default::default()
+            };
+            let json = serde_json::to_string(&build_request("q", &options)).unwrap();
+            assert!(!json.contains("max_search_results"));
+
+            options.max_search_results = Some(3);
+            let json = serde_json::to_string(&build_request("q", &options)).unwrap();
+            assert!(json.contains("\"tools\":[{\"type\":\"web_search\",\"max_search_results\":3}]"));
+        }
+
+        #[test]
+        fn test_prior_messages_come_before_the_query() {
+            let options = RequestOptions {
+                system_instruction: Some("Be terse.".to_string()),
+                prior_messages: priming::parse_example("2+2::4").unwrap().to_vec(),
+                ..Default::default()
+            };
+            let request = build_request("3+3", &options);
+            let roles: Vec<&str> = request.input.iter().map(|m| m.role.as_str()).collect();
+            assert_eq!(roles, ["system", "user", "assistant", "user"]);
+            let json = serde_json::to_string(&request).unwrap();
+            assert!(json.contains(
+                "{\"role\":\"user\",\"content\":\"2+2\"},{\"role\":\"assistant\",\"content\":\"4\"},{\"role\":\"user\",\"content\":\"3+3\"}"
+            ));
+        }
+
+        #[test]
+        fn test_x_search_tool_serialization() {
+            let tool = Tool::XSearch(XSearchTool {
+                r#type: "x_search".to_string(),
+                allowed_x_handles: Some(vec!["user1".to_string(), "user2".to_string()]),
+                excluded_x_handles: None,
+                from_date: Some("2025-01-01".to_string()),
+                to_date: Some("2025-01-15".to_string()),
+                enable_image_understanding: Some(true),
+                enable_video_understanding: Some(true),
+            });
+
+            let json = serde_json::to_string(&tool).unwrap();
+            assert!(json.contains("\"type\":\"x_search\""));
+            assert!(json.contains("\"allowed_x_handles\":[\"user1\",\"user2\"]"));
+            assert!(json.contains("\"from_date\":\"2025-01-01\""));
+            assert!(json.contains("\"to_date\":\"2025-01-15\""));
+            assert!(json.contains("\"enable_image_understanding\":true"));
+            assert!(json.contains("\"enable_video_understanding\":true"));
+        }
+
+        #[test]
+        fn test_x_search_tool_minimal() {
+            let tool = Tool::XSearch(XSearchTool {
+                r#type: "x_search".to_string(),
+                allowed_x_handles: None,
+                excluded_x_handles: None,
+                from_date: None,
+                to_date: None,
+                enable_image_understanding: None,
+                enable_video_understanding: None,
+            });
+
+            let json = serde_json::to_string(&tool).unwrap();
+            assert!(json.contains("\"type\":\"x_search\""));
+            assert!(!json.contains("allowed_x_handles"));
+            assert!(!json.contains("from_date"));
+        }
+
+        #[test]
+        fn test_request_serialization() {
+            let request = GrokRequest {
+                model: "grok-4-1-fast".to_string(),
+                input: vec![Message {
+                    role: "user".to_string(),
+                    content: "test query".into(),
+                }],
+                store: true,
+                max_output_tokens: Some(8192),
+                temperature: None,
+                reasoning_effort: None,
+                previous_response_id: Some("resp_prev".to_string()),
+                prompt_cache_key: None,
+                tools: vec![Tool::WebSearch(WebSearchTool {
+                    r#type: "web_search".to_string(),
+                    enable_image_understanding: None,
+                    country: None,
+                    max_search_results: None,
+                })],
+                tool_choice: None,
+                max_tool_calls: None,
+                background: None,
+                stream: None,
+                text: None,
+            };
+
+            let json = serde_json::to_string(&request).unwrap();
+            assert!(json.contains("\"model\":\"grok-4-1-fast\""));
+            assert!(json.contains("\"store\":true"));
+            assert!(json.contains("\"max_output_tokens\":8192"));
+            assert!(json.contains("\"previous_response_id\":\"resp_prev\""));
+            assert!(json.contains("\"type\":\"web_search\""));
+        }
+
+        #[test]
+        fn test_no_store_sends_store_false() {
+            let options = RequestOptions {
+                no_store: true,
+                ..Default::default()
+            };
+            let json = serde_json::to_string(&build_request("q", &options)).unwrap();
+            assert!(json.contains("\"store\":false"));
+        }
+
+        #[test]
+        fn test_request_without_tools() {
+            let request = GrokRequest {
+                model: "grok-4-1-fast-non-reasoning".to_string(),
+                input: vec![Message {
+                    role: "user".to_string(),
+                    content: "chat".into(),
+                }],
+                store: true,
+                max_output_tokens: None,
+                temperature: None,
+                reasoning_effort: None,
+                previous_response_id: None,
+                prompt_cache_key: None,
+                tools: vec![],
+                tool_choice: None,
+                max_tool_calls: None,
+                background: None,
+                stream: None,
+                text: None,
+            };
+
+            let json = serde_json::to_string(&request).unwrap();
+            assert!(!json.contains("\"tools\""));
+            assert!(!json.contains("\"max_output_tokens\""));
+            assert!(!json.contains("\"previous_response_id\""));
+        }
+
+        #[test]
+        fn test_temperature_omitted_unless_set() {
+            let options = RequestOptions {
+                max_tokens: 1024,
+                ..Default::default()
+            };
+            let json = serde_json::to_string(&build_request("hi", &options)).unwrap();
+            assert!(!json.contains("temperature"));
+
+            let options = RequestOptions {
+                temperature: Some(0.5),
+                ..options
+            };
+            let json = serde_json::to_string(&build_request("hi", &options)).unwrap();
+            assert!(json.contains("\"temperature\":0.5"));
+        }
+
+        #[test]
+        fn test_image_sent_as_content_parts() {
+            let options = RequestOptions {
+                image: Some("data:image/png;base64,iVBORw0KGgo=".to_string()),
+                ..Default::default()
+            };
+            let json = serde_json::to_value(build_request("What is this?", &options)).unwrap();
+            assert_eq!(
+                json["input"][0]["content"],
+                serde_json::json!([
+                    {"type": "input_text", "text": "What is this?"},
+                    {"type": "input_image", "image_url": "data:image/png;base64,iVBORw0KGgo="},
+                ])
+            );
+
+            let json =
+                serde_json::to_value(build_request("hi", &RequestOptions::default())).unwrap();
+            assert_eq!(json["input"][0]["content"], "hi");
+        }
+
+        #[test]
+        fn test_max_tool_calls_omitted_unless_set() {
+            let options = RequestOptions {
+                use_web_search: true,
+                ..Default::default()
+            };
+            let json = serde_json::to_string(&build_request("hi", &options)).unwrap();
+            assert!(!json.contains("max_tool_calls"));
+
+            let options = RequestOptions {
+                max_tool_calls: Some(2),
+                ..options
+            };
+            let json = serde_json::to_string(&build_request("hi", &options)).unwrap();
+            assert!(json.contains("\"max_tool_calls\":2"));
+        }
+
+        #[test]
+        fn test_prompt_cache_key_set_by_cache_prompt() {
+            let options = RequestOptions {
+                system_instruction: Some("A long shared preamble.".to_string()),
+                ..Default::default()
+            };
+            let json = serde_json::to_string(&build_request("hi", &options)).unwrap();
+            assert!(!json.contains("prompt_cache_key"));
+
+            let options = RequestOptions {
+                cache_prompt: true,
+                ..options
+            };
+            let first = build_request("hi", &options);
+            let second = build_request("something else", &options);
+            let json = serde_json::to_value(&first).unwrap();
+            assert_eq!(json["prompt_cache_key"].as_str().unwrap().len(), 64);
+            assert_eq!(first.prompt_cache_key, second.prompt_cache_key);
+
+            // Nothing to cache without a system instruction
+            let options = RequestOptions {
+                system_instruction: None,
+                ..options
+            };
+            assert!(build_request("hi", &options).prompt_cache_key.is_none());
+        }
+
+        #[test]
+        fn test_tool_choice_serialization() {
+            let make = |tool_choice| GrokRequest {
+                model: MODEL.to_string(),
+                input: vec![],
+                store: true,
+                max_output_tokens: None,
+                temperature: None,
+                reasoning_effort: None,
+                previous_response_id: None,
+                prompt_cache_key: None,
+                tools: vec![Tool::WebSearch(WebSearchTool {
+                    r#type: "web_search".to_string(),
+                    enable_image_understanding: None,
+                    country: None,
+                    max_search_results: None,
+                })],
+                tool_choice,
+                max_tool_calls: None,
+                background: None,
+                stream: None,
+                text: None,
+            };
+
+            let json = serde_json::to_string(&make(None)).unwrap();
+            assert!(!json.contains("tool_choice"));
+
+            let json = serde_json::to_string(&make(Some(ToolChoice::Required))).unwrap();
+            assert!(json.contains("\"tool_choice\":\"required\""));
+
+            let json = serde_json::to_string(&make(Some(ToolChoice::None))).unwrap();
+            assert!(json.contains("\"tool_choice\":\"none\""));
+
+            let json = serde_json::to_string(&make(Some(ToolChoice::Auto))).unwrap();
+            assert!(!json.contains("tool_choice"));
+        }
+
+        #[test]
+        fn test_background_serialization() {
+            let request = build_request(
+                "long job",
+                &RequestOptions {
+                    background: true,
+                    ..Default::default()
+                },
+            );
+            let json = serde_json::to_string(&request).unwrap();
+            assert!(json.contains("\"background\":true"));
+
+            let request = build_request("quick", &RequestOptions::default());
+            let json = serde_json::to_string(&request).unwrap();
+            assert!(!json.contains("background"));
+        }
+    }
+
+    // Test XSearchConfig
+    mod x_search_config {
+        use super::*;
+
+        #[test]
+        fn test_default_config() {
+            let config = XSearchConfig::default();
+            assert!(config.allowed_handles.is_none());
+            assert!(config.excluded_handles.is_none());
+            assert!(config.from_date.is_none());
+            assert!(config.to_date.is_none());
+            assert!(!config.enable_images);
+            assert!(!config.enable_video);
+        }
+
+        #[test]
+        fn test_normalize_handles() {
+            let handles = vec!["@foo".to_string(), " bar ".to_string(), "".to_string()];
+            assert_eq!(normalize_handles(&handles), vec!["foo", "bar"]);
+            assert_eq!(
+                normalize_handles(&[" @ ".to_string()]),
+                Vec::<String>::new()
+            );
+        }
+
+        #[test]
+        fn test_config_with_handles() {
+            let config = XSearchConfig {
+                allowed_handles: Some(vec!["user1".to_string(), "user2".to_string()]),
+                excluded_handles: None,
+                from_date: None,
+                to_date: None,
+                enable_images: false,
+                enable_video: false,
+            };
+
+            assert_eq!(
+                config.allowed_handles,
+                Some(vec!["user1".to_string(), "user2".to_string()])
+            );
+        }
+
+        #[test]
+        fn test_config_with_dates() {
+            let config = XSearchConfig {
+                allowed_handles: None,
+                excluded_handles: None,
+                from_date: Some("2025-01-01".to_string()),
+                to_date: Some("2025-01-15".to_string()),
+                enable_images: false,
+                enable_video: false,
+            };
+
+            assert_eq!(config.from_date, Some("2025-01-01".to_string()));
+            assert_eq!(config.to_date, Some("2025-01-15".to_string()));
+        }
+
+        #[test]
+        fn test_config_with_media() {
+            let config = XSearchConfig {
+                allowed_handles: None,
+                excluded_handles: None,
+                from_date: None,
+                to_date: None,
+                enable_images: true,
+                enable_video: true,
+            };
+
+            assert!(config.enable_images);
+            assert!(config.enable_video);
+        }
+
+        #[test]
+        fn test_valid_x_handles() {
+            assert!(is_valid_x_handle("elonmusk"));
+            assert!(is_valid_x_handle("x_AI_2"));
+            assert!(is_valid_x_handle("a"));
+        }
+
+        #[test]
+        fn test_too_long_x_handle() {
+            assert!(is_valid_x_handle("fifteen_chars_x"));
+            assert!(!is_valid_x_handle("sixteen_chars_xx"));
+            assert!(!is_valid_x_handle(""));
+        }
+
+        #[test]
+        fn test_illegal_char_x_handle() {
+            assert!(!is_valid_x_handle("@elonmusk"));
+            assert!(!is_valid_x_handle("bad-handle"));
+            assert!(!is_valid_x_handle("has space"));
+            assert!(!is_valid_x_handle("émile"));
+        }
+    }
+
+    // Test layered system instructions
+    mod system_layers {
+        use super::*;
+
+        #[test]
+        fn test_all_layers_in_order() {
+            let layers = SystemLayers {
+                preset: Some("Be concise."),
+                replace: None,
+                append: Some("You are a pirate."),
+                target_words: None,
+                lang: Some("French"),
+            };
+            assert_eq!(
+                build_system_instruction(&layers).unwrap(),
+                "Be concise.\n\nYou are a pirate.\n\nRespond in French."
+            );
+        }
+
+        #[test]
+        fn test_partial_layers() {
+            let layers = SystemLayers {
+                preset: None,
+                replace: None,
+                append: Some("You are a pirate."),
+                target_words: None,
+                lang: None,
+            };
+            assert_eq!(
+                build_system_instruction(&layers).unwrap(),
+                "You are a pirate."
+            );
+
+            let layers = SystemLayers {
+                preset: Some("Be concise."),
+                replace: None,
+                append: None,
+                target_words: None,
+                lang: Some("German"),
+            };
+            assert_eq!(
+                build_system_instruction(&layers).unwrap(),
+                "Be concise.\n\nRespond in German."
+            );
+
+            let layers = SystemLayers {
+                preset: Some("Be concise."),
+                replace: None,
+                append: None,
+                target_words: Some(150),
+                lang: Some("German"),
+            };
+            assert_eq!(
+                build_system_instruction(&layers).unwrap(),
+                "Be concise.\n\nAim for about 150 words.\n\nRespond in German."
+            );
+        }
+
+        #[test]
+        fn test_replace_drops_only_the_preset() {
+            let layers = SystemLayers {
+                preset: Some("Be concise."),
+                replace: Some("You are a pirate."),
+                append: Some("Say arr."),
+                target_words: None,
+                lang: Some("French"),
+            };
+            assert_eq!(
+                build_system_instruction(&layers).unwrap(),
+                "You are a pirate.\n\nSay arr.\n\nRespond in French."
+            );
+        }
+
+        #[test]
+        fn test_no_layers() {
+            assert!(build_system_instruction(&SystemLayers::default()).is_none());
+
+            let blank = SystemLayers {
+                preset: None,
+                replace: None,
+                append: Some("  "),
+                target_words: None,
+                lang: Some(""),
+            };
+            assert!(build_system_instruction(&blank).is_none());
+        }
+    }
+
+    // Test adaptive max_output_tokens
+    mod adaptive_tokens {
+        use super::*;
+
+        #[test]
+        fn test_short_query_gets_minimum_budget() {
+            assert_eq!(
+                adaptive_token_budget("What is xAI?", false),
+                ADAPTIVE_MIN_TOKENS
+            );
+            assert_eq!(adaptive_token_budget("", false), ADAPTIVE_MIN_TOKENS);
+        }
+
+        #[test]
+        fn test_reasoning_gets_a_higher_floor() {
+            assert_eq!(
+                adaptive_token_budget("What is xAI?", true),
+                ADAPTIVE_REASONING_MIN_TOKENS
+            );
+            let huge = "x".repeat(1_000_000);
+            assert_eq!(adaptive_token_budget(&huge, true), ADAPTIVE_MAX_TOKENS);
+        }
+
+        #[test]
+        fn test_longer_query_gets_larger_budget() {
+            let medium = "word ".repeat(100);
+            assert_eq!(adaptive_token_budget(&medium, false), 2048);
+
+            let long = "word ".repeat(600);
+            assert_eq!(adaptive_token_budget(&long, false), 8192);
+        }
+
+        #[test]
+        fn test_very_long_query_is_capped() {
+            let huge = "x".repeat(1_000_000);
+            assert_eq!(adaptive_token_budget(&huge, false), ADAPTIVE_MAX_TOKENS);
+        }
+
+        #[test]
+        fn test_input_token_estimate() {
+            let options = RequestOptions {
+                system_instruction: Some("x".repeat(40)),
+                ..Default::default()
+            };
+            let estimate = estimate_input_tokens(&"y".repeat(101), &options);
+            assert_eq!(estimate.system_tokens, 10);
+            assert_eq!(estimate.prior_message_tokens, 0);
+            assert_eq!(estimate.query_tokens, 26);
+            assert_eq!(estimate.total_tokens, 36);
+
+            let text = format_token_estimate(&estimate);
+            assert!(text.starts_with("Estimated input tokens: ~36\n  system instruction: ~10\n"));
+            assert!(!text.contains("priming"));
+            assert!(text.contains("Method: about 4 characters per token"));
+        }
+    }
+
+    // Test follow-up suggestions
+    mod followups {
+        use super::*;
+
+        #[test]
+        fn test_followup_prompt_asks_for_three_questions() {
+            assert!(SUGGEST_FOLLOWUPS_PROMPT.contains("3 related questions"));
+            assert!(SUGGEST_FOLLOWUPS_PROMPT.contains("numbered list"));
+        }
+
+        #[test]
+        fn test_parse_question_list() {
+            let text = "1. What is Grok?\n2) Who built it?\n\n- How is it priced?\n4. Extra?";
+            let questions = parse_question_list(text, 3);
+            assert_eq!(
+                questions,
+                vec!["What is Grok?", "Who built it?", "How is it priced?"]
+            );
+        }
+
+        #[test]
+        fn test_format_followups() {
+            let output = format_followups(&["A?".to_string(), "B?".to_string()]);
+            assert_eq!(output, "Related questions:\n1. A?\n2. B?\n");
+        }
+    }
+
+    // Integration tests with mocked HTTP
+    mod integration {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_api_request_format() {
+            // This test verifies the request structure without making real API calls
+            let messages = vec![
+                Message {
+                    role: "system".to_string(),
+                    content: "Be concise.".into(),
+                },
+                Message {
+                    role: "user".to_string(),
+                    content: "test query".into(),
+                },
+            ];
+
+            let request = GrokRequest {
+                model: MODEL.to_string(),
+                input: messages,
+                store: true,
+                max_output_tokens: Some(8192),
+                temperature: None,
+                reasoning_effort: None,
+                previous_response_id: None,
+                prompt_cache_key: None,
+                tools: vec![
+                    Tool::WebSearch(WebSearchTool {
+                        r#type: "web_search".to_string(),
+                        enable_image_understanding: None,
+                        country: None,
+                        max_search_results: None,
+                    }),
+                    Tool::XSearch(XSearchTool {
+                        r#type: "x_search".to_string(),
+                        allowed_x_handles: Some(vec!["elonmusk".to_string()]),
+                        excluded_x_handles: None,
+                        from_date: Some("2025-01-01".to_string()),
+                        to_date: None,
+                        enable_image_understanding: None,
+                        enable_video_understanding: None,
+                    }),
+                ],
+                tool_choice: None,
+                max_tool_calls: None,
+                background: None,
+                stream: None,
+                text: None,
+            };
+
+            let json = serde_json::to_string_pretty(&request).unwrap();
+
+            // Verify structure
+            assert!(json.contains("grok-4-1-fast-non-reasoning"));
+            assert!(json.contains("Be concise."));
+            assert!(json.contains("test query"));
+            assert!(json.contains("web_search"));
+            assert!(json.contains("x_search"));
+            assert!(json.contains("elonmusk"));
+            assert!(json.contains("2025-01-01"));
+        }
+
+        #[tokio::test]
+        async fn test_response_parsing() {
+            let json = r#"{
+                "id": "resp_test",
+                "status": "completed",
+                "output": [
+                    {
+                        "type": "x_search_result",
+                        "results": [
+                            {"url": "https://x.com/u/1", "title": "@user"}
+                        ]
+                    },
+                    {
+                        "type": "message",
+                        "content": [
+                            {"type": "output_text", "text": "Test response."}
+                        ]
+                    }
+                ],
+                "usage": {"input_tokens": 10, "output_tokens": 20}
+            }"#;
+
+            let response: GrokResponse = serde_json::from_str(json).unwrap();
+
+            assert_eq!(response.id, Some("resp_test".to_string()));
+            assert_eq!(response.status, Some("completed".to_string()));
+
+            let output = format_response(&response, &OutputFormat::Text);
+            assert!(output.contains("Test response."));
+            assert!(output.contains("[@user](https://x.com/u/1)"));
+        }
+
+        #[tokio::test]
+        async fn test_ask_assembles_answer() {
+            let mut server = mockito::Server::new_async().await;
+            let body = serde_json::json!({
+                "id": "resp_ask",
+                "status": "completed",
+                "output": [
+                    {
+                        "type": "message",
+                        "content": [{
+                            "type": "output_text",
+                            "text": "xAI builds Grok.",
+                            "annotations": [{"url": "https://x.ai", "title": "xAI"}]
+                        }]
+                    },
+                    {
+                        "type": "web_search_result",
+                        "results": [{"url": "https://x.ai", "title": "Duplicate"}]
+                    }
+                ],
+                "usage": {"input_tokens": 12, "output_tokens": 34}
+            });
+            let mock = server
+                .mock("POST", "/v1/responses")
+                .match_header("authorization", "Bearer test-key")
+                .match_body(mockito::Matcher::PartialJson(
+                    serde_json::json!({"input": [{"role": "user", "content": "What is xAI?"}]}),
+                ))
+                .with_header("content-type", "application/json")
+                .with_body(body.to_string())
+                .expect(1)
+                .create_async()
+                .await;
+
+            let options = RequestOptions {
+                endpoint: Some(server.url()),
+                api_key: Some("test-key".to_string()),
+                retry: RetryPolicy::none(),
+                ..Default::default()
+            };
+            let answer = ask(&reqwest::Client::new(), "What is xAI?", &options)
+                .await
+                .unwrap();
+            mock.assert_async().await;
+
+            assert_eq!(answer.text, "xAI builds Grok.");
+            assert_eq!(
+                answer.sources,
+                vec![Source {
+                    title: "xAI".to_string(),
+                    url: "https://x.ai".to_string(),
+                }]
+            );
+            assert_eq!(answer.response_id.as_deref(), Some("resp_ask"));
+            assert_eq!(answer.usage.unwrap().output_tokens, Some(34));
+        }
+
+        #[test]
+        fn test_effective_timeout() {
+            assert_eq!(effective_timeout(None), Some(DEFAULT_TIMEOUT));
+            assert_eq!(effective_timeout(Some(Duration::ZERO)), None);
+            assert_eq!(
+                effective_timeout(Some(Duration::from_secs(5))),
+                Some(Duration::from_secs(5))
+            );
+        }
+
+        #[tokio::test]
+        async fn test_timeout_reported_distinctly() {
+            let mut server = mockito::Server::new_async().await;
+            let _mock = server
+                .mock("GET", "/slow")
+                .with_chunked_body(|w| {
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                    w.write_all(b"{}")
+                })
+                .create_async()
+                .await;
+
+            let timeout = Duration::from_millis(50);
+            let result = async {
+                reqwest::Client::new()
+                    .get(format!("{}/slow", server.url()))
+                    .timeout(timeout)
+                    .send()
+                    .await?
+                    .text()
+                    .await
+            }
+            .await;
+            let message = request_error(
+                result.unwrap_err(),
+                Some(timeout),
+                "Failed to read response",
+            )
+            .to_string();
+            assert!(message.contains("timed out"));
+            assert!(message.contains("--timeout 0"));
+        }
+
+        #[test]
+        fn test_endpoint_override_forms() {
+            assert_eq!(
+                responses_url("https://gw.example.com"),
+                "https://gw.example.com/v1/responses"
+            );
+            assert_eq!(
+                responses_url("https://gw.example.com/xai/v1/"),
+                "https://gw.example.com/xai/v1/responses"
+            );
+            assert_eq!(
+                responses_url("https://gw.example.com/custom/responses"),
+                "https://gw.example.com/custom/responses"
+            );
+            assert_eq!(
+                endpoint_url(Some("http://localhost:8080")),
+                "http://localhost:8080/v1/responses"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_in_progress_response_is_polled() {
+            let mut server = mockito::Server::new_async().await;
+            let _create = server
+                .mock("POST", "/v1/responses")
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"id": "resp_slow", "status": "in_progress"}"#)
+                .expect(2)
+                .create_async()
+                .await;
+            let poll = server
+                .mock("GET", "/v1/responses/resp_slow")
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"id": "resp_slow", "status": "completed", "output": []}"#)
+                .expect(1)
+                .create_async()
+                .await;
+
+            let options = RequestOptions {
+                endpoint: Some(server.url()),
+                api_key: Some("test-key".to_string()),
+                retry: RetryPolicy::none(),
+                ..Default::default()
+            };
+            let client = reqwest::Client::new();
+            let done = send_request(&client, "hi", &options).await.unwrap();
+            assert_eq!(done.status.as_deref(), Some("completed"));
+
+            let options = RequestOptions {
+                no_poll: true,
+                ..options
+            };
+            let pending = send_request(&client, "hi", &options).await.unwrap();
+            assert_eq!(pending.status.as_deref(), Some("in_progress"));
+            poll.assert_async().await;
+        }
+
+        #[tokio::test]
+        async fn test_polling_gives_up_after_max_wait() {
+            let mut server = mockito::Server::new_async().await;
+            let _poll = server
+                .mock("GET", "/v1/responses/resp_stuck")
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"id": "resp_stuck", "status": "in_progress"}"#)
+                .expect_at_least(2)
+                .create_async()
+                .await;
+
+            let options = RequestOptions {
+                endpoint: Some(server.url()),
+                api_key: Some("test-key".to_string()),
+                ..Default::default()
+            };
+            let interval = Duration::from_millis(10);
+            let err = poll_response("resp_stuck", &options, interval, Duration::from_millis(30))
+                .await
+                .unwrap_err()
+                .to_string();
+            assert!(err.starts_with("Gave up waiting for resp_stuck after 0s"));
+            assert!(err.contains("(status: in_progress)"));
+            assert!(err.ends_with("grok-ask get resp_stuck"));
+        }
+
+        #[tokio::test]
+        async fn test_retrieve_reports_error_pages_as_api_errors() {
+            let mut server = mockito::Server::new_async().await;
+            let _get = server
+                .mock("GET", "/v1/responses/resp_gone")
+                .with_status(502)
+                .with_body("<html>Bad Gateway</html>")
+                .create_async()
+                .await;
+
+            let options = RequestOptions {
+                endpoint: Some(server.url()),
+                api_key: Some("test-key".to_string()),
+                ..Default::default()
+            };
+            let err = retrieve_response("resp_gone", &options).await.unwrap_err();
+            assert!(is_api_error(&err));
+            assert_eq!(
+                err.to_string(),
+                "API returned 502 Bad Gateway: <html>Bad Gateway</html>"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_show_headers_keeps_rate_limits_in_meta() {
+            let mut server = mockito::Server::new_async().await;
+            let _create = server
+                .mock("POST", "/v1/responses")
+                .with_header("content-type", "application/json")
+                .with_header("x-ratelimit-remaining-requests", "41")
+                .with_body(r#"{"id": "resp_meta", "status": "completed", "output": []}"#)
+                .expect(2)
+                .create_async()
+                .await;
+
+            let options = RequestOptions {
+                endpoint: Some(server.url()),
+                api_key: Some("test-key".to_string()),
+                retry: RetryPolicy::none(),
+                ..Default::default()
+            };
+            let client = reqwest::Client::new();
+            let plain = send_request(&client, "hi", &options).await.unwrap();
+            assert!(plain.meta.is_none());
+
+            let options = RequestOptions {
+                show_headers: true,
+                ..options
+            };
+            let response = send_request(&client, "hi", &options).await.unwrap();
+            let json = serde_json::to_value(&response).unwrap();
+            assert_eq!(json["_meta"]["status"], 200);
+            assert_eq!(
+                json["_meta"]["headers"]["x-ratelimit-remaining-requests"],
+                "41"
+            );
+            assert!(json["_meta"]["headers"].get("content-type").is_none());
+        }
+
+        #[tokio::test]
+        async fn test_delivery_is_reported_by_the_caller() {
+            let mut server = mockito::Server::new_async().await;
+            let _create = server
+                .mock("POST", "/v1/responses")
+                .with_header("content-type", "application/json")
+                .with_header("x-ratelimit-remaining-requests", "41")
+                .with_body(r#"{"id": "resp_quiet", "status": "completed", "output": []}"#)
+                .create_async()
+                .await;
+
+            let options = RequestOptions {
+                endpoint: Some(server.url()),
+                api_key: Some("test-key".to_string()),
+                retry: RetryPolicy::none(),
+                verbose: true,
+                ..Default::default()
+            };
+            let (response, delivery) = deliver_request(&reqwest::Client::new(), "hi", &options)
+                .await
+                .unwrap();
+            // Verbose captures headers without keeping them in the response
+            assert!(response.meta.is_none());
+            assert_eq!(delivery.headers.as_ref().unwrap().status, 200);
+            assert!(delivery.elapsed.is_some());
+            assert!(delivery.recorded.is_none());
+
+            let report = delivery.describe(false);
+            assert!(report.starts_with("Request completed in "));
+            assert!(report.contains("x-ratelimit-remaining-requests: 41"));
+            assert!(!delivery.describe(true).contains("Request completed"));
+        }
+
+        #[tokio::test]
+        async fn test_sent_requests_are_audited() {
+            let mut server = mockito::Server::new_async().await;
+            let _create = server
+                .mock("POST", "/v1/responses")
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"id": "resp_log", "status": "completed", "output": []}"#)
+                .create_async()
+                .await;
+
+            let dir = std::env::temp_dir().join("grok-ask-send-audit-test");
+            let _ = std::fs::remove_dir_all(&dir);
+            let path = dir.join("audit.jsonl");
+            let options = RequestOptions {
+                endpoint: Some(server.url()),
+                api_key: Some("test-key".to_string()),
+                retry: RetryPolicy::none(),
+                audit_log: Some(audit::AuditLog {
+                    path: path.clone(),
+                    command: "compare".to_string(),
+                }),
+                ..Default::default()
+            };
+            send_request(&reqwest::Client::new(), "hi", &options)
+                .await
+                .unwrap();
+
+            let line: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            assert_eq!(line["command"], "compare");
+            assert_eq!(line["query"], "hi");
+            assert_eq!(line["response_id"], "resp_log");
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn test_verbose_log_redacts_key() {
+            let options = RequestOptions {
+                use_web_search: true,
+                max_tokens: 100,
+                ..Default::default()
+            };
+            let request = build_request("what's new?", &options);
+            let (name, value) = auth_header(AuthScheme::Bearer, None, "secret-key");
+            let log = describe_request(API_ENDPOINT, &name, &request);
+
+            assert!(log.starts_with("POST https://api.x.ai/v1/responses\nModel: "));
+            assert!(log.contains("Authorization: [REDACTED]"));
+            assert!(log.contains("\"web_search\""));
+            assert!(log.contains("what's new?"));
+            assert!(!log.contains("secret-key"));
+            assert!(!log.contains(&value));
+        }
+
+        #[test]
+        fn test_auth_header_schemes() {
+            let header = |scheme, name| auth_header(scheme, name, "k3y");
+            assert_eq!(
+                header(AuthScheme::Bearer, None),
+                ("Authorization".to_string(), "Bearer k3y".to_string())
+            );
+            assert_eq!(
+                header(AuthScheme::Token, None),
+                ("Authorization".to_string(), "Token k3y".to_string())
+            );
+            assert_eq!(
+                header(AuthScheme::Raw, Some("X-Api-Key")),
+                ("X-Api-Key".to_string(), "k3y".to_string())
+            );
+        }
+
+        #[tokio::test]
+        async fn test_request_sent_to_endpoint_override() {
+            let mut server = mockito::Server::new_async().await;
+            let mock = server
+                .mock("POST", "/v1/responses")
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"id": "resp_gw", "status": "completed", "output": []}"#)
+                .create_async()
+                .await;
+
+            let options = RequestOptions {
+                endpoint: Some(server.url()),
+                api_key: Some("test-key".to_string()),
+                retry: RetryPolicy::none(),
+                ..Default::default()
+            };
+            let response = send_request(&reqwest::Client::new(), "hi", &options)
+                .await
+                .unwrap();
+
+            mock.assert_async().await;
+            assert_eq!(response.id.as_deref(), Some("resp_gw"));
+        }
+
+        #[test]
+        fn test_answer_from_error_response() {
+            let response: GrokResponse = serde_json::from_str(
+                r#"{"id": "r", "status": "failed", "error": {"message": "bad key"}}"#,
+            )
+            .unwrap();
+            let err = Answer::from_response(&response).unwrap_err().to_string();
+            assert!(err.contains("bad key"));
+        }
+    }
+}
@@ -1,1059 +1,4652 @@
-use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use serde::{Deserialize, Serialize};
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use grok_ask::audit::{AuditLog, LOG_FILE_ENV};
+use grok_ask::baseline::SourceBaseline;
+use grok_ask::batch::{
+    batch_json, batch_text, estimate_batch, format_batch_estimate, parse_batch, run_batch,
+};
+use grok_ask::cache::{exact_key, semantic_key, ResponseCache};
+use grok_ask::color::color_enabled;
+use grok_ask::compare::{build_judge_prompt, format_candidate};
+use grok_ask::config::Config;
+use grok_ask::cost::{estimate_cost, token_cost, CostBudget, Pricing};
+use grok_ask::dates::{
+    apply_since_days, parse_date_as, validate_date_range, widen_window, Date, DateFormat,
+};
+use grok_ask::export::{render_html, render_primed, render_text, TranscriptTurn};
+use grok_ask::image::load_image;
+use grok_ask::interrupt::{collect_until_interrupt, StreamOutcome, TurnEvent, TurnState};
+use grok_ask::manifest::Manifest;
+use grok_ask::metrics::BatchMetrics;
+use grok_ask::paths::StatePaths;
+use grok_ask::priming::{load_messages, parse_example, PriorMessage};
+use grok_ask::repl::{self, ReplInput, ReplState};
+use grok_ask::retry::RetryPolicy;
+use grok_ask::schema::JsonSchema;
+use grok_ask::sections::split_by_headings;
+use grok_ask::serve::serve;
+use grok_ask::spinner::spinner_enabled;
+use grok_ask::ssml::to_ssml;
+use grok_ask::stream::send_streaming;
+use grok_ask::thread::{thread_path, Thread, ThreadUsage};
+use grok_ask::tool_context::{tool_context, ToolContexts};
+use grok_ask::wrap::{longest_line, wrap_text};
+use grok_ask::{
+    adaptive_token_budget, build_client, build_request, build_system_instruction, citations_json,
+    collect_sources, deliver_request, estimate_input_tokens, extract_answer_text,
+    format_background_started, format_followups, format_response_with, format_token_estimate,
+    is_api_error, is_empty_response, is_network_error, is_valid_x_handle, normalize_handles,
+    parse_question_list, poll_response, retrieve_response, x_result_count, AuthScheme,
+    FormatOptions, GrokResponse, OutputFormat, RequestOptions, SourceFilter, SystemLayers,
+    ToolChoice, Usage, XSearchConfig, MODEL, REASONING_MODEL, SUGGEST_FOLLOWUPS_PROMPT,
+};
+use regex::Regex;
 use std::env;
-use std::time::Instant;
-
-const API_ENDPOINT: &str = "https://api.x.ai/v1/responses";
-const MODEL: &str = "grok-4-1-fast-non-reasoning";
-const REASONING_MODEL: &str = "grok-4-1-fast";
+use std::ffi::OsString;
+use std::fs;
+use std::future::Future;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncBufReadExt;
+use tokio::sync::mpsc;
 
 #[derive(Parser)]
 #[command(name = "grok-ask")]
 #[command(about = "CLI for xAI Grok API with web and X search", long_about = None)]
+#[command(after_help = EXIT_CODES_HELP)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
+    /// Query to run with the config file's default_command (e.g. default_command = "ask")
+    #[arg(value_name = "QUERY", conflicts_with_all = [
+        "search", "ask", "think", "chat", "x_search", "x_ask", "x_think", "prompt_file",
+    ])]
+    query: Option<String>,
+
+    /// The mode for a bare QUERY, looked up from the config file at startup
+    #[arg(skip)]
+    default_mode: Option<Mode>,
+
     /// Search query (shorthand for search command)
-    #[arg(long)]
+    #[arg(long, conflicts_with = "prompt_file")]
     search: Option<String>,
 
     /// Ask query (shorthand for ask command)
-    #[arg(long)]
+    #[arg(long, conflicts_with = "prompt_file")]
     ask: Option<String>,
 
     /// Think query (shorthand for think command)
-    #[arg(long)]
+    #[arg(long, conflicts_with = "prompt_file")]
     think: Option<String>,
 
     /// Chat query without web search
-    #[arg(long)]
+    #[arg(long, conflicts_with = "prompt_file")]
     chat: Option<String>,
 
+    /// Let this --chat turn search the web
+    #[arg(long, requires = "chat")]
+    web: bool,
+
+    /// Answer this --ask query without searching the web
+    #[arg(long, requires = "ask")]
+    no_web: bool,
+
     /// X search query (shorthand for x-search command)
-    #[arg(long)]
+    #[arg(long, conflicts_with = "prompt_file")]
     x_search: Option<String>,
 
     /// X ask query (shorthand for x-ask command)
-    #[arg(long)]
+    #[arg(long, conflicts_with = "prompt_file")]
     x_ask: Option<String>,
 
+    /// X think query (shorthand for x-think command)
+    #[arg(long, conflicts_with = "prompt_file")]
+    x_think: Option<String>,
+
     /// Previous response ID for follow-up
     #[arg(short = 'r', long)]
     response_id: Option<String>,
 
-    /// Only include posts from these X handles (comma-separated, without @)
-    #[arg(long, value_delimiter = ',')]
-    allowed_handles: Option<Vec<String>>,
+    /// Once the query is answered, ask this as a follow-up to it and print only
+    /// the follow-up's answer (both with --verbose)
+    #[arg(long, global = true, value_name = "QUERY")]
+    then: Option<String>,
 
-    /// Exclude posts from these X handles (comma-separated, without @)
-    #[arg(long, value_delimiter = ',')]
-    excluded_handles: Option<Vec<String>>,
+    #[command(flatten)]
+    x: XSearchArgs,
 
-    /// Start date for X search (YYYY-MM-DD)
-    #[arg(long)]
-    from_date: Option<String>,
+    /// Output format; with --output-file, inferred from its extension (.json, .md) when not given
+    #[arg(short, long, global = true, default_value = "text")]
+    output: OutputFormat,
 
-    /// End date for X search (YYYY-MM-DD)
-    #[arg(long)]
-    to_date: Option<String>,
+    /// With -o json, print each JSON document on a single line
+    #[arg(long, global = true)]
+    compact: bool,
 
-    /// Enable image understanding for X search
-    #[arg(long)]
-    enable_images: bool,
+    /// Print only the answer and sources: no timing line on stderr and no footer
+    #[arg(short, long, global = true)]
+    quiet: bool,
 
-    /// Enable video understanding for X search
-    #[arg(long)]
-    enable_video: bool,
+    /// Write the formatted output to this file instead of stdout
+    #[arg(long, global = true, value_name = "PATH")]
+    output_file: Option<PathBuf>,
 
-    /// Output format
-    #[arg(short, long, default_value = "text")]
-    output: OutputFormat,
+    /// System instruction to use instead of the command's built-in one (chat has
+    /// none, so there it simply adds one)
+    #[arg(long, global = true, value_name = "TEXT")]
+    system: Option<String>,
+
+    /// Ask for JSON matching this JSON schema file, and fail unless the answer conforms
+    #[arg(long, global = true, value_name = "FILE", value_parser = parse_schema_file, conflicts_with_all = [
+        "ssml", "split_by_headings", "citations_json", "stream",
+    ])]
+    schema: Option<JsonSchema>,
+
+    /// Like --system, but reads the instruction from a file
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "system", value_parser = read_text_file)]
+    system_file: Option<String>,
+
+    /// Read the query from this UTF-8 file instead of the command line
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        value_parser = read_text_file
+    )]
+    prompt_file: Option<String>,
+
+    /// A worked example sent before the query, as USER::ASSISTANT; repeatable
+    #[arg(long, global = true, value_name = "USER::ASSISTANT", value_parser = parse_example)]
+    example: Vec<[PriorMessage; 2]>,
+
+    /// Send the messages in this JSON array of {"role", "content"} objects
+    /// (system, user or assistant) before the query, ahead of any --example
+    #[arg(long, global = true, value_name = "PATH")]
+    messages_file: Option<PathBuf>,
+
+    /// Extra system guidance, added after the command's built-in instruction (or --system)
+    #[arg(long, global = true, value_name = "TEXT")]
+    append_system: Option<String>,
+
+    /// Hint the provider to cache the system instruction, cutting the cost of
+    /// many queries that share it
+    #[arg(long, global = true)]
+    cache_prompt: bool,
+
+    /// Language to answer in (e.g. French), layered last in the system message
+    #[arg(long, global = true)]
+    lang: Option<String>,
+
+    /// Turn input warnings (like piped stdin being ignored) into errors
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Ask for an answer of about this many words, and warn if it's more than 25% off
+    #[arg(long, global = true, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    target_words: Option<usize>,
+
+    /// Whether the model may (auto), must (required), or must not (none) use its search tools
+    #[arg(long, global = true, default_value = "auto")]
+    tool_choice: ToolChoice,
+
+    /// Attach an image (PNG, JPEG, GIF, or WebP) to the query; - reads it from stdin
+    #[arg(long, global = true, value_name = "PATH")]
+    image: Option<String>,
+
+    /// Attach an image by URL (https://...) instead of uploading a file
+    #[arg(long, global = true, value_name = "URL", conflicts_with = "image", value_parser = parse_image_url)]
+    image_url: Option<String>,
+
+    /// Let the model make at most N searches (or other tool calls), to bound cost
+    #[arg(long, global = true, value_name = "N")]
+    max_tool_calls: Option<u32>,
+
+    /// Localize web search results to this country (ISO 3166 two-letter code, e.g. DE)
+    #[arg(long, global = true, value_name = "CC", value_parser = parse_country)]
+    country: Option<String>,
+
+    /// How to read --from-date/--to-date; by default ISO, MM/DD/YYYY and DD.MM.YYYY are all accepted
+    #[arg(long, global = true)]
+    date_format: Option<DateFormat>,
+
+    /// Check that X handle filters are valid usernames before searching
+    #[arg(long, global = true)]
+    verify_handles: bool,
+
+    /// Don't scan the response for sources at all (faster on large result sets)
+    #[arg(long, global = true)]
+    skip_sources: bool,
+
+    /// Which kinds of source to list after the answer
+    #[arg(long, global = true, value_enum, default_value_t = SourceFilter::All)]
+    sources: SourceFilter,
+
+    /// Reuse cached search/ask answers for queries that match after normalizing case and whitespace (not for follow-ups or image queries)
+    #[arg(long, global = true)]
+    semantic_cache: bool,
+
+    /// Reuse the stored answer when the exact same request (query, model, instruction, tools, response id and settings) was sent before
+    #[arg(long, global = true, conflicts_with_all = ["semantic_cache", "background"])]
+    cache: bool,
+
+    /// How long --cache answers stay fresh: seconds, or a number with s, m, h or d
+    #[arg(long, global = true, value_name = "AGE", default_value = "1d", value_parser = parse_ttl, requires = "cache")]
+    cache_ttl: Duration,
+
+    /// Only list sources not already in this file, then add the new ones to it
+    #[arg(long, global = true, value_name = "PATH")]
+    baseline_sources: Option<PathBuf>,
+
+    /// Queue the request and return its id immediately; collect it later with `get`
+    #[arg(long, global = true)]
+    background: bool,
+
+    /// Don't let the API keep the response (no follow-ups by response id)
+    #[arg(long, global = true, conflicts_with = "background")]
+    no_store: bool,
+
+    /// Print the answer as it arrives, and each source on stderr as soon as it's confirmed
+    #[arg(long, global = true, conflicts_with_all = [
+        "background", "cache", "semantic_cache", "ssml", "split_by_headings", "citations_json",
+        "inline_citations", "show_reasoning", "show_searches", "wrap", "dry_run",
+    ])]
+    stream: bool,
+
+    /// Model to use; overrides the command's default (think and x-think use the
+    /// reasoning model, other commands the fast model) and --no-reasoning
+    #[arg(long, global = true, value_name = "NAME")]
+    model: Option<String>,
+
+    /// How hard the reasoning model thinks (think and x-think only)
+    #[arg(long, global = true, value_parser = ["low", "medium", "high"])]
+    effort: Option<String>,
+
+    /// Sampling temperature, from 0.0 (focused) to 2.0 (varied)
+    #[arg(long, global = true, value_parser = parse_temperature)]
+    temperature: Option<f32>,
+
+    /// Print only the answer, as SSML for a speech synthesizer (no sources or footer)
+    #[arg(long, global = true)]
+    ssml: bool,
+
+    /// Print only the answer, as a JSON object mapping each `##` heading to its section
+    #[arg(long, global = true, conflicts_with = "ssml")]
+    split_by_headings: bool,
+
+    /// Print only the sources, as a JSON array of {title, url} (no answer text)
+    #[arg(long, global = true, conflicts_with_all = ["ssml", "split_by_headings"])]
+    citations_json: bool,
+
+    /// Add the estimated USD cost to the footer (rates from GROK_INPUT_COST / GROK_OUTPUT_COST, per million tokens)
+    #[arg(long, global = true)]
+    show_cost: bool,
+
+    /// Add why generation stopped (e.g. length, content_filter) to the footer
+    #[arg(long, global = true)]
+    show_finish_reason: bool,
+
+    /// Sort sources by normalized URL so identical answers print byte-identically
+    /// (text and markdown only; `-o json` keeps the API's order)
+    #[arg(long, global = true)]
+    stable_sources: bool,
+
+    /// Never color text output (also disabled by NO_COLOR, or when not writing to a terminal)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Mark cited claims in the answer with [n], numbered as in the Sources list
+    #[arg(long, global = true)]
+    inline_citations: bool,
+
+    /// Print the reasoning model's thinking before its answer (text output)
+    #[arg(long, global = true)]
+    show_reasoning: bool,
+
+    /// List the searches the model ran (e.g. Searched: 'climate policy 2025') before the answer
+    #[arg(long, global = true)]
+    show_searches: bool,
+
+    /// Retries on rate limits (429) and transient server errors (5xx), with exponential backoff
+    #[arg(long, global = true, value_name = "N", default_value = "3")]
+    max_retries: u32,
+
+    /// HTTP statuses that trigger a retry (comma-separated)
+    #[arg(long, global = true, value_name = "CODES", value_delimiter = ',', value_parser = clap::value_parser!(u16).range(100..600))]
+    retry_codes: Option<Vec<u16>>,
+
+    /// Seconds to wait for each HTTP request before giving up (0 waits indefinitely)
+    #[arg(long, global = true, value_name = "SECONDS", default_value = "120")]
+    timeout: u64,
+
+    /// API URL: a base like https://gateway.example.com or a full .../responses URL (default from XAI_BASE_URL)
+    #[arg(long, global = true, value_name = "URL")]
+    endpoint: Option<String>,
+
+    /// How to present the API key: bearer (Authorization: Bearer KEY), token (Token KEY), or raw (KEY)
+    #[arg(long, global = true, default_value = "bearer")]
+    auth_scheme: AuthScheme,
+
+    /// Send the API key in this header instead of Authorization
+    #[arg(long, global = true, value_name = "NAME")]
+    auth_header: Option<String>,
+
+    /// Print the request JSON that would be sent, without sending it
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Log the request JSON, endpoint, and model, plus the raw response body, to stderr
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Print the HTTP status and rate-limit headers to stderr; with -o json, also
+    /// add them to the output as `_meta`
+    #[arg(long, global = true)]
+    show_headers: bool,
+
+    /// Label each part of a transcript with its role (User:, Assistant:); with
+    /// --messages-file or --example, the answer follows the labeled priming turns
+    #[arg(long, global = true)]
+    show_roles: bool,
+
+    /// Return an in-progress response immediately instead of waiting for it to finish
+    #[arg(long, global = true)]
+    no_poll: bool,
+
+    /// Fail on the first rate limit or server error instead of retrying
+    #[arg(long, global = true, conflicts_with = "max_retries")]
+    no_retry: bool,
+
+    /// Fail when fewer than this many distinct sources back the answer
+    #[arg(long, global = true, value_name = "N")]
+    min_source_count: Option<usize>,
+
+    /// Fail if the answer matches this regex (repeatable)
+    #[arg(long, global = true, value_name = "REGEX")]
+    forbid: Vec<Regex>,
+
+    /// Record the HTTP exchange to this file, or replay it if the file exists
+    /// (failing if it was recorded for a different request)
+    #[arg(long, global = true, value_name = "PATH")]
+    cassette: Option<PathBuf>,
+
+    /// Write a JSON manifest of the run (model, parameters, tools, query, response id) to this file
+    #[arg(long, global = true, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Append each query and answer as a JSON line to this audit log [env: GROK_LOG_FILE]
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Use the fast non-reasoning model even for think and x-think
+    #[arg(long, global = true)]
+    no_reasoning: bool,
+
+    /// Size max_output_tokens from the query length instead of the command default
+    #[arg(long, global = true)]
+    adaptive_tokens: bool,
+
+    /// Cap the answer at N output tokens instead of the command default (search 4096, ask/chat 8192, think 16384)
+    #[arg(long, global = true, value_name = "N", conflicts_with = "adaptive_tokens", value_parser = clap::value_parser!(u32).range(1..))]
+    max_tokens: Option<u32>,
+
+    /// Append a machine-readable `X-Response-Id: <id>` line to text output
+    #[arg(long, global = true)]
+    id_trailer: bool,
+
+    /// Hard-wrap text output at this many columns, including single-line answers
+    #[arg(long, global = true, value_name = "COLS")]
+    wrap: Option<usize>,
+
+    /// Warn on a terminal when a line is longer than this and --wrap is off
+    #[arg(long, global = true, value_name = "CHARS", default_value = "10000")]
+    long_line_warn: usize,
+
+    /// Treat each line of stdin as a separate query (use - as the query)
+    #[arg(long, global = true)]
+    lines: bool,
+
+    /// Stop a multi-query run before its estimated spend exceeds this many USD
+    #[arg(long, global = true, value_name = "USD")]
+    max_total_cost: Option<f64>,
+
+    /// After answering, ask for 3 related questions and list them under the answer (text output)
+    #[arg(long, global = true)]
+    suggest_followups: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Quick web search with minimal thinking
     Search {
-        query: String,
+        query: Option<String>,
         #[arg(long, default_value = "10")]
         max_results: u32,
+        /// Enable image understanding for web search
+        #[arg(long)]
+        enable_images: bool,
     },
     /// Get grounded answer with balanced reasoning
     Ask {
-        query: String,
+        query: Option<String>,
         #[arg(short = 'r', long)]
         response_id: Option<String>,
+        /// Answer without searching the web
+        #[arg(long)]
+        no_web: bool,
+        /// Enable image understanding for web search
+        #[arg(long, conflicts_with = "no_web")]
+        enable_images: bool,
     },
     /// Deep reasoning for complex problems
     Think {
-        query: String,
+        query: Option<String>,
         #[arg(short = 'r', long)]
         response_id: Option<String>,
     },
     /// Chat without web search
     Chat {
-        query: String,
+        query: Option<String>,
         #[arg(short = 'r', long)]
         response_id: Option<String>,
+        /// Search the web for this turn
+        #[arg(long)]
+        web: bool,
     },
     /// Search X (Twitter) posts
     XSearch {
-        query: String,
+        query: Option<String>,
         #[arg(long, default_value = "10")]
         max_results: u32,
-        /// Only include posts from these X handles (comma-separated, without @)
-        #[arg(long, value_delimiter = ',')]
-        allowed_handles: Option<Vec<String>>,
-        /// Exclude posts from these X handles (comma-separated, without @)
-        #[arg(long, value_delimiter = ',')]
-        excluded_handles: Option<Vec<String>>,
-        /// Start date (YYYY-MM-DD)
-        #[arg(long)]
-        from_date: Option<String>,
-        /// End date (YYYY-MM-DD)
-        #[arg(long)]
-        to_date: Option<String>,
-        /// Enable image understanding
-        #[arg(long)]
-        enable_images: bool,
-        /// Enable video understanding
-        #[arg(long)]
-        enable_video: bool,
+        /// Widen the date window and retry (up to 3 times) while fewer than N posts come back
+        #[arg(long, value_name = "N")]
+        min_results: Option<usize>,
+        /// Days added to each end of the date window per --min-results retry
+        #[arg(
+            long,
+            value_name = "DAYS",
+            default_value = "7",
+            requires = "min_results"
+        )]
+        widen_days: u32,
+        #[command(flatten)]
+        x: XSearchArgs,
     },
     /// Get grounded answers from X (Twitter) posts
     XAsk {
-        query: String,
+        query: Option<String>,
         #[arg(short = 'r', long)]
         response_id: Option<String>,
-        /// Only include posts from these X handles (comma-separated, without @)
-        #[arg(long, value_delimiter = ',')]
-        allowed_handles: Option<Vec<String>>,
-        /// Exclude posts from these X handles (comma-separated, without @)
-        #[arg(long, value_delimiter = ',')]
-        excluded_handles: Option<Vec<String>>,
-        /// Start date (YYYY-MM-DD)
-        #[arg(long)]
-        from_date: Option<String>,
-        /// End date (YYYY-MM-DD)
+        #[command(flatten)]
+        x: XSearchArgs,
+    },
+    /// Fetch a stored or background response, waiting until it completes
+    Get {
+        response_id: String,
+        /// Seconds between status checks
+        #[arg(long, default_value = "5")]
+        poll_interval: u64,
+        /// Give up if the response is still running after this many seconds
+        #[arg(long, default_value = "1800")]
+        max_wait: u64,
+    },
+    /// Deep reasoning grounded in X (Twitter) posts
+    XThink {
+        query: Option<String>,
+        #[arg(short = 'r', long)]
+        response_id: Option<String>,
+        #[command(flatten)]
+        x: XSearchArgs,
+    },
+    /// Ask two models the same question and show their answers side by side
+    Compare {
+        query: Option<String>,
+        /// The two models to compare (comma-separated)
+        #[arg(long, value_delimiter = ',', num_args = 1, default_values_t = [MODEL.to_string(), REASONING_MODEL.to_string()])]
+        models: Vec<String>,
+        /// Ask a judge model which answer is better, and why
         #[arg(long)]
-        to_date: Option<String>,
-        /// Enable image understanding
+        judge: bool,
+        /// Model used for --judge
+        #[arg(long, default_value = REASONING_MODEL)]
+        judge_model: String,
+    },
+    /// Interactive chat that threads each turn onto the previous response
+    Repl {
+        /// Start with web search on (toggle with /web)
         #[arg(long)]
-        enable_images: bool,
-        /// Enable video understanding
+        web: bool,
+        /// Save turns to this named thread, resuming it if it exists
+        #[arg(long, value_name = "NAME")]
+        thread: Option<String>,
+    },
+    /// Ask every non-empty line of FILE as its own question
+    Batch {
+        file: PathBuf,
+        /// Requests to run at once
+        #[arg(long, default_value = "4", value_parser = clap::value_parser!(u64).range(1..))]
+        concurrency: u64,
+        /// Write Prometheus text-format metrics for the run to this file
+        #[arg(long, value_name = "PATH")]
+        metrics_file: Option<PathBuf>,
+    },
+    /// Ask several follow-ups to one response at once and show the answers grouped
+    Branch {
+        /// The response every query follows up on
+        #[arg(long, value_name = "RESPONSE_ID")]
+        from: String,
+        #[arg(required = true)]
+        queries: Vec<String>,
+        /// Requests to run at once (all of them by default)
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        concurrency: Option<u64>,
+    },
+    /// Estimate what `batch FILE` would cost, without sending anything
+    BatchEstimate { file: PathBuf },
+    /// Estimate the input tokens of a prompt plus its system instruction, offline
+    CountTokens {
+        /// The prompt, or - to read it from stdin
+        text: Option<String>,
+        /// The command whose built-in instruction the prompt would be sent with
+        #[arg(long = "for", value_name = "COMMAND", default_value = "chat", value_parser = parse_mode)]
+        target: Mode,
+    },
+    /// Answer queries from a local web UI over a websocket on 127.0.0.1
+    Serve {
+        #[arg(long, default_value_t = 8765)]
+        port: u16,
+        /// Ground answers with web search (plain chat otherwise)
         #[arg(long)]
-        enable_video: bool,
+        web: bool,
+    },
+    /// Inspect saved conversation threads
+    Thread {
+        #[command(subcommand)]
+        command: ThreadCommand,
+    },
+    /// Print a shell completion script (e.g. grok-ask completions zsh > ~/.zfunc/_grok-ask)
+    Completions { shell: Shell },
+    /// Manage the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage cached responses
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
     },
-    // TODO: Add XThink command - deep reasoning with X search grounding (use_reasoning=true, use_x_search=true)
 }
 
-#[derive(Clone, Debug, clap::ValueEnum)]
-enum OutputFormat {
-    Text,
-    Json,
-}
+impl Commands {
+    /// The positional query, for commands that take one
+    fn query(&self) -> Option<&str> {
+        match self {
+            Commands::Search { query, .. }
+            | Commands::Ask { query, .. }
+            | Commands::Think { query, .. }
+            | Commands::Chat { query, .. }
+            | Commands::XSearch { query, .. }
+            | Commands::XAsk { query, .. }
+            | Commands::XThink { query, .. }
+            | Commands::Compare { query, .. }
+            | Commands::CountTokens { text: query, .. } => query.as_deref(),
+            _ => None,
+        }
+    }
 
-// Request structures
-#[derive(Serialize)]
-struct Message {
-    role: String,
-    content: String,
+    /// Whether the command renders its result in the `-o/--output` format
+    fn uses_output_format(&self) -> bool {
+        !matches!(
+            self,
+            Commands::Thread { .. }
+                | Commands::Completions { .. }
+                | Commands::Config { .. }
+                | Commands::Cache { .. }
+                | Commands::Serve { .. }
+        )
+    }
 }
 
-#[derive(Serialize)]
-#[serde(untagged)]
-enum Tool {
-    WebSearch(WebSearchTool),
-    XSearch(XSearchTool),
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Delete every cached response
+    Clear,
 }
 
-#[derive(Serialize)]
-struct WebSearchTool {
-    r#type: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    enable_image_understanding: Option<bool>,
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Store the API key in the config file (read from stdin when KEY is omitted)
+    SetKey { key: Option<String> },
+    /// Print where the config file lives on this machine
+    Path,
 }
 
-#[derive(Serialize)]
-struct XSearchTool {
-    r#type: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    allowed_x_handles: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    excluded_x_handles: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    from_date: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    to_date: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    enable_image_understanding: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    enable_video_understanding: Option<bool>,
-}
-
-#[derive(Serialize)]
-struct GrokRequest {
-    model: String,
-    input: Vec<Message>,
-    store: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    max_output_tokens: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    previous_response_id: Option<String>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    tools: Vec<Tool>,
-}
-
-// Response structures
-#[derive(Deserialize, Serialize, Debug)]
-struct GrokResponse {
-    id: Option<String>,
-    status: Option<String>,
-    output: Option<Vec<Output>>,
-    usage: Option<Usage>,
-    #[serde(default)]
-    error: Option<ApiError>,
+#[derive(Subcommand)]
+enum ThreadCommand {
+    /// Total token usage across a thread's turns
+    Usage { name: String },
+    /// Print the conversation as text (--show-roles labels each part)
+    Show { name: String },
+    /// Write the conversation as a standalone HTML page (NAME.html unless --output-file is set)
+    ExportHtml { name: String },
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Output {
-    r#type: String,
-    content: Option<Vec<Content>>,
-    results: Option<Vec<WebSearchResult>>,
-}
+/// X search filters shared by the X commands and their shorthand flags
+#[derive(Args, Clone, Debug, Default)]
+struct XSearchArgs {
+    /// Only include posts from these X handles (comma-separated, without @)
+    #[arg(long, value_delimiter = ',')]
+    allowed_handles: Option<Vec<String>>,
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Content {
-    r#type: String,
-    text: Option<String>,
-    annotations: Option<Vec<Annotation>>,
-}
+    /// Exclude posts from these X handles (comma-separated, without @)
+    #[arg(long, value_delimiter = ',')]
+    excluded_handles: Option<Vec<String>>,
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Annotation {
-    url: Option<String>,
-    title: Option<String>,
-}
+    /// Start date for X search (YYYY-MM-DD)
+    #[arg(long)]
+    from_date: Option<String>,
 
-#[derive(Deserialize, Serialize, Debug)]
-struct WebSearchResult {
-    url: Option<String>,
-    title: Option<String>,
-}
+    /// End date for X search (YYYY-MM-DD)
+    #[arg(long)]
+    to_date: Option<String>,
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Usage {
-    input_tokens: Option<u32>,
-    output_tokens: Option<u32>,
-}
+    /// Search the last N days: --from-date becomes N days ago (UTC), --to-date today unless given
+    #[arg(long, value_name = "N", conflicts_with = "from_date")]
+    since_days: Option<u32>,
 
-#[derive(Deserialize, Serialize, Debug)]
-struct ApiError {
-    message: Option<String>,
-    code: Option<String>,
-}
+    /// Enable image understanding for X search
+    #[arg(long)]
+    enable_images: bool,
 
-fn get_api_key() -> Result<String> {
-    env::var("XAI_API_KEY").context(
-        "XAI_API_KEY environment variable not set. Get your key from https://console.x.ai/",
-    )
+    /// Enable video understanding for X search
+    #[arg(long)]
+    enable_video: bool,
 }
 
-/// X search configuration
-#[derive(Default)]
-struct XSearchConfig {
-    allowed_handles: Option<Vec<String>>,
-    excluded_handles: Option<Vec<String>>,
-    from_date: Option<String>,
-    to_date: Option<String>,
-    enable_images: bool,
-    enable_video: bool,
+impl XSearchArgs {
+    fn config(&self) -> XSearchConfig {
+        XSearchConfig {
+            allowed_handles: self.allowed_handles.as_deref().map(normalize_handles),
+            excluded_handles: self.excluded_handles.as_deref().map(normalize_handles),
+            from_date: self.from_date.clone(),
+            to_date: self.to_date.clone(),
+            enable_images: self.enable_images,
+            enable_video: self.enable_video,
+        }
+    }
 }
 
-async fn create_request(
-    query: &str,
-    previous_response_id: Option<&str>,
-    system_instruction: Option<&str>,
-    max_tokens: u32,
-    use_web_search: bool,
-    use_x_search: bool,
-    x_search_config: Option<XSearchConfig>,
-    use_reasoning: bool,
-) -> Result<GrokResponse> {
-    let api_key = get_api_key()?;
-    let client = reqwest::Client::new();
+const SEARCH_INSTRUCTION: &str = "Search for the query and return results in this exact format:\n\n---\nTITLE: [page title]\nURL: [full url]\nSNIPPET: [2-3 sentence excerpt]\n---\n\nReturn up to {max_results} results. No additional commentary or analysis.";
+const ASK_INSTRUCTION: &str = "Be concise and factual. Cite sources when using web information.";
+const THINK_INSTRUCTION: &str = "Think step by step. Be thorough and cite sources.";
+const X_SEARCH_INSTRUCTION: &str = "Search X for the query and return results in this exact format:\n\n---\nAUTHOR: @[handle]\nPOST: [post content]\nURL: [full x.com url]\n---\n\nReturn up to {max_results} results. No additional commentary or analysis.";
+const X_THINK_INSTRUCTION: &str = "Think step by step over X discussions and cite posts.";
+const X_ASK_INSTRUCTION: &str = "Be concise and factual. Cite X posts when referencing discussions or opinions.";
 
-    let mut messages = Vec::new();
+/// Results requested by the `--search`/`--x-search` shorthands, which take no `--max-results`
+const SHORTHAND_MAX_RESULTS: u32 = 10;
 
-    // Add system instruction if provided
-    if let Some(instruction) = system_instruction {
-        messages.push(Message {
-            role: "system".to_string(),
-            content: instruction.to_string(),
-        });
-    }
+/// The request profile behind each command and its shorthand flag
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Search,
+    Ask,
+    Think,
+    Chat,
+    XSearch,
+    XAsk,
+    XThink,
+}
 
-    // Add user query
-    messages.push(Message {
-        role: "user".to_string(),
-        content: query.to_string(),
-    });
+impl Mode {
+    const ALL: [Mode; 7] = [
+        Mode::Search,
+        Mode::Ask,
+        Mode::Think,
+        Mode::Chat,
+        Mode::XSearch,
+        Mode::XAsk,
+        Mode::XThink,
+    ];
 
-    let mut tools = Vec::new();
-
-    if use_web_search {
-        tools.push(Tool::WebSearch(WebSearchTool {
-            r#type: "web_search".to_string(),
-            enable_image_understanding: None,
-        }));
-    }
-
-    if use_x_search {
-        let config = x_search_config.unwrap_or_default();
-        tools.push(Tool::XSearch(XSearchTool {
-            r#type: "x_search".to_string(),
-            allowed_x_handles: config.allowed_handles,
-            excluded_x_handles: config.excluded_handles,
-            from_date: config.from_date,
-            to_date: config.to_date,
-            enable_image_understanding: if config.enable_images { Some(true) } else { None },
-            enable_video_understanding: if config.enable_video { Some(true) } else { None },
-        }));
-    }
-
-    let model = if use_reasoning { REASONING_MODEL } else { MODEL };
-
-    let request = GrokRequest {
-        model: model.to_string(),
-        input: messages,
-        store: true,
-        max_output_tokens: Some(max_tokens),
-        previous_response_id: previous_response_id.map(|s| s.to_string()),
-        tools,
-    };
+    fn from_name(name: &str) -> Option<Mode> {
+        Mode::ALL
+            .into_iter()
+            .find(|mode| mode.name() == name.trim())
+    }
 
-    let start = Instant::now();
-    let response = client
-        .post(API_ENDPOINT)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .header("Content-Type", "application/json")
-        .json(&request)
-        .send()
-        .await
-        .context("Failed to send request")?;
+    /// The subcommand name, as recorded in the audit log
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Search => "search",
+            Mode::Ask => "ask",
+            Mode::Think => "think",
+            Mode::Chat => "chat",
+            Mode::XSearch => "x-search",
+            Mode::XAsk => "x-ask",
+            Mode::XThink => "x-think",
+        }
+    }
 
-    let elapsed = start.elapsed();
-    eprintln!("Request completed in {:.2}s", elapsed.as_secs_f64());
+    fn options(self, max_results: u32) -> RequestOptions {
+        let (instruction, max_tokens) = match self {
+            Mode::Search => (Some(SEARCH_INSTRUCTION), 4096),
+            Mode::Ask => (Some(ASK_INSTRUCTION), 8192),
+            Mode::Think => (Some(THINK_INSTRUCTION), 16384),
+            Mode::Chat => (None, 8192),
+            Mode::XSearch => (Some(X_SEARCH_INSTRUCTION), 4096),
+            Mode::XAsk => (Some(X_ASK_INSTRUCTION), 8192),
+            Mode::XThink => (Some(X_THINK_INSTRUCTION), 16384),
+        };
 
-    let data: GrokResponse = response.json().await.context("Failed to parse response")?;
-    Ok(data)
+        RequestOptions {
+            system_instruction: instruction
+                .map(|s| s.replace("{max_results}", &max_results.to_string())),
+            max_tokens,
+            use_web_search: matches!(self, Mode::Search | Mode::Ask | Mode::Think),
+            use_x_search: matches!(self, Mode::XSearch | Mode::XAsk | Mode::XThink),
+            use_reasoning: matches!(self, Mode::Think | Mode::XThink),
+            max_search_results: matches!(self, Mode::Search).then_some(max_results),
+            ..Default::default()
+        }
+    }
 }
 
-fn format_response(response: &GrokResponse, format: &OutputFormat) -> String {
-    match format {
-        OutputFormat::Json => serde_json::to_string_pretty(response).unwrap_or_default(),
-        OutputFormat::Text => {
-            let mut output = String::new();
-
-            // Check for error
-            if let Some(error) = &response.error {
-                output.push_str(&format!(
-                    "Error: {}\n",
-                    error.message.as_deref().unwrap_or("Unknown error")
-                ));
-                return output;
-            }
+/// The mode a bare `grok-ask QUERY` runs in, from the config's `default_command`
+fn default_mode(config_file: &Path) -> Result<Mode> {
+    let config = Config::load(config_file)?;
+    let Some(name) = config.default_command.as_deref() else {
+        bail!(
+            "Give a command for the query (e.g. grok-ask ask \"...\"), or set default_command = \"ask\" in {}",
+            config_file.display()
+        );
+    };
+    Mode::from_name(name).with_context(|| {
+        let names: Vec<&str> = Mode::ALL.iter().map(|mode| mode.name()).collect();
+        format!(
+            "default_command `{}` in {} isn't one of {}",
+            name,
+            config_file.display(),
+            names.join(", ")
+        )
+    })
+}
 
-            // Extract text from outputs
-            let mut sources: Vec<(String, String)> = Vec::new();
-
-            if let Some(outputs) = &response.output {
-                for out in outputs {
-                    if out.r#type == "message" {
-                        if let Some(contents) = &out.content {
-                            for content in contents {
-                                if content.r#type == "output_text" || content.r#type == "text" {
-                                    if let Some(text) = &content.text {
-                                        output.push_str(text);
-                                    }
-                                    // Extract annotations
-                                    if let Some(annotations) = &content.annotations {
-                                        for ann in annotations {
-                                            if let Some(url) = &ann.url {
-                                                let title = ann.title.clone().unwrap_or_else(|| "Source".to_string());
-                                                if !sources.iter().any(|(_, u)| u == url) {
-                                                    sources.push((title, url.clone()));
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    } else if out.r#type == "web_search_result" {
-                        if let Some(results) = &out.results {
-                            for result in results {
-                                if let Some(url) = &result.url {
-                                    let title = result.title.clone().unwrap_or_else(|| "Web Result".to_string());
-                                    if !sources.iter().any(|(_, u)| u == url) {
-                                        sources.push((title, url.clone()));
-                                    }
-                                }
-                            }
-                        }
-                    } else if out.r#type == "x_search_result" {
-                        if let Some(results) = &out.results {
-                            for result in results {
-                                if let Some(url) = &result.url {
-                                    let title = result.title.clone().unwrap_or_else(|| "X Post".to_string());
-                                    if !sources.iter().any(|(_, u)| u == url) {
-                                        sources.push((title, url.clone()));
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+/// A resolved invocation: which mode, what to ask, and the request settings
+struct Resolved {
+    mode: Mode,
+    query: String,
+    options: RequestOptions,
+}
 
-            // Add sources
-            if !sources.is_empty() {
-                output.push_str("\n\nSources:\n");
-                for (i, (title, url)) in sources.iter().enumerate() {
-                    output.push_str(&format!("{}. [{}]({})\n", i + 1, title, url));
-                }
-            }
+/// Resolves the query and request options from a shorthand flag or subcommand
+fn resolve_request(cli: &Cli) -> Option<Resolved> {
+    let shorthands = [
+        (Mode::Search, &cli.search),
+        (Mode::Ask, &cli.ask),
+        (Mode::Think, &cli.think),
+        (Mode::Chat, &cli.chat),
+        (Mode::XSearch, &cli.x_search),
+        (Mode::XAsk, &cli.x_ask),
+        (Mode::XThink, &cli.x_think),
+    ];
 
-            // Add follow-up instructions
-            output.push_str("\n---\n");
-            if let Some(id) = &response.id {
-                output.push_str(&format!("To follow up, use response_id: {}\n", id));
-            }
+    let mut resolved = if let Some((mode, query)) = shorthands
+        .into_iter()
+        .find_map(|(mode, query)| query.as_ref().map(|q| (mode, q)))
+        .or_else(|| cli.default_mode.zip(cli.query.as_ref()))
+    {
+        let mut options = mode.options(SHORTHAND_MAX_RESULTS);
+        options.previous_response_id = cli.response_id.clone();
+        if options.use_x_search {
+            options.x_search_config = Some(cli.x.config());
+        }
+        if cli.web || cli.no_web {
+            options.use_web_search = cli.web;
+        }
+        options.web_images = options.use_web_search && cli.x.enable_images;
+        Resolved {
+            mode,
+            query: query.clone(),
+            options,
+        }
+    } else {
+        resolve_command(cli.command.as_ref()?)?
+    };
 
-            output
+    if let Some(prompt) = &cli.prompt_file {
+        if resolved.query.is_empty() {
+            resolved.query = prompt.trim().to_string();
         }
     }
+
+    let options = &mut resolved.options;
+    options.system_instruction = build_system_instruction(&SystemLayers {
+        preset: options.system_instruction.as_deref(),
+        replace: cli.system.as_deref().or(cli.system_file.as_deref()),
+        append: cli.append_system.as_deref(),
+        target_words: cli.target_words,
+        lang: cli.lang.as_deref(),
+    });
+    if cli.no_reasoning {
+        options.use_reasoning = false;
+    }
+    if let Some(max_tokens) = cli.max_tokens {
+        options.max_tokens = max_tokens;
+    }
+    options.tool_choice = Some(cli.tool_choice);
+    options.image = cli.image_url.clone();
+    options.max_tool_calls = cli.max_tool_calls;
+    options.country = cli.country.clone();
+    options.cache_prompt = cli.cache_prompt;
+    options.schema = cli.schema.clone();
+    options.cassette = cli.cassette.clone();
+    options.background = cli.background;
+    options.no_store = cli.no_store;
+    options.temperature = cli.temperature;
+    options.reasoning_effort = cli.effort.clone();
+    options.model = cli.model.clone();
+    options.retry = retry_policy(cli);
+    let connection = connection_options(cli);
+    options.timeout = connection.timeout;
+    options.endpoint = connection.endpoint;
+    options.auth_scheme = connection.auth_scheme;
+    options.auth_header = connection.auth_header;
+    options.no_poll = cli.no_poll;
+    options.verbose = connection.verbose;
+    options.show_headers = connection.show_headers;
+    options.quiet = connection.quiet;
+    options.spinner = spinner_enabled(
+        io::stderr().is_terminal(),
+        cli.stream,
+        matches!(cli.output, OutputFormat::Json),
+        cli.verbose,
+    );
+    Some(resolved)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// Where and how to reach the API, for requests that don't go through a mode
+fn connection_options(cli: &Cli) -> RequestOptions {
+    RequestOptions {
+        timeout: Some(Duration::from_secs(cli.timeout)),
+        endpoint: cli.endpoint.clone(),
+        auth_scheme: cli.auth_scheme,
+        auth_header: cli.auth_header.clone(),
+        verbose: cli.verbose,
+        show_headers: cli.show_headers,
+        quiet: cli.quiet,
+        ..Default::default()
+    }
+}
 
-    let result = if let Some(query) = &cli.search {
-        let system_instruction = "Search for the query and return results in this exact format:\n\n---\nTITLE: [page title]\nURL: [full url]\nSNIPPET: [2-3 sentence excerpt]\n---\n\nReturn up to 10 results. No additional commentary or analysis.";
-        create_request(
+fn resolve_command(command: &Commands) -> Option<Resolved> {
+    let (mode, query, options) = match command {
+        Commands::Search {
             query,
-            cli.response_id.as_deref(),
-            Some(system_instruction),
-            4096,
-            true,  // web search
-            false, // no x search
-            None,
-            false, // no reasoning
-        )
-        .await?
-    } else if let Some(query) = &cli.ask {
-        create_request(
+            max_results,
+            enable_images,
+        } => {
+            let mut options = Mode::Search.options(*max_results);
+            options.web_images = *enable_images;
+            (Mode::Search, query, options)
+        }
+        Commands::Ask {
             query,
-            cli.response_id.as_deref(),
-            Some("Be concise and factual. Cite sources when using web information."),
-            8192,
-            true,  // web search
-            false, // no x search
-            None,
-            false, // no reasoning
-        )
-        .await?
-    } else if let Some(query) = &cli.think {
-        create_request(
+            response_id,
+            no_web,
+            enable_images,
+        } => {
+            let mut options = Mode::Ask.options(10);
+            options.previous_response_id = response_id.clone();
+            options.use_web_search = !no_web;
+            options.web_images = *enable_images;
+            (Mode::Ask, query, options)
+        }
+        Commands::Think { query, response_id } => {
+            let mut options = Mode::Think.options(10);
+            options.previous_response_id = response_id.clone();
+            (Mode::Think, query, options)
+        }
+        Commands::Chat {
             query,
-            cli.response_id.as_deref(),
-            Some("Think step by step. Be thorough and cite sources."),
-            16384,
-            true,  // web search
-            false, // no x search
-            None,
-            true,  // use reasoning model
-        )
-        .await?
-    } else if let Some(query) = &cli.chat {
-        create_request(
+            response_id,
+            web,
+        } => {
+            let mut options = Mode::Chat.options(10);
+            options.previous_response_id = response_id.clone();
+            options.use_web_search = *web;
+            (Mode::Chat, query, options)
+        }
+        Commands::XSearch {
             query,
-            cli.response_id.as_deref(),
-            None,
-            8192,
-            false, // no web search
-            false, // no x search
-            None,
-            false, // no reasoning
-        )
-        .await?
-    } else if let Some(query) = &cli.x_search {
-        let system_instruction = "Search X for the query and return results in this exact format:\n\n---\nAUTHOR: @[handle]\nPOST: [post content]\nURL: [full x.com url]\n---\n\nReturn up to 10 results. No additional commentary or analysis.";
-        let config = XSearchConfig {
-            allowed_handles: cli.allowed_handles.clone(),
-            excluded_handles: cli.excluded_handles.clone(),
-            from_date: cli.from_date.clone(),
-            to_date: cli.to_date.clone(),
-            enable_images: cli.enable_images,
-            enable_video: cli.enable_video,
-        };
-        create_request(
+            max_results,
+            x,
+            ..
+        } => {
+            let mut options = Mode::XSearch.options(*max_results);
+            options.x_search_config = Some(x.config());
+            (Mode::XSearch, query, options)
+        }
+        Commands::XAsk {
             query,
-            cli.response_id.as_deref(),
-            Some(system_instruction),
-            4096,
-            false, // no web search
-            true,  // x search
-            Some(config),
-            false, // no reasoning
-        )
-        .await?
-    } else if let Some(query) = &cli.x_ask {
-        let config = XSearchConfig {
-            allowed_handles: cli.allowed_handles.clone(),
-            excluded_handles: cli.excluded_handles.clone(),
-            from_date: cli.from_date.clone(),
-            to_date: cli.to_date.clone(),
-            enable_images: cli.enable_images,
-            enable_video: cli.enable_video,
-        };
-        create_request(
+            response_id,
+            x,
+        } => {
+            let mut options = Mode::XAsk.options(10);
+            options.previous_response_id = response_id.clone();
+            options.x_search_config = Some(x.config());
+            (Mode::XAsk, query, options)
+        }
+        Commands::XThink {
             query,
-            cli.response_id.as_deref(),
-            Some("Be concise and factual. Cite X posts when referencing discussions or opinions."),
-            8192,
-            false, // no web search
-            true,  // x search
-            Some(config),
-            false, // no reasoning
-        )
-        .await?
-    } else if let Some(command) = &cli.command {
-        match command {
-            Commands::Search { query, max_results } => {
-                let system_instruction = format!(
-                    "Search for the query and return results in this exact format:\n\n---\nTITLE: [page title]\nURL: [full url]\nSNIPPET: [2-3 sentence excerpt]\n---\n\nReturn up to {} results. No additional commentary or analysis.",
-                    max_results
-                );
-                create_request(
-                    query,
-                    None,
-                    Some(&system_instruction),
-                    4096,
-                    true,  // web search
-                    false, // no x search
-                    None,
-                    false, // no reasoning
-                )
-                .await?
-            }
-            Commands::Ask { query, response_id } => {
-                create_request(
-                    query,
-                    response_id.as_deref(),
-                    Some("Be concise and factual. Cite sources when using web information."),
-                    8192,
-                    true,  // web search
-                    false, // no x search
-                    None,
-                    false, // no reasoning
-                )
-                .await?
-            }
-            Commands::Think { query, response_id } => {
-                create_request(
-                    query,
-                    response_id.as_deref(),
-                    Some("Think step by step. Be thorough and cite sources."),
-                    16384,
-                    true,  // web search
-                    false, // no x search
-                    None,
-                    true,  // use reasoning model
-                )
-                .await?
-            }
-            Commands::Chat { query, response_id } => {
-                create_request(
-                    query,
-                    response_id.as_deref(),
-                    None,
-                    8192,
-                    false, // no web search
-                    false, // no x search
-                    None,
-                    false, // no reasoning
-                )
-                .await?
-            }
-            Commands::XSearch {
-                query,
-                max_results,
-                allowed_handles,
-                excluded_handles,
-                from_date,
-                to_date,
-                enable_images,
-                enable_video,
-            } => {
-                let system_instruction = format!(
-                    "Search X for the query and return results in this exact format:\n\n---\nAUTHOR: @[handle]\nPOST: [post content]\nURL: [full x.com url]\n---\n\nReturn up to {} results. No additional commentary or analysis.",
-                    max_results
-                );
-                let config = XSearchConfig {
-                    allowed_handles: allowed_handles.clone(),
-                    excluded_handles: excluded_handles.clone(),
-                    from_date: from_date.clone(),
-                    to_date: to_date.clone(),
-                    enable_images: *enable_images,
-                    enable_video: *enable_video,
-                };
-                create_request(
-                    query,
-                    None,
-                    Some(&system_instruction),
-                    4096,
-                    false, // no web search
-                    true,  // x search
-                    Some(config),
-                    false, // no reasoning
-                )
-                .await?
-            }
-            Commands::XAsk {
-                query,
-                response_id,
-                allowed_handles,
-                excluded_handles,
-                from_date,
-                to_date,
-                enable_images,
-                enable_video,
-            } => {
-                let config = XSearchConfig {
-                    allowed_handles: allowed_handles.clone(),
-                    excluded_handles: excluded_handles.clone(),
-                    from_date: from_date.clone(),
-                    to_date: to_date.clone(),
-                    enable_images: *enable_images,
-                    enable_video: *enable_video,
-                };
-                create_request(
-                    query,
-                    response_id.as_deref(),
-                    Some("Be concise and factual. Cite X posts when referencing discussions or opinions."),
-                    8192,
-                    false, // no web search
-                    true,  // x search
-                    Some(config),
-                    false, // no reasoning
-                )
-                .await?
-            }
+            response_id,
+            x,
+        } => {
+            let mut options = Mode::XThink.options(10);
+            options.previous_response_id = response_id.clone();
+            options.x_search_config = Some(x.config());
+            (Mode::XThink, query, options)
         }
-    } else {
-        eprintln!("No command or query provided. Use --help for usage.");
-        std::process::exit(1);
+        Commands::Compare { query, .. } => (Mode::Ask, query, Mode::Ask.options(10)),
+        Commands::Repl { .. } => {
+            return Some(Resolved {
+                mode: Mode::Chat,
+                query: String::new(),
+                options: Mode::Chat.options(10),
+            })
+        }
+        Commands::Branch { from, .. } => {
+            let mut options = Mode::Ask.options(10);
+            options.previous_response_id = Some(from.clone());
+            return Some(Resolved {
+                mode: Mode::Ask,
+                query: String::new(),
+                options,
+            });
+        }
+        Commands::CountTokens { text, target } => (*target, text, target.options(10)),
+        Commands::Batch { .. } | Commands::BatchEstimate { .. } => {
+            return Some(Resolved {
+                mode: Mode::Ask,
+                query: String::new(),
+                options: Mode::Ask.options(10),
+            })
+        }
+        Commands::Serve { web, .. } => {
+            let mode = if *web { Mode::Ask } else { Mode::Chat };
+            return Some(Resolved {
+                mode,
+                query: String::new(),
+                options: mode.options(10),
+            });
+        }
+        Commands::Get { .. }
+        | Commands::Thread { .. }
+        | Commands::Config { .. }
+        | Commands::Cache { .. }
+        | Commands::Completions { .. } => return None,
     };
 
-    println!("{}", format_response(&result, &cli.output));
-    Ok(())
+    Some(Resolved {
+        mode,
+        query: query.clone().unwrap_or_default(),
+        options,
+    })
 }
 
-#[cfg(test)]
-mod tests {
+fn parse_country(value: &str) -> Result<String, String> {
+    let code = value.trim();
+    if code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(code.to_ascii_uppercase())
+    } else {
+        Err("expected a two-letter country code such as US or DE".to_string())
+    }
+}
+
+fn parse_mode(value: &str) -> Result<Mode, String> {
+    Mode::from_name(value).ok_or_else(|| {
+        let names: Vec<&str> = Mode::ALL.iter().map(|mode| mode.name()).collect();
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+fn parse_image_url(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value.starts_with("https://") || value.starts_with("http://") {
+        Ok(value.to_string())
+    } else {
+        Err("expected an http(s) URL; use --image for a local file".to_string())
+    }
+}
+
+fn parse_schema_file(path: &str) -> Result<JsonSchema, String> {
+    JsonSchema::load(Path::new(path)).map_err(|e| format!("{:#}", e))
+}
+
+/// The priming turns: the --messages-file ones first, then each --example pair
+fn prior_messages(cli: &Cli) -> Result<Vec<PriorMessage>> {
+    let mut messages = match &cli.messages_file {
+        Some(path) => load_messages(path)?,
+        None => Vec::new(),
+    };
+    messages.extend(cli.example.iter().flatten().cloned());
+    Ok(messages)
+}
+
+fn read_text_file(path: &str) -> Result<String, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    if text.trim().is_empty() {
+        return Err(format!("{} is empty", path));
+    }
+    Ok(text)
+}
+
+fn parse_ttl(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit `{}`; use s, m, h or d", unit)),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("`{}` is not a duration like 90, 30m, 12h or 7d", value))?;
+    number
+        .checked_mul(multiplier)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("`{}` is too long a duration", value))
+}
+
+fn parse_temperature(value: &str) -> Result<f32, String> {
+    let temperature: f32 = value
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", value))?;
+    if !(0.0..=2.0).contains(&temperature) {
+        return Err(format!(
+            "{} is out of range; temperature must be between 0.0 and 2.0",
+            temperature
+        ));
+    }
+    Ok(temperature)
+}
+
+/// The X search flags in effect: the subcommand's, or the top-level ones for shorthands
+fn x_search_args(cli: &Cli) -> &XSearchArgs {
+    match &cli.command {
+        Some(Commands::XSearch { x, .. })
+        | Some(Commands::XAsk { x, .. })
+        | Some(Commands::XThink { x, .. }) => x,
+        _ => &cli.x,
+    }
+}
+
+/// Rewrites the X date filters as the ISO dates the API expects, and checks the range
+fn normalize_dates(config: &mut XSearchConfig, format: Option<DateFormat>) -> Result<()> {
+    for date in [&mut config.from_date, &mut config.to_date]
+        .into_iter()
+        .flatten()
+    {
+        *date = parse_date_as(date, format)?.to_string();
+    }
+    validate_date_range(config.from_date.as_deref(), config.to_date.as_deref())
+}
+
+/// Rejects handle filters that can never match an X username
+fn verify_handles(config: &XSearchConfig) -> Result<()> {
+    let invalid: Vec<&str> = config
+        .allowed_handles
+        .iter()
+        .chain(config.excluded_handles.iter())
+        .flatten()
+        .map(String::as_str)
+        .filter(|h| !is_valid_x_handle(h))
+        .collect();
+
+    if !invalid.is_empty() {
+        bail!(
+            "Invalid X handle(s): {}. Handles are 1-15 letters, digits, or underscores, without @",
+            invalid.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Printed between answers when running several queries in one invocation
+const ANSWER_SEPARATOR: &str = "========================================";
+
+/// Splits stdin into one query per non-blank line
+fn split_query_lines(input: &str) -> Vec<String> {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Rejects queries that are empty or only whitespace
+fn ensure_query_not_empty(query: &str) -> Result<()> {
+    if query.trim().is_empty() {
+        bail!("Query is empty. Pass the question as an argument, - to read it from stdin, or --prompt-file PATH. Use --help for usage.");
+    }
+    Ok(())
+}
+
+/// Parses the command line. Unless `--output` was given, the format of commands
+/// that use it follows `--output-file`'s extension; the flag says whether it
+/// was inferred that way.
+fn parse_cli<I, T>(args: I) -> Result<(Cli, bool), clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = Cli::command().try_get_matches_from(args)?;
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    let mut inferred = false;
+    if let Some(path) = &cli.output_file {
+        let uses_format = cli
+            .command
+            .as_ref()
+            .is_none_or(Commands::uses_output_format);
+        if uses_format && !given_on_command_line(&matches, "output") {
+            cli.output = OutputFormat::for_path(path);
+            inferred = true;
+        }
+    }
+    Ok((cli, inferred))
+}
+
+/// The stderr note for a format taken from `--output-file`'s extension
+fn inferred_format_note(cli: &Cli) -> String {
+    let name = cli
+        .output
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default();
+    let path = cli.output_file.as_deref().unwrap_or(Path::new(""));
+    format!(
+        "Writing {} output to {} (from its extension; --output overrides)",
+        name,
+        path.display()
+    )
+}
+
+/// Whether `id` was given on the command line, before or after the subcommand
+fn given_on_command_line(matches: &clap::ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+        || matches
+            .subcommand()
+            .is_some_and(|(_, sub)| given_on_command_line(sub, id))
+}
+
+/// A bare query runs the default command, so it can't be combined with a subcommand.
+/// Checked by hand because clap would otherwise parse the subcommand and drop the query.
+fn check_query(cli: &Cli) -> Result<()> {
+    if let (Some(query), Some(_)) = (&cli.query, &cli.command) {
+        bail!(
+            "Unexpected query `{}` before the subcommand; give it to the subcommand instead",
+            query
+        );
+    }
+    Ok(())
+}
+
+/// `--prompt-file` stands in for the positional query, so only one may be given.
+/// Checked by hand because clap can't see a global flag given before the subcommand.
+fn check_prompt_file(cli: &Cli) -> Result<()> {
+    let positional = cli.command.as_ref().and_then(Commands::query);
+    if cli.prompt_file.is_some() && positional.is_some() {
+        bail!("Give the query either as an argument or with --prompt-file, not both");
+    }
+    Ok(())
+}
+
+/// `--then` needs one stored response from a query command to follow up on.
+/// Checked by hand because clap can't see a global flag given before the subcommand.
+fn check_then(cli: &Cli) -> Result<()> {
+    if cli.then.is_none() {
+        return Ok(());
+    }
+    match &cli.command {
+        None
+        | Some(Commands::Search { .. })
+        | Some(Commands::Ask { .. })
+        | Some(Commands::Think { .. })
+        | Some(Commands::Chat { .. })
+        | Some(Commands::XSearch { .. })
+        | Some(Commands::XAsk { .. })
+        | Some(Commands::XThink { .. }) => {}
+        Some(_) => bail!("--then only follows up on search, ask, think, chat and x-* queries"),
+    }
+    for (set, flag) in [
+        (cli.no_store, "--no-store"),
+        (cli.background, "--background"),
+        (cli.lines, "--lines"),
+        (cli.dry_run, "--dry-run"),
+    ] {
+        if set {
+            bail!("--then can't be combined with {}", flag);
+        }
+    }
+    Ok(())
+}
+
+/// `--suggest-followups` asks for its questions as a follow-up to the answer,
+/// which the API can only find if the answer was stored.
+/// Checked by hand because clap can't see a global flag given before the subcommand.
+fn check_suggest_followups(cli: &Cli) -> Result<()> {
+    if cli.suggest_followups && cli.no_store {
+        bail!("--suggest-followups can't be combined with --no-store");
+    }
+    Ok(())
+}
+
+/// `--stream` prints the answer before it is complete, so it can't be combined with
+/// flags that need the whole answer first: to write it to a file, check it, follow up
+/// on it, or count its X posts.
+/// Checked by hand because clap can't see a global flag given before the subcommand.
+fn check_stream(cli: &Cli) -> Result<()> {
+    if !cli.stream {
+        return Ok(());
+    }
+    for (set, flag) in [
+        (cli.output_file.is_some(), "--output-file"),
+        (!cli.forbid.is_empty(), "--forbid"),
+        (cli.then.is_some(), "--then"),
+        (
+            matches!(
+                cli.command,
+                Some(Commands::XSearch {
+                    min_results: Some(_),
+                    ..
+                })
+            ),
+            "--min-results",
+        ),
+    ] {
+        if set {
+            bail!("--stream can't be combined with {}", flag);
+        }
+    }
+    Ok(())
+}
+
+/// `repl`, `serve` and `compare` send requests of their own, which `--dry-run` can't show.
+/// Checked by hand because clap can't see a global flag given before the subcommand.
+fn check_dry_run(cli: &Cli) -> Result<()> {
+    let command = match &cli.command {
+        Some(Commands::Repl { .. }) => "repl",
+        Some(Commands::Serve { .. }) => "serve",
+        Some(Commands::Compare { .. }) => "compare",
+        _ => return Ok(()),
+    };
+    if cli.dry_run {
+        bail!("--dry-run can't be used with {}", command);
+    }
+    Ok(())
+}
+
+/// Whether a positional query would leave piped stdin unread
+fn ignores_piped_stdin(query: &str, stdin_piped: bool) -> bool {
+    stdin_piped && query != "-"
+}
+
+/// Whether stdin carries data: a pipe or a non-empty file. Not being a terminal
+/// isn't enough, since under cron, CI, systemd or nohup stdin is `/dev/null`.
+fn stdin_has_data() -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsFd;
+        io::stdin()
+            .as_fd()
+            .try_clone_to_owned()
+            .and_then(|fd| fs::File::from(fd).metadata())
+            .is_ok_and(|metadata| carries_data(&metadata))
+    }
+    #[cfg(not(unix))]
+    {
+        !io::stdin().is_terminal()
+    }
+}
+
+#[cfg(unix)]
+fn carries_data(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    metadata.file_type().is_fifo() || (metadata.is_file() && metadata.len() > 0)
+}
+
+/// Resolves the queries to run: the argument itself, or stdin when it is `-`
+fn resolve_queries(cli: &Cli, query: String) -> Result<Vec<String>> {
+    let image_on_stdin = cli.image.as_deref() == Some("-");
+    if query != "-" {
+        if cli.lines {
+            bail!("--lines reads queries from stdin; pass - as the query");
+        }
+        let from_argument = !image_on_stdin && cli.prompt_file.is_none();
+        if from_argument && ignores_piped_stdin(&query, stdin_has_data()) {
+            let message = "stdin is piped but the query was given as an argument, so stdin is ignored; pass - as the query to read it";
+            if cli.strict {
+                bail!("{}", message);
+            }
+            eprintln!("Warning: {}", message);
+        }
+        ensure_query_not_empty(&query)?;
+        return Ok(vec![query]);
+    }
+    if image_on_stdin {
+        bail!("--image - and a - query both read stdin; give one of them as an argument");
+    }
+
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .context("Failed to read query from stdin")?;
+
+    if cli.lines {
+        let queries = split_query_lines(&input);
+        if queries.is_empty() {
+            bail!("No queries found on stdin");
+        }
+        return Ok(queries);
+    }
+
+    ensure_query_not_empty(&input)?;
+    Ok(vec![input.trim().to_string()])
+}
+
+/// Runs each query in order through `run`, writing the answers separated by [`ANSWER_SEPARATOR`]
+///
+/// With a `budget`, stops before a query that would likely push the estimated spend past it.
+async fn dispatch_queries<F, Fut>(
+    queries: &[String],
+    out: &mut impl Write,
+    mut budget: Option<&mut CostBudget>,
+    mut run: F,
+) -> Result<Outcome>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<Answered>>,
+{
+    let mut outcome = Outcome::Completed;
+    for (i, query) in queries.iter().enumerate() {
+        if let Some(budget) = budget.as_deref() {
+            if !budget.allows_next() {
+                eprintln!("{}", budget_stop_note(budget, queries.len()));
+                break;
+            }
+        }
+        if i > 0 {
+            writeln!(out, "{}", ANSWER_SEPARATOR)?;
+        }
+        let answered = run(query.clone()).await?;
+        writeln!(out, "{}", answered.output)?;
+        if outcome == Outcome::Completed {
+            outcome = answered.outcome;
+        }
+        if let Some(budget) = budget.as_deref_mut() {
+            let cost = answered
+                .usage
+                .map(|usage| estimate_cost(&usage, &budget.pricing))
+                .unwrap_or(0.0);
+            budget.record(cost);
+        }
+    }
+    Ok(outcome)
+}
+
+/// Why a run of `total` queries stopped before sending them all
+fn budget_stop_note(budget: &CostBudget, total: usize) -> String {
+    format!(
+        "Stopping: --max-total-cost ${:.4} reached after {} of {} queries (${:.4} spent)",
+        budget.limit, budget.completed, total, budget.spent
+    )
+}
+
+/// Suggests `--wrap` when a line is longer than `limit` characters
+fn long_line_warning(output: &str, limit: usize) -> Option<String> {
+    let longest = longest_line(output);
+    (longest > limit).then(|| {
+        format!(
+            "Warning: output has a {}-character line; use --wrap COLS or pipe through a pager (e.g. | less)",
+            longest
+        )
+    })
+}
+
+/// How far an answer's length may stray from --target-words before warning
+const TARGET_WORDS_TOLERANCE: f64 = 0.25;
+
+/// Warns when `actual` words is more than the tolerance away from `target`
+fn word_target_warning(target: usize, actual: usize) -> Option<String> {
+    let deviation = (actual as f64 - target as f64).abs() / target.max(1) as f64;
+    (deviation > TARGET_WORDS_TOLERANCE).then(|| {
+        format!(
+            "Warning: answer is {} words, {:.0}% {} the --target-words {}",
+            actual,
+            deviation * 100.0,
+            if actual > target { "over" } else { "under" },
+            target
+        )
+    })
+}
+
+/// A rendered answer and the usage it incurred (`None` when served from cache)
+struct Answered {
+    output: String,
+    usage: Option<Usage>,
+    outcome: Outcome,
+    /// The id to follow up on, if the API kept the response
+    response_id: Option<String>,
+}
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+    0  The response completed
+    1  Usage or other error
+    2  The API returned an error
+    3  The answer was truncated (output token limit reached)
+    4  Network failure or timeout
+    5  The response had no content (declined or filtered)
+  130  Cancelled with Ctrl-C";
+
+/// How a run ended, as its process exit code (see [`EXIT_CODES_HELP`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    Completed = 0,
+    Usage = 1,
+    ApiError = 2,
+    Truncated = 3,
+    Network = 4,
+    Empty = 5,
+    /// 128 + SIGINT, as shells report a process stopped by Ctrl-C
+    Cancelled = 130,
+}
+
+impl Outcome {
+    fn of(response: &GrokResponse) -> Self {
+        let truncated = response.status.as_deref() == Some("incomplete")
+            || matches!(
+                response.finish_reason(),
+                Some("length" | "max_output_tokens")
+            );
+        if response.error.is_some() {
+            Outcome::ApiError
+        } else if truncated {
+            Outcome::Truncated
+        } else if is_empty_response(response) {
+            Outcome::Empty
+        } else {
+            Outcome::Completed
+        }
+    }
+
+    fn of_error(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<Outcome>() {
+            Some(outcome) => *outcome,
+            None if is_network_error(err) => Outcome::Network,
+            None if is_api_error(err) => Outcome::ApiError,
+            None => Outcome::Usage,
+        }
+    }
+
+    /// Fails with this outcome unless it's [`Outcome::Completed`]; the detail
+    /// has already been printed with the answer
+    fn into_result(self) -> Result<()> {
+        match self {
+            Outcome::Completed => Ok(()),
+            outcome => Err(outcome.into()),
+        }
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "exited with code {}", *self as i32)
+    }
+}
+
+impl std::error::Error for Outcome {}
+
+/// `--country` only localizes web search
+fn check_country(country: Option<&str>, use_web_search: bool) -> Result<()> {
+    if country.is_some() && !use_web_search {
+        bail!("--country only applies to commands that search the web (search, ask, think)");
+    }
+    Ok(())
+}
+
+/// `--effort` only means something to the reasoning model
+fn check_effort(effort: Option<&str>, use_reasoning: bool) -> Result<()> {
+    if effort.is_some() && !use_reasoning {
+        bail!("--effort only applies to reasoning commands (think, x-think)");
+    }
+    Ok(())
+}
+
+/// Quality gate for `--min-source-count`
+fn check_min_sources(count: usize, min: usize) -> Result<()> {
+    if count < min {
+        bail!(
+            "Answer is backed by {} source(s), fewer than --min-source-count {}",
+            count,
+            min
+        );
+    }
+    Ok(())
+}
+
+/// The `--forbid` patterns that match somewhere in `answer`
+fn forbidden_matches<'a>(answer: &str, patterns: &'a [Regex]) -> Vec<&'a Regex> {
+    patterns.iter().filter(|p| p.is_match(answer)).collect()
+}
+
+fn retry_policy(cli: &Cli) -> RetryPolicy {
+    if cli.no_retry {
+        RetryPolicy::none()
+    } else {
+        let defaults = RetryPolicy::default();
+        RetryPolicy {
+            max_retries: cli.max_retries,
+            retry_codes: cli.retry_codes.clone().unwrap_or(defaults.retry_codes),
+            ..defaults
+        }
+    }
+}
+
+/// How many sources `search` asked for; other commands list them all
+fn max_sources(cli: &Cli) -> Option<usize> {
+    match &cli.command {
+        Some(Commands::Search { max_results, .. }) => Some(*max_results as usize),
+        _ if cli.search.is_some() => Some(SHORTHAND_MAX_RESULTS as usize),
+        _ => None,
+    }
+}
+
+fn format_options(cli: &Cli) -> Result<FormatOptions> {
+    Ok(FormatOptions {
+        skip_sources: cli.skip_sources,
+        id_trailer: cli.id_trailer,
+        pricing: if cli.show_cost {
+            Some(Pricing::from_env()?)
+        } else {
+            None
+        },
+        show_reasoning: cli.show_reasoning,
+        show_finish_reason: cli.show_finish_reason,
+        inline_citations: cli.inline_citations,
+        stable_sources: cli.stable_sources,
+        show_searches: cli.show_searches,
+        sources: cli.sources,
+        quiet: cli.quiet,
+        compact: cli.compact,
+        no_store: cli.no_store,
+        max_sources: max_sources(cli),
+        color: cli.output.is_text()
+            && cli.wrap.is_none()
+            && color_enabled(
+                cli.no_color,
+                env::var("NO_COLOR").ok().as_deref(),
+                cli.output_file.is_none() && io::stdout().is_terminal(),
+            ),
+        ..Default::default()
+    })
+}
+
+/// Date-window retries allowed by `x-search --min-results`
+const MAX_WIDENINGS: u32 = 3;
+
+/// Sends an X search, widening its date window and retrying while it finds
+/// fewer than `min` posts; settles for what it has, with a warning, after
+/// [`MAX_WIDENINGS`] retries or when there's no window to widen. The returned
+/// response's usage covers every request sent.
+async fn x_search_min_results(
+    query: &str,
+    options: &RequestOptions,
+    min: usize,
+    widen_days: u32,
+) -> Result<GrokResponse> {
+    let mut options = options.clone();
+    let mut response = send_query(query, &options).await?;
+    let mut spent = response.usage.clone();
+    let mut widenings = 0;
+    loop {
+        let found = x_result_count(&response);
+        if found >= min || response.error.is_some() {
+            return Ok(GrokResponse {
+                usage: spent,
+                ..response
+            });
+        }
+        let config = options.x_search_config.get_or_insert_with(Default::default);
+        if widenings == MAX_WIDENINGS || !widen_window(config, widen_days)? {
+            eprintln!(
+                "Warning: found {} of the {} X posts asked for by --min-results",
+                found, min
+            );
+            return Ok(GrokResponse {
+                usage: spent,
+                ..response
+            });
+        }
+        widenings += 1;
+        eprintln!(
+            "Found {} of {} X posts; widening the window to {} .. {} ({}/{})",
+            found,
+            min,
+            config.from_date.as_deref().unwrap_or("any"),
+            config.to_date.as_deref().unwrap_or("any"),
+            widenings,
+            MAX_WIDENINGS
+        );
+        response = send_query(query, &options).await?;
+        spent = Usage::sum(spent.as_ref(), response.usage.as_ref());
+    }
+}
+
+/// Sends `query`, then reports the timing, headers and any cassette on stderr
+async fn send_query(query: &str, options: &RequestOptions) -> Result<GrokResponse> {
+    let (response, delivery) = deliver_request(&options.http_client(), query, options).await?;
+    eprint!("{}", delivery.describe(options.quiet));
+    Ok(response)
+}
+
+/// Sends `query`, widening the window of an `x-search --min-results` as needed
+async fn send_uncached(cli: &Cli, query: &str, options: &RequestOptions) -> Result<GrokResponse> {
+    match &cli.command {
+        Some(Commands::XSearch {
+            min_results: Some(min),
+            widen_days,
+            ..
+        }) => x_search_min_results(query, options, *min, *widen_days).await,
+        _ => send_query(query, options).await,
+    }
+}
+
+/// Prints the answer to stdout as it streams in, and each new source to stderr
+async fn stream_answer(
+    query: &str,
+    options: &RequestOptions,
+    filter: SourceFilter,
+) -> Result<GrokResponse> {
+    let mut stdout = io::stdout();
+    send_streaming(&options.http_client(), query, options, |update| {
+        let _ = stdout.write_all(update.text.as_bytes());
+        let _ = stdout.flush();
+        for (title, url) in update.sources.iter().filter(|(_, url)| filter.keeps(url)) {
+            eprintln!("Source: {} <{}>", title, url);
+        }
+    })
+    .await
+}
+
+/// Sends a single query and renders its answer, plus suggested follow-ups if requested
+async fn run_query(
+    cli: &Cli,
+    mode: Mode,
+    query: &str,
+    options: &RequestOptions,
+) -> Result<Answered> {
+    let mut options = options.clone();
+    if cli.adaptive_tokens {
+        options.max_tokens = adaptive_token_budget(query, options.use_reasoning);
+    }
+
+    warn_on_tool_context_change(&options);
+
+    let mut from_cache = false;
+    let mut result = if cli.stream {
+        stream_answer(query, &options, cli.sources).await?
+    } else if cli.cache {
+        let cache = ResponseCache::new(StatePaths::resolve()?.cache_dir.join("responses"));
+        let key = exact_key(&build_request(query, &options));
+        match cache.get_fresh(&key, cli.cache_ttl)? {
+            Some(cached) => {
+                eprintln!("Cache hit");
+                from_cache = true;
+                cached
+            }
+            None => {
+                let result = send_uncached(cli, query, &options).await?;
+                cache.put(&key, &result)?;
+                result
+            }
+        }
+    } else if cli.semantic_cache
+        && matches!(mode, Mode::Search | Mode::Ask)
+        && options.use_web_search
+        && options.prior_messages.is_empty()
+        && options.previous_response_id.is_none()
+        && options.image.is_none()
+    {
+        let cache = ResponseCache::new(StatePaths::resolve()?.cache_dir.join("semantic"));
+        let key = semantic_key(
+            &build_request(query, &options).model,
+            options.system_instruction.as_deref(),
+            query,
+        );
+        match cache.get(&key)? {
+            Some(cached) => {
+                eprintln!("Semantic cache hit");
+                from_cache = true;
+                cached
+            }
+            None => {
+                let result = send_uncached(cli, query, &options).await?;
+                cache.put(&key, &result)?;
+                result
+            }
+        }
+    } else {
+        send_uncached(cli, query, &options).await?
+    };
+
+    if from_cache {
+        // Requests that were sent are logged as they're answered; this one wasn't sent
+        if let Some(log) = &options.audit_log {
+            log.record(query, &build_request(query, &options), &result)?;
+        }
+    } else {
+        record_tool_contexts(result.id.as_deref(), &options);
+    }
+
+    if let Some(path) = &cli.manifest {
+        Manifest::new(query, &build_request(query, &options), &result)?.save(path)?;
+    }
+
+    if let Some(min) = cli.min_source_count {
+        if result.error.is_none() {
+            check_min_sources(collect_sources(&result).len(), min)?;
+        }
+    }
+
+    if !cli.forbid.is_empty() && result.error.is_none() {
+        let matched = forbidden_matches(&extract_answer_text(&result), &cli.forbid);
+        if !matched.is_empty() {
+            for pattern in &matched {
+                eprintln!("Warning: answer matches forbidden pattern /{}/", pattern);
+            }
+            bail!("Answer rejected by {} --forbid pattern(s)", matched.len());
+        }
+    }
+
+    if let Some(target) = cli.target_words {
+        if result.error.is_none() && !options.background {
+            let words = extract_answer_text(&result).split_whitespace().count();
+            if let Some(warning) = word_target_warning(target, words) {
+                eprintln!("{}", warning);
+            }
+        }
+    }
+
+    let baseline = match &cli.baseline_sources {
+        Some(path) => Some(SourceBaseline::load(path)?),
+        None => None,
+    };
+    let format_options = FormatOptions {
+        baseline: baseline.clone(),
+        // The answer itself has already been printed
+        omit_answer: cli.stream,
+        ..format_options(cli)?
+    };
+    if options.background && cli.output.is_text() && result.error.is_none() {
+        return Ok(Answered {
+            output: format_background_started(&result),
+            usage: None,
+            outcome: Outcome::Completed,
+            response_id: result.id.clone(),
+        });
+    }
+
+    // Sent before formatting so the footer's tokens and cost include it
+    let wants_followups = cli.suggest_followups
+        && cli.output.is_text()
+        && result.error.is_none()
+        && !cli.ssml
+        && !cli.citations_json
+        && cli.schema.is_none()
+        && !cli.split_by_headings;
+    let mut followups = Vec::new();
+    let mut followup_usage = None;
+    if let Some(id) = result.id.clone().filter(|_| wants_followups) {
+        let followup_options = suggest_followups_options(&options, &id);
+        let followup = send_query(SUGGEST_FOLLOWUPS_PROMPT, &followup_options).await?;
+        followups = parse_question_list(&extract_answer_text(&followup), 3);
+        result.usage = Usage::sum(result.usage.as_ref(), followup.usage.as_ref());
+        followup_usage = followup.usage;
+    }
+
+    let mut output = format_response_with(&result, &cli.output, &format_options);
+    let outcome = Outcome::of(&result);
+
+    if let (Some(path), Some(mut baseline)) = (&cli.baseline_sources, baseline) {
+        baseline.extend(&collect_sources(&result));
+        baseline.save(path)?;
+    }
+
+    if cli.ssml && result.error.is_none() {
+        return Ok(Answered {
+            output: to_ssml(&extract_answer_text(&result)),
+            usage: if from_cache { None } else { result.usage },
+            outcome,
+            response_id: result.id.clone(),
+        });
+    }
+
+    if cli.citations_json && result.error.is_none() {
+        return Ok(Answered {
+            output: citations_json(&result),
+            usage: if from_cache { None } else { result.usage },
+            outcome,
+            response_id: result.id.clone(),
+        });
+    }
+
+    if let Some(schema) = cli.schema.as_ref().filter(|_| result.error.is_none()) {
+        let value = schema.validate_answer(&extract_answer_text(&result))?;
+        if cli.output.is_text() {
+            return Ok(Answered {
+                output: serde_json::to_string_pretty(&value)?,
+                usage: if from_cache { None } else { result.usage },
+                outcome,
+                response_id: result.id.clone(),
+            });
+        }
+    }
+
+    if cli.split_by_headings && result.error.is_none() {
+        let sections = split_by_headings(&extract_answer_text(&result));
+        return Ok(Answered {
+            output: serde_json::to_string_pretty(&sections)?,
+            usage: if from_cache { None } else { result.usage },
+            outcome,
+            response_id: result.id.clone(),
+        });
+    }
+
+    if !followups.is_empty() {
+        output.push('\n');
+        output.push_str(&format_followups(&followups));
+    }
+
+    // The answer has already been streamed, so there's nothing to put it under
+    if cli.show_roles && cli.output.is_text() && !cli.stream && !options.prior_messages.is_empty() {
+        output = render_primed(&options.prior_messages, query, &output);
+    }
+
+    if cli.output.is_text() {
+        match cli.wrap {
+            Some(width) => output = wrap_text(&output, width),
+            None if io::stdout().is_terminal() => {
+                if let Some(warning) = long_line_warning(&output, cli.long_line_warn) {
+                    eprintln!("{}", warning);
+                }
+            }
+            None => {}
+        }
+    }
+
+    Ok(Answered {
+        output,
+        usage: if from_cache {
+            followup_usage
+        } else {
+            result.usage
+        },
+        outcome,
+        response_id: result.id.clone(),
+    })
+}
+
+/// The query's own model, connection and retry settings, following up on
+/// `id` with no tools, instruction or priming of its own
+fn suggest_followups_options(options: &RequestOptions, id: &str) -> RequestOptions {
+    RequestOptions {
+        previous_response_id: Some(id.to_string()),
+        system_instruction: None,
+        max_tokens: 1024,
+        use_web_search: false,
+        use_x_search: false,
+        x_search_config: None,
+        tool_choice: None,
+        prior_messages: Vec::new(),
+        image: None,
+        max_tool_calls: None,
+        country: None,
+        max_search_results: None,
+        web_images: false,
+        schema: None,
+        cassette: None,
+        background: false,
+        ..options.clone()
+    }
+}
+
+/// Runs the query, then `then` as a follow-up to its response. Both go through
+/// [`run_query`], so caching, checks and logging apply to each; the follow-up
+/// isn't sent if the first query fails or uses up the budget.
+async fn run_then(
+    cli: &Cli,
+    mode: Mode,
+    queries: &[String],
+    then: &str,
+    options: &RequestOptions,
+    budget: Option<&mut CostBudget>,
+) -> Result<()> {
+    let [first] = queries else {
+        bail!(
+            "--then follows up on one query, but {} were given",
+            queries.len()
+        );
+    };
+    ensure_query_not_empty(then)?;
+
+    let first_answer = run_query(cli, mode, first, options).await?;
+    if first_answer.outcome == Outcome::ApiError {
+        bail!(
+            "The first query failed, so --then wasn't sent:\n{}",
+            first_answer.output.trim_end()
+        );
+    }
+    let Some(id) = first_answer.response_id.clone() else {
+        bail!("The first query returned no response id, so --then wasn't sent");
+    };
+    if let Some(budget) = budget {
+        let cost = first_answer
+            .usage
+            .map(|usage| estimate_cost(&usage, &budget.pricing))
+            .unwrap_or(0.0);
+        budget.record(cost);
+        if !budget.allows_next() {
+            bail!(
+                "--max-total-cost ${:.4} reached by the first query (${:.4} spent), so --then wasn't sent",
+                budget.limit,
+                budget.spent
+            );
+        }
+    }
+
+    let follow_up = RequestOptions {
+        previous_response_id: Some(id),
+        ..options.clone()
+    };
+    let answered = run_query(cli, mode, then, &follow_up).await?;
+    let mut output = String::new();
+    if cli.verbose {
+        output.push_str(&first_answer.output);
+        output.push_str(&format!("\n{}\n", ANSWER_SEPARATOR));
+    }
+    output.push_str(&answered.output);
+    output.push('\n');
+    emit_output(cli.output_file.as_deref(), &output)?;
+    answered.outcome.into_result()
+}
+
+/// Answers `query` with both models, then optionally asks `judge_model` to pick one
+async fn run_compare(
+    cli: &Cli,
+    query: &str,
+    options: &RequestOptions,
+    models: &[String],
+    judge_model: Option<&str>,
+) -> Result<String> {
+    let [model_a, model_b] = models else {
+        bail!("compare needs exactly two models, got {}", models.len());
+    };
+    ensure_query_not_empty(query)?;
+
+    let mut answers = Vec::new();
+    for model in [model_a, model_b] {
+        // A single cassette or background id can't stand in for three requests
+        let options = RequestOptions {
+            model: Some(model.clone()),
+            cassette: None,
+            background: false,
+            ..options.clone()
+        };
+        let result = send_query(query, &options).await?;
+        if let Some(error) = &result.error {
+            bail!(
+                "{} failed: {}",
+                model,
+                error.message.as_deref().unwrap_or("Unknown error")
+            );
+        }
+        answers.push(extract_answer_text(&result));
+    }
+
+    let verdict = match judge_model {
+        Some(judge_model) => {
+            let judge_options = RequestOptions {
+                model: Some(judge_model.to_string()),
+                max_tokens: 4096,
+                client: options.client.clone(),
+                audit_log: options.audit_log.clone(),
+                ..connection_options(cli)
+            };
+            let prompt = build_judge_prompt(query, &answers[0], &answers[1]);
+            Some(extract_answer_text(
+                &send_query(&prompt, &judge_options).await?,
+            ))
+        }
+        None => None,
+    };
+
+    Ok(match cli.output {
+        OutputFormat::Json => {
+            let json = serde_json::json!({
+                "query": query,
+                "answers": [
+                    { "model": model_a, "answer": answers[0] },
+                    { "model": model_b, "answer": answers[1] },
+                ],
+                "verdict": verdict.as_ref().map(|text| serde_json::json!({
+                    "model": judge_model,
+                    "text": text,
+                })),
+            });
+            format!("{}\n", json_string(cli, &json)?)
+        }
+        OutputFormat::Text | OutputFormat::Markdown | OutputFormat::Plain => {
+            let mut output = format_candidate("A", model_a, &answers[0]);
+            output.push('\n');
+            output.push_str(&format_candidate("B", model_b, &answers[1]));
+            if let (Some(judge_model), Some(verdict)) = (judge_model, &verdict) {
+                output.push('\n');
+                output.push_str(&format_candidate("Verdict", judge_model, verdict));
+            }
+            output
+        }
+    })
+}
+
+fn read_batch_file(file: &Path) -> Result<Vec<String>> {
+    let text = fs::read_to_string(file)
+        .with_context(|| format!("Failed to read batch file {}", file.display()))?;
+    let queries = parse_batch(&text);
+    if queries.is_empty() {
+        bail!("No queries found in {}", file.display());
+    }
+    Ok(queries)
+}
+
+/// The pretty-printed request each query would send, one JSON document per query
+fn dry_run(cli: &Cli, queries: &[String], options: &RequestOptions) -> Result<String> {
+    let mut out = String::new();
+    for query in queries {
+        let mut options = options.clone();
+        if cli.adaptive_tokens {
+            options.max_tokens = adaptive_token_budget(query, options.use_reasoning);
+        }
+        out.push_str(&serde_json::to_string_pretty(&build_request(
+            query, &options,
+        ))?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Runs `batch`, writing every result before failing if any query failed
+async fn run_batch_file(
+    cli: &Cli,
+    options: &RequestOptions,
+    file: &Path,
+    concurrency: usize,
+    metrics_file: Option<&Path>,
+) -> Result<()> {
+    let queries = read_batch_file(file)?;
+    run_grouped(cli, options, &queries, concurrency, "batch", metrics_file).await
+}
+
+/// Sends `queries` with up to `concurrency` in flight and prints each answer
+/// under its query. A failed query is reported in its place without stopping
+/// the others, and fails the run once all have finished.
+///
+/// With `--max-total-cost`, queries go out in waves of `concurrency`, each
+/// shrunk to what the budget likely allows, and the run stops once none fit.
+async fn run_grouped(
+    cli: &Cli,
+    options: &RequestOptions,
+    queries: &[String],
+    concurrency: usize,
+    command: &str,
+    metrics_file: Option<&Path>,
+) -> Result<()> {
+    if cli.dry_run {
+        return emit_output(cli.output_file.as_deref(), &dry_run(cli, queries, options)?);
+    }
+
+    // One cassette or background id can't stand in for every query
+    let options = RequestOptions {
+        cassette: None,
+        background: false,
+        ..options.clone()
+    };
+    let mut budget = match cli.max_total_cost {
+        Some(limit) => Some(CostBudget::new(limit, Pricing::from_env()?)),
+        None => None,
+    };
+    let wave_size = match budget {
+        Some(_) => concurrency.max(1),
+        None => queries.len(),
+    };
+    let client = options.http_client();
+    let mut results = Vec::new();
+    while results.len() < queries.len() {
+        let mut wave = wave_size.min(queries.len() - results.len());
+        if let Some(budget) = &budget {
+            while wave > 0 && !budget.allows(wave) {
+                wave -= 1;
+            }
+            if wave == 0 {
+                eprintln!("{}", budget_stop_note(budget, queries.len()));
+                break;
+            }
+        }
+        let sent = &queries[results.len()..results.len() + wave];
+        let answered = run_batch(&client, sent, &options, concurrency).await;
+        if let Some(budget) = budget.as_mut() {
+            for result in &answered {
+                let usage = result.outcome.as_ref().ok().and_then(|r| r.usage.as_ref());
+                let cost = usage.map_or(0.0, |usage| estimate_cost(usage, &budget.pricing));
+                budget.record(cost);
+            }
+        }
+        results.extend(answered);
+    }
+    let ids = results
+        .iter()
+        .filter_map(|r| r.outcome.as_ref().ok()?.id.as_deref());
+    record_tool_contexts(ids, &options);
+
+    let output = match cli.output {
+        OutputFormat::Json => format!("{}\n", json_string(cli, &batch_json(&results))?),
+        _ => batch_text(&results, &cli.output, &format_options(cli)?),
+    };
+    emit_output(cli.output_file.as_deref(), &output)?;
+    if let Some(path) = metrics_file {
+        write_output_file(
+            path,
+            BatchMetrics::from_results(&results)
+                .to_prometheus()
+                .as_bytes(),
+        )?;
+    }
+
+    let failed = results.iter().filter(|r| r.is_err()).count();
+    if failed > 0 {
+        bail!("{} of {} {} queries failed", failed, results.len(), command);
+    }
+    Ok(())
+}
+
+/// Warns on stderr when a follow-up's tools differ from those of the response
+/// it continues. Best-effort: an unknown id or unreadable record says nothing.
+fn warn_on_tool_context_change(options: &RequestOptions) {
+    let Some(id) = &options.previous_response_id else {
+        return;
+    };
+    let Ok(paths) = StatePaths::resolve() else {
+        return;
+    };
+    if let Ok(contexts) = ToolContexts::load(&paths.tool_contexts_file()) {
+        let tools = tool_context(&build_request("", options));
+        if let Some(warning) = contexts.mismatch_warning(id, &tools) {
+            eprintln!("{}", warning);
+        }
+    }
+}
+
+/// Remembers which tools produced these responses; failing to is not an error
+fn record_tool_contexts<'a>(ids: impl IntoIterator<Item = &'a str>, options: &RequestOptions) {
+    let Ok(paths) = StatePaths::resolve() else {
+        return;
+    };
+    let path = paths.tool_contexts_file();
+    let mut contexts = ToolContexts::load(&path).unwrap_or_else(|err| {
+        eprintln!("Warning: {:#}; starting a new tool-context record", err);
+        ToolContexts::default()
+    });
+    let tools = tool_context(&build_request("", options));
+    let mut recorded = false;
+    for id in ids {
+        contexts.record(id, &tools);
+        recorded = true;
+    }
+    if recorded {
+        let _ = contexts.save(&path);
+    }
+}
+
+/// `-o json` output, on one line with `--compact`
+fn json_string(cli: &Cli, value: &impl serde::Serialize) -> Result<String> {
+    Ok(if cli.compact {
+        serde_json::to_string(value)?
+    } else {
+        serde_json::to_string_pretty(value)?
+    })
+}
+
+/// The audit log from `--log-file`, falling back to `GROK_LOG_FILE`, with
+/// requests filed under the subcommand that sends them
+fn audit_log(cli: &Cli, mode: Mode) -> Option<AuditLog> {
+    let path = cli.log_file.clone().or_else(|| {
+        env::var_os(LOG_FILE_ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    })?;
+    let command = match &cli.command {
+        Some(Commands::Repl { .. }) => "repl",
+        Some(Commands::Serve { .. }) => "serve",
+        Some(Commands::Compare { .. }) => "compare",
+        Some(Commands::Batch { .. }) => "batch",
+        Some(Commands::Branch { .. }) => "branch",
+        _ => mode.name(),
+    };
+    Some(AuditLog {
+        path,
+        command: command.to_string(),
+    })
+}
+
+/// Reads prompts from stdin until /quit, EOF, or Ctrl-C, answering each in the same thread
+async fn run_repl(
+    cli: &Cli,
+    options: RequestOptions,
+    web: bool,
+    thread_name: Option<&str>,
+) -> Result<()> {
+    let base = RequestOptions {
+        cassette: None,
+        background: false,
+        ..options
+    };
+    let mut thread = match thread_name {
+        Some(name) => {
+            let path = thread_path(&StatePaths::resolve()?.threads_dir(), name)?;
+            let thread = Thread::load(&path)?;
+            if !thread.turns.is_empty() {
+                eprintln!("Resuming thread {} ({} turns)", name, thread.turns.len());
+            }
+            Some((path, thread))
+        }
+        None => None,
+    };
+    let mut state = ReplState {
+        web,
+        previous_response_id: thread
+            .as_ref()
+            .and_then(|(_, t)| t.last_response_id().map(str::to_string)),
+        ..Default::default()
+    };
+    let format_options = FormatOptions {
+        // With --stream the answer has already been printed as it arrived
+        omit_answer: cli.stream,
+        ..format_options(cli)?
+    };
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+
+    eprintln!("{}", repl::HELP);
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+
+        let line = tokio::select! {
+            line = lines.next_line() => line.context("Failed to read from stdin")?,
+            _ = tokio::signal::ctrl_c() => None,
+        };
+        let Some(line) = line else {
+            println!();
+            return Ok(());
+        };
+
+        let input = ReplInput::parse(&line);
+        match &input {
+            ReplInput::Quit => return Ok(()),
+            ReplInput::Empty => {}
+            ReplInput::Query(query) => {
+                let options = state.options(&base);
+                let interrupt = async {
+                    let _ = tokio::signal::ctrl_c().await;
+                };
+                let Some(result) = repl_turn(query, &options, cli.stream, interrupt).await else {
+                    eprintln!("\nInterrupted.");
+                    continue;
+                };
+                match result {
+                    Ok(response) => {
+                        if response.error.is_none() {
+                            state.previous_response_id = response.id.clone();
+                            if let Some((path, thread)) = &mut thread {
+                                thread.push(query, &response);
+                                thread.save(path)?;
+                            }
+                        }
+                        println!(
+                            "{}",
+                            format_response_with(&response, &cli.output, &format_options)
+                        );
+                    }
+                    Err(e) => eprintln!("Error: {:#}", e),
+                }
+            }
+            _ => {
+                if let Some(message) = state.apply(&input) {
+                    println!("{}", message);
+                }
+            }
+        }
+    }
+}
+
+/// Sends one REPL turn, printing the answer as it arrives with `--stream`.
+/// If `interrupt` resolves first, the in-flight request is dropped and `None`
+/// is returned, so the loop can go straight back to the prompt.
+async fn repl_turn(
+    query: &str,
+    options: &RequestOptions,
+    stream: bool,
+    interrupt: impl Future<Output = ()>,
+) -> Option<Result<GrokResponse>> {
+    let turn = TurnState::Prompting.next(TurnEvent::Submit);
+    let (chunks_tx, chunks) = mpsc::unbounded_channel();
+    // The sender lives as long as the request, so the chunk stream ends when it does
+    let request = async move {
+        if stream {
+            send_streaming(&options.http_client(), query, options, |update| {
+                let _ = chunks_tx.send(update.text.clone());
+            })
+            .await
+        } else {
+            let _open_until_answered = chunks_tx;
+            send_query(query, options).await
+        }
+    };
+    tokio::pin!(request);
+
+    let collect = collect_until_interrupt(chunks, interrupt, |chunk| {
+        print!("{}", chunk);
+        let _ = io::stdout().flush();
+    });
+    tokio::pin!(collect);
+    let mut response = None;
+    let outcome = loop {
+        tokio::select! {
+            outcome = &mut collect => break outcome,
+            result = &mut request, if response.is_none() => response = Some(result),
+        }
+    };
+
+    let turn = turn.next(match outcome {
+        StreamOutcome::Completed(_) => TurnEvent::Finished,
+        StreamOutcome::Interrupted(_) => TurnEvent::Interrupt,
+    });
+    if turn.cancels_request() {
+        return None;
+    }
+    response
+}
+
+fn completion_script(shell: Shell) -> Vec<u8> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "grok-ask", &mut script);
+    script
+}
+
+/// Saves the API key to the config file, keeping anything else already in it
+fn set_api_key(path: &Path, key: &str) -> Result<()> {
+    let key = key.trim();
+    if key.is_empty() {
+        bail!("API key cannot be empty");
+    }
+    let mut config = Config::load(path)?;
+    config.auth.api_key = Some(key.to_string());
+    config.save(path)
+}
+
+async fn fetch_thread_responses(
+    cli: &Cli,
+    client: &reqwest::Client,
+    thread: &Thread,
+) -> Result<Vec<GrokResponse>> {
+    let options = RequestOptions {
+        client: Some(client.clone()),
+        ..connection_options(cli)
+    };
+    let mut responses = Vec::new();
+    for turn in &thread.turns {
+        responses.push(retrieve_response(&turn.response_id, &options).await?);
+    }
+    Ok(responses)
+}
+
+fn transcript_turns<'a>(
+    thread: &'a Thread,
+    responses: &'a [GrokResponse],
+) -> Vec<TranscriptTurn<'a>> {
+    thread
+        .turns
+        .iter()
+        .zip(responses)
+        .map(|(turn, response)| TranscriptTurn {
+            query: &turn.query,
+            response,
+        })
+        .collect()
+}
+
+fn load_existing_thread(name: &str) -> Result<Thread> {
+    let path = thread_path(&StatePaths::resolve()?.threads_dir(), name)?;
+    if !path.exists() {
+        bail!("No thread named {}", name);
+    }
+    Thread::load(&path)
+}
+
+fn format_thread_usage(name: &str, usage: &ThreadUsage, pricing: Option<&Pricing>) -> String {
+    let mut output = format!(
+        "Thread {}: {} turn(s), {} in / {} out tokens\n",
+        name, usage.turns, usage.input_tokens, usage.output_tokens
+    );
+    if let Some(pricing) = pricing {
+        let cost = token_cost(usage.input_tokens, usage.output_tokens, pricing);
+        output.push_str(&format!("Estimated cost: ${:.4}\n", cost));
+    }
+    output
+}
+
+/// Writes the complete output to `path`, creating parent directories as needed
+fn write_output_file(path: &Path, output: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    fs::write(path, output).with_context(|| format!("Failed to write {}", path.display()))?;
+    eprintln!("Wrote {} bytes to {}", output.len(), path.display());
+    Ok(())
+}
+
+/// Prints `output`, or writes it to `path` when --output-file is set
+fn emit_output(path: Option<&Path>, output: &str) -> Result<()> {
+    match path {
+        Some(path) => write_output_file(path, output.as_bytes()),
+        None => {
+            print!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = match parse_cli(env::args_os()) {
+        Ok((cli, inferred)) => {
+            if inferred && !cli.quiet {
+                eprintln!("{}", inferred_format_note(&cli));
+            }
+            cli
+        }
+        Err(err) => {
+            // --help and --version print to stdout and succeed
+            let code = if err.use_stderr() {
+                Outcome::Usage as i32
+            } else {
+                0
+            };
+            let _ = err.print();
+            std::process::exit(code);
+        }
+    };
+    // The REPL handles Ctrl-C itself, between and during turns
+    let result = if matches!(cli.command, Some(Commands::Repl { .. })) {
+        Some(run(cli).await)
+    } else {
+        until_cancelled(run(cli), async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+    };
+    let outcome = match result {
+        None => {
+            eprintln!("\nCancelled.");
+            Outcome::Cancelled
+        }
+        Some(Ok(())) => Outcome::Completed,
+        Some(Err(err)) => {
+            let outcome = Outcome::of_error(&err);
+            if err.downcast_ref::<Outcome>().is_none() {
+                eprintln!("Error: {:?}", err);
+            }
+            outcome
+        }
+    };
+    let _ = io::stdout().flush();
+    std::process::exit(outcome as i32);
+}
+
+/// Runs `work` unless `cancel` resolves first, in which case `work` is dropped
+/// (cancelling its request and erasing any spinner) and `None` is returned
+async fn until_cancelled<T>(
+    work: impl Future<Output = T>,
+    cancel: impl Future<Output = ()>,
+) -> Option<T> {
+    tokio::select! {
+        result = work => Some(result),
+        _ = cancel => None,
+    }
+}
+
+async fn run(mut cli: Cli) -> Result<()> {
+    // Built once and handed to every request through its options, so batch and
+    // branch requests (and follow-ups, judges, polls) share one connection pool
+    let client = build_client()?;
+    check_query(&cli)?;
+
+    if let Some(Commands::Completions { shell }) = &cli.command {
+        io::stdout().write_all(&completion_script(*shell))?;
+        return Ok(());
+    }
+
+    if let Some(Commands::Get {
+        response_id,
+        poll_interval,
+        max_wait,
+    }) = &cli.command
+    {
+        let options = RequestOptions {
+            client: Some(client),
+            ..connection_options(&cli)
+        };
+        let result = poll_response(
+            response_id,
+            &options,
+            Duration::from_secs(*poll_interval),
+            Duration::from_secs(*max_wait),
+        )
+        .await?;
+        let output = format_response_with(&result, &cli.output, &format_options(&cli)?);
+        return emit_output(cli.output_file.as_deref(), &format!("{}\n", output));
+    }
+
+    if let Some(Commands::Thread { command }) = &cli.command {
+        match command {
+            ThreadCommand::Usage { name } => {
+                let thread = load_existing_thread(name)?;
+                let pricing = cli.show_cost.then(Pricing::from_env).transpose()?;
+                print!(
+                    "{}",
+                    format_thread_usage(name, &thread.usage(), pricing.as_ref())
+                );
+            }
+            ThreadCommand::Show { name } => {
+                let thread = load_existing_thread(name)?;
+                let responses = fetch_thread_responses(&cli, &client, &thread).await?;
+                let turns = transcript_turns(&thread, &responses);
+                emit_output(
+                    cli.output_file.as_deref(),
+                    &render_text(&turns, cli.show_roles),
+                )?;
+            }
+            ThreadCommand::ExportHtml { name } => {
+                let thread = load_existing_thread(name)?;
+                let responses = fetch_thread_responses(&cli, &client, &thread).await?;
+                let turns = transcript_turns(&thread, &responses);
+                let path = cli
+                    .output_file
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from(format!("{}.html", name)));
+                write_output_file(&path, render_html(name, &turns, cli.show_roles).as_bytes())?;
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::Config { command }) = &cli.command {
+        match command {
+            ConfigCommand::SetKey { key } => {
+                let key = match key {
+                    Some(key) => key.clone(),
+                    None => {
+                        let mut input = String::new();
+                        io::stdin()
+                            .read_to_string(&mut input)
+                            .context("Failed to read API key from stdin")?;
+                        input
+                    }
+                };
+                let path = StatePaths::resolve()?.config_file();
+                set_api_key(&path, &key)?;
+                eprintln!("Saved API key to {}", path.display());
+            }
+            ConfigCommand::Path => {
+                println!("{}", StatePaths::resolve()?.config_file().display());
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::Cache { command }) = &cli.command {
+        match command {
+            CacheCommand::Clear => {
+                let cache_dir = StatePaths::resolve()?.cache_dir;
+                let mut removed = 0;
+                for name in ["responses", "semantic"] {
+                    removed += ResponseCache::new(cache_dir.join(name)).clear()?;
+                }
+                eprintln!("Removed {} cached response(s)", removed);
+            }
+        }
+        return Ok(());
+    }
+
+    if cli.query.is_some() && cli.command.is_none() {
+        cli.default_mode = Some(default_mode(&StatePaths::resolve()?.config_file())?);
+    }
+
+    let Some(Resolved {
+        mode,
+        query,
+        mut options,
+    }) = resolve_request(&cli)
+    else {
+        eprintln!("No command or query provided. Use --help for usage.");
+        std::process::exit(1);
+    };
+
+    options.client = Some(client);
+    options.audit_log = audit_log(&cli, mode);
+    options.prior_messages = prior_messages(&cli)?;
+
+    check_effort(cli.effort.as_deref(), options.use_reasoning)?;
+    check_country(cli.country.as_deref(), options.use_web_search)?;
+    check_prompt_file(&cli)?;
+    check_then(&cli)?;
+    check_suggest_followups(&cli)?;
+    check_dry_run(&cli)?;
+    check_stream(&cli)?;
+
+    if let Some(config) = &mut options.x_search_config {
+        normalize_dates(config, cli.date_format)?;
+        if let Some(days) = x_search_args(&cli).since_days {
+            apply_since_days(config, days, Date::today())?;
+        }
+        if cli.verify_handles {
+            verify_handles(config)?;
+        }
+    }
+
+    if let Some(Commands::Repl { web, thread }) = &cli.command {
+        if cli.no_store {
+            bail!("--no-store can't be used with repl, whose turns follow up on each other by response id");
+        }
+        return run_repl(&cli, options, *web, thread.as_deref()).await;
+    }
+
+    if let Some(Commands::BatchEstimate { file }) = &cli.command {
+        let estimate = estimate_batch(&read_batch_file(file)?, &options, &Pricing::from_env()?);
+        let output = match cli.output {
+            OutputFormat::Json => format!("{}\n", json_string(&cli, &estimate)?),
+            _ => format_batch_estimate(&estimate),
+        };
+        return emit_output(cli.output_file.as_deref(), &output);
+    }
+
+    if let Some(Commands::CountTokens { .. }) = &cli.command {
+        if cli.lines {
+            bail!("count-tokens estimates a single prompt; --lines doesn't apply");
+        }
+        let query = resolve_queries(&cli, query)?.remove(0);
+        let estimate = estimate_input_tokens(&query, &options);
+        let output = match cli.output {
+            OutputFormat::Json => format!("{}\n", json_string(&cli, &estimate)?),
+            _ => format_token_estimate(&estimate),
+        };
+        return emit_output(cli.output_file.as_deref(), &output);
+    }
+
+    if let Some(Commands::Batch {
+        file,
+        concurrency,
+        metrics_file,
+    }) = &cli.command
+    {
+        // Concurrent requests would draw over each other's spinners
+        let options = RequestOptions {
+            spinner: false,
+            ..options
+        };
+        return run_batch_file(
+            &cli,
+            &options,
+            file,
+            *concurrency as usize,
+            metrics_file.as_deref(),
+        )
+        .await;
+    }
+
+    if let Some(Commands::Branch {
+        queries,
+        concurrency,
+        ..
+    }) = &cli.command
+    {
+        // Branches run side by side, so there's no single request to spin for
+        let options = RequestOptions {
+            spinner: false,
+            ..options
+        };
+        warn_on_tool_context_change(&options);
+        let concurrency = concurrency.map_or(queries.len(), |n| n as usize);
+        return run_grouped(&cli, &options, queries, concurrency, "branch", None).await;
+    }
+
+    if let Some(Commands::Serve { port, .. }) = &cli.command {
+        // Every query is a new request; a cassette or background id can't serve them all
+        let options = RequestOptions {
+            cassette: None,
+            background: false,
+            spinner: false,
+            ..options
+        };
+        return serve(*port, &options).await;
+    }
+
+    if let Some(Commands::Compare {
+        models,
+        judge,
+        judge_model,
+        ..
+    }) = &cli.command
+    {
+        ensure_query_not_empty(&query)?;
+        let judge_model = judge.then_some(judge_model.as_str());
+        let output = run_compare(&cli, &query, &options, models, judge_model).await?;
+        return emit_output(cli.output_file.as_deref(), &output);
+    }
+
+    if cli.ssml && matches!(cli.output, OutputFormat::Json) {
+        bail!("--ssml can't be combined with -o json");
+    }
+    if cli.split_by_headings && matches!(cli.output, OutputFormat::Json) {
+        bail!("--split-by-headings can't be combined with -o json");
+    }
+    if cli.stream && matches!(cli.output, OutputFormat::Json) {
+        bail!("--stream can't be combined with -o json");
+    }
+
+    let queries = resolve_queries(&cli, query)?;
+    if let Some(path) = &cli.image {
+        options.image = Some(load_image(path)?);
+    }
+    if cli.dry_run {
+        return emit_output(
+            cli.output_file.as_deref(),
+            &dry_run(&cli, &queries, &options)?,
+        );
+    }
+    if cli.manifest.is_some() && queries.len() > 1 {
+        bail!(
+            "--manifest records a single query, but {} were given",
+            queries.len()
+        );
+    }
+    let mut budget = match cli.max_total_cost {
+        Some(limit) => Some(CostBudget::new(limit, Pricing::from_env()?)),
+        None => None,
+    };
+    if let Some(then) = &cli.then {
+        return run_then(&cli, mode, &queries, then, &options, budget.as_mut()).await;
+    }
+    let run = |query: String| {
+        let (cli, options) = (&cli, &options);
+        async move { run_query(cli, mode, &query, options).await }
+    };
+    let outcome = match &cli.output_file {
+        Some(path) => {
+            let mut buffer = Vec::new();
+            let outcome = dispatch_queries(&queries, &mut buffer, budget.as_mut(), run).await?;
+            write_output_file(path, &buffer)?;
+            outcome
+        }
+        None => dispatch_queries(&queries, &mut io::stdout(), budget.as_mut(), run).await?,
+    };
+    outcome.into_result()
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
 
-    // Test response parsing
-    mod parse_response {
+    /// Serializes the tests that point `GROK_ASK_HOME` at a scratch dir
+    static HOME: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    /// Keeps the tool-context bookkeeping of sent queries out of the real data
+    /// dir; restores the environment when dropped
+    struct ScratchHome {
+        path: PathBuf,
+        _lock: tokio::sync::MutexGuard<'static, ()>,
+    }
+
+    impl Drop for ScratchHome {
+        fn drop(&mut self) {
+            std::env::remove_var(grok_ask::paths::HOME_ENV);
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    async fn scratch_home(name: &str) -> ScratchHome {
+        let lock = HOME.lock().await;
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        std::env::set_var(grok_ask::paths::HOME_ENV, &path);
+        ScratchHome { path, _lock: lock }
+    }
+
+    // Test query validation
+    mod query_validation {
+        use super::*;
+
+        #[test]
+        fn test_empty_and_whitespace_queries_rejected() {
+            assert!(ensure_query_not_empty("").is_err());
+            assert!(ensure_query_not_empty("   \n\t ").is_err());
+
+            let err = ensure_query_not_empty("").unwrap_err().to_string();
+            assert!(err.contains("Query is empty"));
+        }
+
+        #[test]
+        fn test_non_empty_query_accepted() {
+            assert!(ensure_query_not_empty("what is rust").is_ok());
+            assert!(ensure_query_not_empty("  padded  ").is_ok());
+        }
+    }
+
+    // Test temperature validation
+    mod temperature {
+        use super::*;
+
+        #[test]
+        fn test_temperature_range() {
+            assert_eq!(parse_temperature("0"), Ok(0.0));
+            assert_eq!(parse_temperature("2.0"), Ok(2.0));
+            assert!(parse_temperature("2.1")
+                .unwrap_err()
+                .contains("between 0.0 and 2.0"));
+            assert!(parse_temperature("-0.5").is_err());
+            assert!(parse_temperature("warm")
+                .unwrap_err()
+                .contains("not a number"));
+        }
+
+        #[test]
+        fn test_temperature_flag_reaches_request() {
+            let cli =
+                Cli::try_parse_from(["grok-ask", "chat", "hi", "--temperature", "0.7"]).unwrap();
+            let resolved = resolve_request(&cli).unwrap();
+            assert_eq!(
+                build_request(&resolved.query, &resolved.options).temperature,
+                Some(0.7)
+            );
+            assert!(Cli::try_parse_from(["grok-ask", "chat", "hi", "--temperature", "3"]).is_err());
+        }
+    }
+
+    // Test answer length targeting
+    mod length_target {
+        use super::*;
+
+        #[test]
+        fn test_within_tolerance_is_quiet() {
+            assert!(word_target_warning(200, 200).is_none());
+            assert!(word_target_warning(200, 250).is_none());
+            assert!(word_target_warning(200, 150).is_none());
+        }
+
+        #[test]
+        fn test_deviation_beyond_tolerance_warns() {
+            let warning = word_target_warning(200, 300).unwrap();
+            assert!(warning.contains("300 words"));
+            assert!(warning.contains("50% over"));
+
+            let warning = word_target_warning(200, 100).unwrap();
+            assert!(warning.contains("50% under"));
+        }
+
+        #[test]
+        fn test_target_words_added_to_system_message() {
+            let cli =
+                Cli::try_parse_from(["grok-ask", "chat", "hi", "--target-words", "120"]).unwrap();
+            let instruction = resolve_request(&cli)
+                .unwrap()
+                .options
+                .system_instruction
+                .unwrap();
+            assert!(instruction.contains("Aim for about 120 words."));
+            assert!(
+                Cli::try_parse_from(["grok-ask", "chat", "hi", "--target-words", "0"]).is_err()
+            );
+        }
+    }
+
+    // Test stdin/argument conflicts
+    mod stdin_conflict {
+        use super::*;
+
+        #[test]
+        fn test_piped_stdin_with_argument_is_flagged() {
+            assert!(ignores_piped_stdin("what is rust", true));
+        }
+
+        #[test]
+        fn test_no_conflict_without_pipe_or_with_dash() {
+            assert!(!ignores_piped_stdin("what is rust", false));
+            assert!(!ignores_piped_stdin("-", true));
+            assert!(!ignores_piped_stdin("-", false));
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn test_only_pipes_and_non_empty_files_carry_data() {
+            let path = std::env::temp_dir().join(format!("grok-ask-stdin-{}", std::process::id()));
+            fs::write(&path, "").unwrap();
+            assert!(!carries_data(&fs::metadata(&path).unwrap()));
+            fs::write(&path, "what is rust").unwrap();
+            assert!(carries_data(&fs::metadata(&path).unwrap()));
+            fs::remove_file(&path).unwrap();
+
+            // What cron and nohup hand a command
+            assert!(!carries_data(&fs::metadata("/dev/null").unwrap()));
+        }
+    }
+
+    // Test forbidden content patterns
+    mod forbid {
+        use super::*;
+
+        fn patterns(sources: &[&str]) -> Vec<Regex> {
+            sources.iter().map(|s| Regex::new(s).unwrap()).collect()
+        }
+
+        #[test]
+        fn test_matching_patterns_reported() {
+            let patterns = patterns(&[r"(?i)as an ai", r"\bpassword\b", r"lorem"]);
+            let matched = forbidden_matches("As an AI, I can't share the password.", &patterns);
+            let matched: Vec<&str> = matched.iter().map(|p| p.as_str()).collect();
+            assert_eq!(matched, vec![r"(?i)as an ai", r"\bpassword\b"]);
+        }
+
+        #[test]
+        fn test_clean_answer_passes() {
+            let patterns = patterns(&[r"\bpassword\b"]);
+            assert!(forbidden_matches("Use a passwords manager.", &patterns).is_empty());
+            assert!(forbidden_matches("anything", &[]).is_empty());
+        }
+
+        #[test]
+        fn test_invalid_regex_rejected_at_parse() {
+            assert!(Cli::try_parse_from(["grok-ask", "--forbid", "(", "ask", "q"]).is_err());
+            let cli =
+                Cli::try_parse_from(["grok-ask", "--forbid", "a", "--forbid", "b", "ask", "q"])
+                    .unwrap();
+            assert_eq!(cli.forbid.len(), 2);
+        }
+    }
+
+    // Test --output-file
+    mod output_file {
+        use super::*;
+        use std::env;
+
+        #[test]
+        fn test_writes_file_and_creates_parents() {
+            let dir = env::temp_dir().join("grok-ask-output-file-test");
+            let _ = fs::remove_dir_all(&dir);
+            let path = dir.join("nested").join("result.json");
+
+            write_output_file(&path, b"{\"ok\":true}\n").unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), "{\"ok\":true}\n");
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn test_output_flags_accepted_after_subcommand() {
+            let cli = Cli::try_parse_from([
+                "grok-ask",
+                "ask",
+                "q",
+                "--output",
+                "json",
+                "--output-file",
+                "result.json",
+            ])
+            .unwrap();
+            assert!(matches!(cli.output, OutputFormat::Json));
+            assert_eq!(cli.output_file, Some(PathBuf::from("result.json")));
+        }
+
+        #[test]
+        fn test_format_inferred_from_output_file() {
+            let format = |args: &[&str]| parse_cli(args).unwrap().0.output;
+            assert!(matches!(
+                format(&["grok-ask", "ask", "q", "--output-file", "result.json"]),
+                OutputFormat::Json
+            ));
+            assert!(matches!(
+                format(&["grok-ask", "--output-file", "notes.md", "ask", "q"]),
+                OutputFormat::Markdown
+            ));
+            assert!(matches!(
+                format(&["grok-ask", "ask", "q", "--output-file", "notes.txt"]),
+                OutputFormat::Text
+            ));
+            assert!(matches!(
+                format(&["grok-ask", "ask", "q"]),
+                OutputFormat::Text
+            ));
+
+            let (cli, inferred) =
+                parse_cli(["grok-ask", "ask", "q", "--output-file", "notes.txt"]).unwrap();
+            assert!(inferred);
+            assert_eq!(
+                inferred_format_note(&cli),
+                "Writing text output to notes.txt (from its extension; --output overrides)"
+            );
+
+            // Fixed-format commands write what they write, whatever the extension
+            let (cli, inferred) = parse_cli([
+                "grok-ask",
+                "thread",
+                "export-html",
+                "t",
+                "--output-file",
+                "t.html",
+            ])
+            .unwrap();
+            assert!(!inferred);
+            assert!(matches!(cli.output, OutputFormat::Text));
+        }
+
+        #[test]
+        fn test_explicit_output_beats_extension() {
+            for args in [
+                &[
+                    "grok-ask",
+                    "ask",
+                    "q",
+                    "-o",
+                    "plain",
+                    "--output-file",
+                    "result.json",
+                ][..],
+                &[
+                    "grok-ask",
+                    "-o",
+                    "plain",
+                    "ask",
+                    "q",
+                    "--output-file",
+                    "result.json",
+                ],
+                &[
+                    "grok-ask",
+                    "--output-file",
+                    "result.json",
+                    "--output",
+                    "plain",
+                    "--ask",
+                    "q",
+                ],
+            ] {
+                assert!(
+                    matches!(parse_cli(args).unwrap().0.output, OutputFormat::Plain),
+                    "{:?}",
+                    args
+                );
+            }
+            // Naming the default explicitly still counts as a choice
+            let cli = parse_cli([
+                "grok-ask",
+                "ask",
+                "q",
+                "-o",
+                "text",
+                "--output-file",
+                "r.md",
+            ]);
+            let (cli, inferred) = cli.unwrap();
+            assert!(matches!(cli.output, OutputFormat::Text));
+            assert!(!inferred);
+        }
+    }
+
+    // Test source count gate
+    mod source_gate {
+        use super::*;
+
+        #[test]
+        fn test_gate_fires_below_threshold() {
+            let err = check_min_sources(1, 3).unwrap_err().to_string();
+            assert!(err.contains("1 source(s)"));
+            assert!(err.contains("--min-source-count 3"));
+        }
+
+        #[test]
+        fn test_gate_passes_at_or_above_threshold() {
+            assert!(check_min_sources(3, 3).is_ok());
+            assert!(check_min_sources(5, 3).is_ok());
+            assert!(check_min_sources(0, 0).is_ok());
+        }
+    }
+
+    // Test long-line detection
+    mod long_output {
+        use super::*;
+
+        #[test]
+        fn test_long_single_line_warns() {
+            let giant = "x".repeat(20_000);
+            let warning = long_line_warning(&giant, 10_000).unwrap();
+            assert!(warning.contains("20000-character line"));
+            assert!(warning.contains("--wrap"));
+        }
+
+        #[test]
+        fn test_normal_output_does_not_warn() {
+            let normal = "a short answer\n".repeat(1_000);
+            assert!(long_line_warning(&normal, 10_000).is_none());
+        }
+    }
+
+    // Test retry flags
+    mod retry_flags {
+        use super::*;
+
+        fn retries(args: &[&str]) -> u32 {
+            let cli = Cli::try_parse_from(args).unwrap();
+            resolve_request(&cli).unwrap().options.retry.max_retries
+        }
+
+        #[test]
+        fn test_retry_flags() {
+            assert_eq!(retries(&["grok-ask", "ask", "q"]), 3);
+            assert_eq!(retries(&["grok-ask", "ask", "q", "--max-retries", "5"]), 5);
+            assert_eq!(retries(&["grok-ask", "--no-retry", "ask", "q"]), 0);
+            assert!(Cli::try_parse_from([
+                "grok-ask",
+                "--no-retry",
+                "--max-retries",
+                "2",
+                "ask",
+                "q"
+            ])
+            .is_err());
+        }
+
+        #[test]
+        fn test_retry_codes_parsed_and_validated() {
+            let cli = Cli::try_parse_from(["grok-ask", "--retry-codes", "429,500,529", "ask", "q"])
+                .unwrap();
+            let policy = resolve_request(&cli).unwrap().options.retry;
+            assert_eq!(policy.retry_codes, vec![429, 500, 529]);
+            assert!(policy.should_retry(529));
+            assert!(!policy.should_retry(503));
+
+            for bad in ["abc", "99", "600", "429,,500"] {
+                assert!(
+                    Cli::try_parse_from(["grok-ask", "--retry-codes", bad, "ask", "q"]).is_err(),
+                    "{bad} should be rejected"
+                );
+            }
+        }
+
+        #[tokio::test]
+        async fn test_rate_limited_request_is_retried() {
+            let mut server = mockito::Server::new_async().await;
+            let limited = server
+                .mock("POST", "/v1/responses")
+                .with_status(429)
+                .with_header("retry-after", "0")
+                .with_body(r#"{"error": {"message": "slow down"}}"#)
+                .expect(1)
+                .create_async()
+                .await;
+            let answered = server
+                .mock("POST", "/v1/responses")
+                .with_body(r#"{"id": "resp_retried", "status": "completed", "output": []}"#)
+                .expect(1)
+                .create_async()
+                .await;
+
+            let cli = Cli::try_parse_from(["grok-ask", "--max-retries", "1", "ask", "q"]).unwrap();
+            let mut options = resolve_request(&cli).unwrap().options;
+            options.endpoint = Some(server.url());
+            options.api_key = Some("test-key".to_string());
+            options.retry.initial_delay = Duration::from_millis(1);
+            options.spinner = false;
+
+            let response = send_query("q", &options).await.unwrap();
+            assert_eq!(response.id.as_deref(), Some("resp_retried"));
+            limited.assert_async().await;
+            answered.assert_async().await;
+        }
+    }
+
+    // Test date normalization
+    mod dates {
+        use super::*;
+
+        #[test]
+        fn test_dates_normalized_to_iso() {
+            let mut config = XSearchConfig {
+                from_date: Some("12/13/2025".to_string()),
+                to_date: Some("24.12.2025".to_string()),
+                ..Default::default()
+            };
+            normalize_dates(&mut config, None).unwrap();
+            assert_eq!(config.from_date.as_deref(), Some("2025-12-13"));
+            assert_eq!(config.to_date.as_deref(), Some("2025-12-24"));
+        }
+
+        #[test]
+        fn test_date_format_flag_and_range_check() {
+            let mut config = XSearchConfig {
+                from_date: Some("02/01/2025".to_string()),
+                to_date: Some("01/02/2025".to_string()),
+                ..Default::default()
+            };
+            assert!(normalize_dates(&mut config.clone(), None).is_err());
+            assert!(normalize_dates(&mut config.clone(), Some(DateFormat::Us)).is_err());
+            normalize_dates(&mut config, Some(DateFormat::Eu)).unwrap();
+            assert_eq!(config.from_date.as_deref(), Some("2025-01-02"));
+        }
+
+        #[test]
+        fn test_since_days_flag() {
+            for args in [
+                &["grok-ask", "x-search", "q", "--since-days", "7"][..],
+                &["grok-ask", "x-ask", "q", "--since-days", "7"],
+                &["grok-ask", "--x-ask", "q", "--since-days", "7"],
+            ] {
+                let cli = Cli::try_parse_from(args).unwrap();
+                assert_eq!(x_search_args(&cli).since_days, Some(7), "{:?}", args);
+            }
+            assert!(Cli::try_parse_from([
+                "grok-ask",
+                "x-search",
+                "q",
+                "--since-days",
+                "7",
+                "--from-date",
+                "2025-01-01"
+            ])
+            .is_err());
+        }
+    }
+
+    mod model_selection {
+        use super::*;
+        use grok_ask::{MODEL, REASONING_MODEL};
+
+        fn request_for(args: &[&str]) -> grok_ask::GrokRequest {
+            let cli = Cli::try_parse_from(args).unwrap();
+            let resolved = resolve_request(&cli).unwrap();
+            build_request(&resolved.query, &resolved.options)
+        }
+
+        #[test]
+        fn test_max_tokens_overrides_command_default() {
+            assert_eq!(
+                request_for(&["grok-ask", "think", "why?"]).max_output_tokens,
+                Some(16384)
+            );
+            assert_eq!(
+                request_for(&["grok-ask", "--max-tokens", "32000", "think", "why?"])
+                    .max_output_tokens,
+                Some(32000)
+            );
+            assert_eq!(
+                request_for(&["grok-ask", "--search", "news", "--max-tokens", "512"])
+                    .max_output_tokens,
+                Some(512)
+            );
+            assert!(Cli::try_parse_from(["grok-ask", "--max-tokens", "0", "ask", "q"]).is_err());
+            assert!(Cli::try_parse_from(["grok-ask", "--max-tokens", "-5", "ask", "q"]).is_err());
+        }
+
+        #[test]
+        fn test_model_flag_overrides_default() {
+            let request = request_for(&[
+                "grok-ask",
+                "think",
+                "why?",
+                "--model",
+                "grok-4-1-fast-reasoning",
+            ]);
+            assert_eq!(request.model, "grok-4-1-fast-reasoning");
+
+            let request = request_for(&[
+                "grok-ask",
+                "--model",
+                "grok-4",
+                "--no-reasoning",
+                "--ask",
+                "q",
+            ]);
+            assert_eq!(request.model, "grok-4");
+
+            let request = request_for(&["grok-ask", "ask", "q"]);
+            assert_eq!(request.model, MODEL);
+        }
+
+        #[test]
+        fn test_think_uses_reasoning_model() {
+            let request = request_for(&["grok-ask", "think", "why?"]);
+            assert_eq!(request.model, REASONING_MODEL);
+        }
+
+        #[test]
+        fn test_handle_flags_normalized() {
+            let request = request_for(&[
+                "grok-ask",
+                "--x-ask",
+                "q",
+                "--allowed-handles",
+                "@elonmusk, @xai",
+                "--excluded-handles",
+                "@spam,",
+            ]);
+            let json = serde_json::to_string(&request).unwrap();
+            assert!(json.contains("\"allowed_x_handles\":[\"elonmusk\",\"xai\"]"));
+            assert!(json.contains("\"excluded_x_handles\":[\"spam\"]"));
+        }
+
+        #[test]
+        fn test_x_think_uses_reasoning_model_with_x_search() {
+            let request = request_for(&[
+                "grok-ask",
+                "x-think",
+                "what are people saying?",
+                "--allowed-handles",
+                "xai",
+            ]);
+            assert_eq!(request.model, REASONING_MODEL);
+            assert_eq!(request.max_output_tokens, Some(16384));
+
+            let json = serde_json::to_string(&request).unwrap();
+            assert!(json.contains("\"type\":\"x_search\""));
+            assert!(json.contains("\"allowed_x_handles\":[\"xai\"]"));
+            assert!(!json.contains("\"type\":\"web_search\""));
+            assert!(json.contains("Think step by step over X discussions"));
+
+            let request = request_for(&["grok-ask", "--x-think", "q", "--from-date", "2025-01-01"]);
+            assert_eq!(request.model, REASONING_MODEL);
+            assert!(serde_json::to_string(&request)
+                .unwrap()
+                .contains("\"from_date\":\"2025-01-01\""));
+        }
+
+        #[test]
+        fn test_effort_sent_only_for_reasoning() {
+            let request = request_for(&["grok-ask", "--effort", "high", "think", "why?"]);
+            let json = serde_json::to_value(&request).unwrap();
+            assert_eq!(json["reasoning_effort"], "high");
+
+            let request = request_for(&["grok-ask", "think", "why?"]);
+            assert!(serde_json::to_value(&request)
+                .unwrap()
+                .get("reasoning_effort")
+                .is_none());
+
+            let request = request_for(&["grok-ask", "--effort", "low", "search", "news"]);
+            assert!(serde_json::to_value(&request)
+                .unwrap()
+                .get("reasoning_effort")
+                .is_none());
+
+            let cli = Cli::try_parse_from(["grok-ask", "--effort", "low", "chat", "hi"]).unwrap();
+            let resolved = resolve_request(&cli).unwrap();
+            assert!(check_effort(cli.effort.as_deref(), resolved.options.use_reasoning).is_err());
+            assert!(check_effort(Some("low"), true).is_ok());
+            assert!(Cli::try_parse_from(["grok-ask", "--effort", "max", "think", "q"]).is_err());
+        }
+
+        #[test]
+        fn test_think_no_reasoning_uses_fast_model() {
+            let request = request_for(&["grok-ask", "think", "why?", "--no-reasoning"]);
+            assert_eq!(request.model, MODEL);
+
+            let request = request_for(&["grok-ask", "--no-reasoning", "--think", "why?"]);
+            assert_eq!(request.model, MODEL);
+        }
+    }
+
+    // Test newline-delimited stdin queries
+    mod lines {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_dispatch_stops_at_cost_budget() {
+            let queries: Vec<String> = (1..=5).map(|i| format!("q{}", i)).collect();
+            let mut budget = CostBudget::new(0.001, Pricing::default());
+            let mut out = Vec::new();
+
+            // 1000 in / 1000 out costs $0.0007 at default pricing
+            dispatch_queries(&queries, &mut out, Some(&mut budget), |query| async move {
+                Ok(Answered {
+                    output: query,
+                    usage: Some(Usage {
+                        input_tokens: Some(1000),
+                        output_tokens: Some(1000),
+                    }),
+                    outcome: Outcome::Completed,
+                    response_id: None,
+                })
+            })
+            .await
+            .unwrap();
+
+            assert_eq!(budget.completed, 1);
+            assert_eq!(String::from_utf8(out).unwrap(), "q1\n");
+        }
+
+        #[test]
+        fn test_split_query_lines() {
+            let input = "first question\n\n  second question  \r\n\t\nthird";
+            assert_eq!(
+                split_query_lines(input),
+                vec!["first question", "second question", "third"]
+            );
+        }
+
+        #[tokio::test]
+        async fn test_dispatch_runs_each_query_in_order() {
+            let queries = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+            let mut seen = Vec::new();
+            let mut out = Vec::new();
+
+            dispatch_queries(&queries, &mut out, None, |query| {
+                seen.push(query.clone());
+                async move {
+                    Ok(Answered {
+                        output: format!("answer to {}", query),
+                        usage: None,
+                        outcome: Outcome::Completed,
+                        response_id: None,
+                    })
+                }
+            })
+            .await
+            .unwrap();
+
+            assert_eq!(seen, queries);
+            let out = String::from_utf8(out).unwrap();
+            assert_eq!(
+                out,
+                format!(
+                    "answer to a\n{0}\nanswer to b\n{0}\nanswer to c\n",
+                    ANSWER_SEPARATOR
+                )
+            );
+        }
+    }
+
+    // Test completion scripts
+    mod completions {
         use super::*;
 
-        fn make_response(text: &str, sources: Vec<(&str, &str)>) -> GrokResponse {
-            let results: Vec<WebSearchResult> = sources
-                .iter()
-                .map(|(title, url)| WebSearchResult {
-                    title: Some(title.to_string()),
-                    url: Some(url.to_string()),
-                })
-                .collect();
+        #[test]
+        fn test_scripts_cover_subcommands_and_flags() {
+            for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+                let script = String::from_utf8(completion_script(shell)).unwrap();
+                assert!(script.contains("grok-ask"), "{:?}", shell);
+                assert!(script.contains("allowed-handles"), "{:?}", shell);
+                assert!(script.contains("x-think"), "{:?}", shell);
+            }
+            assert!(Cli::try_parse_from(["grok-ask", "completions", "tcsh"]).is_err());
+        }
+    }
+
+    // Test --dry-run
+    mod dry_run {
+        use super::*;
 
-            GrokResponse {
-                id: Some("resp_123".to_string()),
-                status: Some("completed".to_string()),
-                output: Some(vec![
-                    Output {
-                        r#type: "web_search_result".to_string(),
-                        content: None,
-                        results: Some(results),
-                    },
-                    Output {
-                        r#type: "message".to_string(),
-                        content: Some(vec![Content {
-                            r#type: "output_text".to_string(),
-                            text: Some(text.to_string()),
-                            annotations: None,
-                        }]),
-                        results: None,
-                    },
-                ]),
-                usage: None,
-                error: None,
+        #[test]
+        fn test_dry_run_rejects_commands_that_send_their_own_requests() {
+            for args in [
+                &["grok-ask", "--dry-run", "compare", "q"][..],
+                &["grok-ask", "compare", "q", "--dry-run"],
+                &["grok-ask", "--dry-run", "serve"],
+                &["grok-ask", "--dry-run", "repl"],
+            ] {
+                let cli = Cli::try_parse_from(args).unwrap();
+                assert!(check_dry_run(&cli)
+                    .unwrap_err()
+                    .to_string()
+                    .starts_with("--dry-run can't be used with"));
             }
+            let cli = Cli::try_parse_from(["grok-ask", "--dry-run", "ask", "q"]).unwrap();
+            assert!(check_dry_run(&cli).is_ok());
         }
 
         #[test]
-        fn test_format_simple_response() {
-            let response = GrokResponse {
-                id: Some("resp_123".to_string()),
-                status: Some("completed".to_string()),
-                output: Some(vec![Output {
-                    r#type: "message".to_string(),
-                    content: Some(vec![Content {
-                        r#type: "output_text".to_string(),
-                        text: Some("Hello, world!".to_string()),
-                        annotations: None,
-                    }]),
-                    results: None,
-                }]),
-                usage: None,
-                error: None,
-            };
+        fn test_dry_run_prints_full_request() {
+            let cli = Cli::try_parse_from([
+                "grok-ask",
+                "--dry-run",
+                "--temperature",
+                "0.2",
+                "--adaptive-tokens",
+                "search",
+                "latest AI news",
+            ])
+            .unwrap();
+            let resolved = resolve_request(&cli).unwrap();
+            let output = dry_run(
+                &cli,
+                std::slice::from_ref(&resolved.query),
+                &resolved.options,
+            )
+            .unwrap();
 
-            let output = format_response(&response, &OutputFormat::Text);
-            assert!(output.contains("Hello, world!"));
-            assert!(output.contains("response_id: resp_123"));
+            let request: serde_json::Value = serde_json::from_str(&output).unwrap();
+            assert_eq!(request["model"], grok_ask::MODEL);
+            assert_eq!(request["input"][0]["role"], "system");
+            assert_eq!(request["input"][1]["content"], "latest AI news");
+            assert_eq!(request["tools"][0]["type"], "web_search");
+            assert_eq!(request["temperature"], 0.2);
+            assert_eq!(
+                request["max_output_tokens"],
+                adaptive_token_budget("latest AI news", false)
+            );
         }
+    }
+
+    // Test image attachment
+    mod image_input {
+        use super::*;
 
         #[test]
-        fn test_format_response_with_sources() {
-            let response = make_response(
-                "Found results.",
-                vec![("News", "https://news.com"), ("Blog", "https://blog.com")],
+        fn test_image_url_attached_as_input_image() {
+            let cli = Cli::try_parse_from([
+                "grok-ask",
+                "ask",
+                "What is in this photo?",
+                "--image-url",
+                "https://example.com/cat.jpg",
+            ])
+            .unwrap();
+            let resolved = resolve_request(&cli).unwrap();
+            let json =
+                serde_json::to_value(build_request(&resolved.query, &resolved.options)).unwrap();
+            let content = json["input"].as_array().unwrap().last().unwrap()["content"].clone();
+            assert_eq!(
+                content,
+                serde_json::json!([
+                    {"type": "input_text", "text": "What is in this photo?"},
+                    {"type": "input_image", "image_url": "https://example.com/cat.jpg"},
+                ])
             );
 
-            let output = format_response(&response, &OutputFormat::Text);
-            assert!(output.contains("Found results."));
-            assert!(output.contains("Sources:"));
-            assert!(output.contains("[News](https://news.com)"));
-            assert!(output.contains("[Blog](https://blog.com)"));
+            assert!(
+                Cli::try_parse_from(["grok-ask", "--image-url", "cat.jpg", "chat", "q"]).is_err()
+            );
+            assert!(Cli::try_parse_from([
+                "grok-ask",
+                "--image",
+                "a.png",
+                "--image-url",
+                "https://example.com/b.png",
+                "chat",
+                "q"
+            ])
+            .is_err());
         }
 
         #[test]
-        fn test_format_error_response() {
-            let response = GrokResponse {
-                id: None,
-                status: Some("failed".to_string()),
-                output: None,
-                usage: None,
-                error: Some(ApiError {
-                    message: Some("Rate limit exceeded".to_string()),
-                    code: Some("429".to_string()),
-                }),
-            };
+        fn test_image_and_query_cannot_both_read_stdin() {
+            let cli = Cli::try_parse_from(["grok-ask", "--image", "-", "ask", "-"]).unwrap();
+            let err = resolve_queries(&cli, "-".to_string()).unwrap_err();
+            assert!(err.to_string().contains("both read stdin"));
+        }
+    }
 
-            let output = format_response(&response, &OutputFormat::Text);
-            assert!(output.contains("Error: Rate limit exceeded"));
+    // Test config set-key
+    mod config_set_key {
+        use super::*;
+
+        #[test]
+        fn test_set_key_parses_and_writes_trimmed_key() {
+            let cli = Cli::try_parse_from(["grok-ask", "config", "set-key", "xai-1"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Some(Commands::Config {
+                    command: ConfigCommand::SetKey { key: Some(ref k) }
+                }) if k == "xai-1"
+            ));
+
+            let dir = std::env::temp_dir().join("grok-ask-set-key-test");
+            let _ = fs::remove_dir_all(&dir);
+            let path = dir.join("config.toml");
+
+            set_api_key(&path, "  xai-1\n").unwrap();
+            assert_eq!(Config::load(&path).unwrap().api_key(), Some("xai-1"));
+            assert!(set_api_key(&path, "\n").is_err());
+
+            fs::remove_dir_all(&dir).unwrap();
         }
 
         #[test]
-        fn test_format_json_output() {
-            let response = GrokResponse {
-                id: Some("resp_json".to_string()),
-                status: Some("completed".to_string()),
-                output: Some(vec![]),
-                usage: None,
-                error: None,
-            };
+        fn test_config_path_parses() {
+            let cli = Cli::try_parse_from(["grok-ask", "config", "path"]).unwrap();
+            assert!(matches!(
+                cli.command,
+                Some(Commands::Config {
+                    command: ConfigCommand::Path
+                })
+            ));
+        }
+    }
+
+    // Test response cache flags
+    mod response_cache {
+        use super::*;
 
-            let output = format_response(&response, &OutputFormat::Json);
-            assert!(output.contains("\"id\": \"resp_json\""));
-            assert!(output.contains("\"status\": \"completed\""));
+        #[test]
+        fn test_cache_ttl_parsing() {
+            assert_eq!(parse_ttl("90"), Ok(Duration::from_secs(90)));
+            assert_eq!(parse_ttl("30m"), Ok(Duration::from_secs(1800)));
+            assert_eq!(parse_ttl("12h"), Ok(Duration::from_secs(43200)));
+            assert_eq!(parse_ttl("7d"), Ok(Duration::from_secs(604800)));
+            assert!(parse_ttl("1w").unwrap_err().contains("unknown unit"));
+            assert!(parse_ttl("h").is_err());
+            assert!(parse_ttl("99999999999999999999d").is_err());
+            assert!(parse_ttl("999999999999999999d")
+                .unwrap_err()
+                .contains("too long"));
         }
 
         #[test]
-        fn test_x_search_result_parsing() {
-            let response = GrokResponse {
-                id: Some("resp_x".to_string()),
-                status: Some("completed".to_string()),
-                output: Some(vec![
-                    Output {
-                        r#type: "x_search_result".to_string(),
-                        content: None,
-                        results: Some(vec![WebSearchResult {
-                            title: Some("@user".to_string()),
-                            url: Some("https://x.com/user/status/123".to_string()),
-                        }]),
-                    },
-                    Output {
-                        r#type: "message".to_string(),
-                        content: Some(vec![Content {
-                            r#type: "output_text".to_string(),
-                            text: Some("X post found.".to_string()),
-                            annotations: None,
-                        }]),
-                        results: None,
-                    },
-                ]),
-                usage: None,
-                error: None,
-            };
+        fn test_cache_flags() {
+            let cli = Cli::try_parse_from(["grok-ask", "--cache", "ask", "q"]).unwrap();
+            assert!(cli.cache);
+            assert_eq!(cli.cache_ttl, Duration::from_secs(86400));
 
-            let output = format_response(&response, &OutputFormat::Text);
-            assert!(output.contains("X post found."));
-            assert!(output.contains("[@user](https://x.com/user/status/123)"));
+            assert!(Cli::try_parse_from(["grok-ask", "--cache-ttl", "1h", "ask", "q"]).is_err());
+            assert!(
+                Cli::try_parse_from(["grok-ask", "--cache", "--background", "ask", "q"]).is_err()
+            );
+            assert!(matches!(
+                Cli::try_parse_from(["grok-ask", "cache", "clear"])
+                    .unwrap()
+                    .command,
+                Some(Commands::Cache {
+                    command: CacheCommand::Clear
+                })
+            ));
         }
     }
 
-    // Test request serialization
-    mod serialize_request {
+    // Test system instruction overrides
+    mod system_override {
         use super::*;
 
+        fn instruction(args: &[&str]) -> Option<String> {
+            let cli = Cli::try_parse_from(args).unwrap();
+            resolve_request(&cli).unwrap().options.system_instruction
+        }
+
         #[test]
-        fn test_web_search_tool_serialization() {
-            let tool = Tool::WebSearch(WebSearchTool {
-                r#type: "web_search".to_string(),
-                enable_image_understanding: None,
-            });
+        fn test_system_replaces_and_append_system_extends() {
+            let preset = instruction(&["grok-ask", "ask", "q"]).unwrap();
 
-            let json = serde_json::to_string(&tool).unwrap();
-            assert!(json.contains("\"type\":\"web_search\""));
-            assert!(!json.contains("enable_image_understanding"));
+            assert_eq!(
+                instruction(&["grok-ask", "--system", "Be a pirate.", "ask", "q"]).as_deref(),
+                Some("Be a pirate.")
+            );
+            assert_eq!(
+                instruction(&["grok-ask", "--append-system", "Be a pirate.", "ask", "q"]),
+                Some(format!("{}\n\nBe a pirate.", preset))
+            );
+            // chat has no built-in instruction to replace
+            assert_eq!(instruction(&["grok-ask", "chat", "q"]), None);
+            assert_eq!(
+                instruction(&["grok-ask", "--system", "Be a pirate.", "chat", "q"]).as_deref(),
+                Some("Be a pirate.")
+            );
         }
 
         #[test]
-        fn test_x_search_tool_serialization() {
-            let tool = Tool::XSearch(XSearchTool {
-                r#type: "x_search".to_string(),
-                allowed_x_handles: Some(vec!["user1".to_string(), "user2".to_string()]),
-                excluded_x_handles: None,
-                from_date: Some("2025-01-01".to_string()),
-                to_date: Some("2025-01-15".to_string()),
-                enable_image_understanding: Some(true),
-                enable_video_understanding: Some(true),
-            });
+        fn test_system_file() {
+            let path = std::env::temp_dir().join("grok-ask-system-file.txt");
+            fs::write(&path, "From a file.\n").unwrap();
+            let path = path.to_str().unwrap();
 
-            let json = serde_json::to_string(&tool).unwrap();
-            assert!(json.contains("\"type\":\"x_search\""));
-            assert!(json.contains("\"allowed_x_handles\":[\"user1\",\"user2\"]"));
-            assert!(json.contains("\"from_date\":\"2025-01-01\""));
-            assert!(json.contains("\"to_date\":\"2025-01-15\""));
-            assert!(json.contains("\"enable_image_understanding\":true"));
-            assert!(json.contains("\"enable_video_understanding\":true"));
+            assert_eq!(
+                instruction(&["grok-ask", "--system-file", path, "think", "q"]).as_deref(),
+                Some("From a file.")
+            );
+            assert!(Cli::try_parse_from([
+                "grok-ask",
+                "--system",
+                "x",
+                "--system-file",
+                path,
+                "ask",
+                "q"
+            ])
+            .is_err());
+            assert!(
+                Cli::try_parse_from(["grok-ask", "--system-file", "/nonexistent", "ask", "q"])
+                    .is_err()
+            );
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    // Test exit codes
+    mod exit_codes {
+        use super::*;
+
+        fn response(json: &str) -> GrokResponse {
+            serde_json::from_str(json).unwrap()
+        }
+
+        #[test]
+        fn test_outcome_of_response() {
+            assert_eq!(
+                Outcome::of(&response(
+                    r#"{"id": "r", "status": "completed", "output": [{"type": "message", "content": [{"type": "output_text", "text": "Hi"}]}]}"#
+                )),
+                Outcome::Completed
+            );
+            assert_eq!(
+                Outcome::of(&response(
+                    r#"{"id": "r", "status": "completed", "output": []}"#
+                )),
+                Outcome::Empty
+            );
+            assert_eq!(
+                Outcome::of(&response(r#"{"id": "r", "error": {"message": "bad key"}}"#)),
+                Outcome::ApiError
+            );
+            assert_eq!(
+                Outcome::of(&response(
+                    r#"{"id": "r", "status": "incomplete", "incomplete_details": {"reason": "max_output_tokens"}}"#
+                )),
+                Outcome::Truncated
+            );
+            assert_eq!(
+                Outcome::of(&response(r#"{"id": "r", "finish_reason": "length"}"#)),
+                Outcome::Truncated
+            );
         }
 
         #[test]
-        fn test_x_search_tool_minimal() {
-            let tool = Tool::XSearch(XSearchTool {
-                r#type: "x_search".to_string(),
-                allowed_x_handles: None,
-                excluded_x_handles: None,
-                from_date: None,
-                to_date: None,
-                enable_image_understanding: None,
-                enable_video_understanding: None,
+        fn test_outcome_of_error() {
+            assert_eq!(
+                Outcome::of_error(&Outcome::Truncated.into_result().unwrap_err()),
+                Outcome::Truncated
+            );
+            assert!(Outcome::Completed.into_result().is_ok());
+            assert_eq!(
+                Outcome::of_error(&anyhow::anyhow!("--effort only applies")),
+                Outcome::Usage
+            );
+            let rejected = grok_ask::ApiStatusError {
+                status: reqwest::StatusCode::SERVICE_UNAVAILABLE,
+                body: "upstream down".to_string(),
+            };
+            assert_eq!(
+                Outcome::of_error(&anyhow::Error::new(rejected).context("Query failed")),
+                Outcome::ApiError
+            );
+            assert!(Cli::command()
+                .render_long_help()
+                .to_string()
+                .contains("2  The API returned an error"));
+        }
+
+        #[tokio::test]
+        async fn test_network_and_api_errors_told_apart() {
+            let unreachable = reqwest::Client::new()
+                .get("http://[::1")
+                .send()
+                .await
+                .unwrap_err();
+            let unreachable = anyhow::Error::new(unreachable).context("Failed to send request");
+            assert!(is_network_error(&unreachable));
+            assert_eq!(Outcome::of_error(&unreachable), Outcome::Network);
+
+            let rejected = anyhow::Error::new(grok_ask::ApiStatusError {
+                status: reqwest::StatusCode::BAD_GATEWAY,
+                body: "<html>Bad Gateway</html>".to_string(),
             });
+            assert!(!is_network_error(&rejected));
+            assert!(is_api_error(&rejected));
+            assert_eq!(
+                rejected.to_string(),
+                "API returned 502 Bad Gateway: <html>Bad Gateway</html>"
+            );
+        }
 
-            let json = serde_json::to_string(&tool).unwrap();
-            assert!(json.contains("\"type\":\"x_search\""));
-            assert!(!json.contains("allowed_x_handles"));
-            assert!(!json.contains("from_date"));
+        #[tokio::test]
+        async fn test_first_failure_is_kept() {
+            let queries = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+            let outcomes = [Outcome::Completed, Outcome::ApiError, Outcome::Truncated];
+            let outcome = dispatch_queries(&queries, &mut Vec::new(), None, |query| {
+                let outcome = outcomes[queries.iter().position(|q| *q == query).unwrap()];
+                async move {
+                    Ok(Answered {
+                        output: query,
+                        usage: None,
+                        outcome,
+                        response_id: None,
+                    })
+                }
+            })
+            .await
+            .unwrap();
+            assert_eq!(outcome, Outcome::ApiError);
+        }
+    }
+
+    // Test x-search --min-results
+    mod min_results {
+        use super::*;
+        use mockito::Matcher;
+
+        fn x_results(count: usize) -> String {
+            let results: Vec<String> = (0..count)
+                .map(|i| format!(r#"{{"url": "https://x.com/a/status/{}"}}"#, i))
+                .collect();
+            format!(
+                r#"{{"id": "r{}", "status": "completed", "output": [{{"type": "x_search_result", "results": [{}]}}], "usage": {{"input_tokens": 100, "output_tokens": 10}}}}"#,
+                count,
+                results.join(",")
+            )
+        }
+
+        #[tokio::test]
+        async fn test_widens_window_until_enough_posts() {
+            let mut server = mockito::Server::new_async().await;
+            let thin = server
+                .mock("POST", "/v1/responses")
+                .match_body(Matcher::Regex(r#""from_date":"2025-01-10""#.to_string()))
+                .with_body(x_results(1))
+                .expect(1)
+                .create_async()
+                .await;
+            let wider = server
+                .mock("POST", "/v1/responses")
+                .match_body(Matcher::Regex(
+                    r#""from_date":"2025-01-05","to_date":"2025-01-17""#.to_string(),
+                ))
+                .with_body(x_results(3))
+                .expect(1)
+                .create_async()
+                .await;
+
+            let cli = Cli::try_parse_from([
+                "grok-ask",
+                "x-search",
+                "launch",
+                "--min-results",
+                "3",
+                "--widen-days",
+                "5",
+                "--from-date",
+                "2025-01-10",
+                "--to-date",
+                "2025-01-12",
+            ])
+            .unwrap();
+            let mut options = resolve_request(&cli).unwrap().options;
+            options.endpoint = Some(server.url());
+            options.api_key = Some("test-key".to_string());
+            options.retry = RetryPolicy::none();
+
+            let response = x_search_min_results("launch", &options, 3, 5)
+                .await
+                .unwrap();
+            assert_eq!(x_result_count(&response), 3);
+            // Both requests were billed, not just the one that found enough
+            assert_eq!(
+                response.usage,
+                Some(Usage {
+                    input_tokens: Some(200),
+                    output_tokens: Some(20),
+                })
+            );
+            thin.assert_async().await;
+            wider.assert_async().await;
+
+            // Without a window there's nothing to widen: the thin result is returned as is
+            options.x_search_config = None;
+            let _ = server
+                .mock("POST", "/v1/responses")
+                .with_body(x_results(1))
+                .create_async()
+                .await;
+            let response = x_search_min_results("launch", &options, 3, 5)
+                .await
+                .unwrap();
+            assert_eq!(response.id.as_deref(), Some("r1"));
         }
+    }
+
+    // Test --schema
+    mod schema_flag {
+        use super::*;
 
         #[test]
-        fn test_request_serialization() {
-            let request = GrokRequest {
-                model: "grok-4-1-fast".to_string(),
-                input: vec![Message {
-                    role: "user".to_string(),
-                    content: "test query".to_string(),
-                }],
-                store: true,
-                max_output_tokens: Some(8192),
-                previous_response_id: Some("resp_prev".to_string()),
-                tools: vec![Tool::WebSearch(WebSearchTool {
-                    r#type: "web_search".to_string(),
-                    enable_image_understanding: None,
-                })],
-            };
+        fn test_schema_sets_text_format() {
+            let path = std::env::temp_dir().join("grok-ask-person.json");
+            fs::write(&path, r#"{"type": "object", "required": ["name"]}"#).unwrap();
+            let path = path.to_str().unwrap();
 
-            let json = serde_json::to_string(&request).unwrap();
-            assert!(json.contains("\"model\":\"grok-4-1-fast\""));
-            assert!(json.contains("\"store\":true"));
-            assert!(json.contains("\"max_output_tokens\":8192"));
-            assert!(json.contains("\"previous_response_id\":\"resp_prev\""));
-            assert!(json.contains("\"type\":\"web_search\""));
+            let cli = Cli::try_parse_from(["grok-ask", "--schema", path, "ask", "q"]).unwrap();
+            let resolved = resolve_request(&cli).unwrap();
+            let request = serde_json::to_value(build_request("q", &resolved.options)).unwrap();
+            assert_eq!(request["text"]["format"]["name"], "grok-ask-person");
+            assert_eq!(request["text"]["format"]["schema"]["required"][0], "name");
+
+            let cli = Cli::try_parse_from(["grok-ask", "ask", "q"]).unwrap();
+            let resolved = resolve_request(&cli).unwrap();
+            let request = serde_json::to_value(build_request("q", &resolved.options)).unwrap();
+            assert!(request.get("text").is_none());
+
+            assert!(
+                Cli::try_parse_from(["grok-ask", "--schema", "/nonexistent.json", "ask", "q"])
+                    .is_err()
+            );
+            fs::remove_file(path).unwrap();
         }
+    }
+
+    // Test --prompt-file
+    mod prompt_file {
+        use super::*;
 
         #[test]
-        fn test_request_without_tools() {
-            let request = GrokRequest {
-                model: "grok-4-1-fast-non-reasoning".to_string(),
-                input: vec![Message {
-                    role: "user".to_string(),
-                    content: "chat".to_string(),
-                }],
-                store: true,
-                max_output_tokens: None,
-                previous_response_id: None,
-                tools: vec![],
+        fn test_query_read_from_file() {
+            let path = std::env::temp_dir().join("grok-ask-prompt-file.txt");
+            fs::write(&path, "First paragraph.\n\nSecond \"quoted\" one.\n").unwrap();
+            let path = path.to_str().unwrap();
+
+            for args in [
+                vec!["grok-ask", "ask", "--prompt-file", path],
+                vec!["grok-ask", "--prompt-file", path, "x-think"],
+            ] {
+                let cli = Cli::try_parse_from(args).unwrap();
+                assert!(check_prompt_file(&cli).is_ok());
+                assert_eq!(
+                    resolve_request(&cli).unwrap().query,
+                    "First paragraph.\n\nSecond \"quoted\" one."
+                );
+            }
+
+            for args in [
+                ["grok-ask", "ask", "q", "--prompt-file", path],
+                ["grok-ask", "--prompt-file", path, "ask", "q"],
+            ] {
+                let cli = Cli::try_parse_from(args).unwrap();
+                assert!(check_prompt_file(&cli).is_err());
+            }
+            assert!(
+                Cli::try_parse_from(["grok-ask", "--prompt-file", path, "--ask", "q"]).is_err()
+            );
+            assert!(
+                Cli::try_parse_from(["grok-ask", "--prompt-file", "/nonexistent", "ask"]).is_err()
+            );
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    // Test branch
+    mod branch {
+        use super::*;
+        use mockito::Matcher;
+
+        #[tokio::test]
+        async fn test_branches_share_the_parent_and_fail_independently() {
+            let mut server = mockito::Server::new_async().await;
+            let ok = server
+                .mock("POST", "/v1/responses")
+                .match_body(Matcher::AllOf(vec![
+                    Matcher::Regex(r#""previous_response_id":"resp_parent""#.to_string()),
+                    Matcher::Regex("cheaper".to_string()),
+                ]))
+                .with_body(r#"{"id": "r1", "status": "completed", "output": [{"type": "message", "content": [{"type": "output_text", "text": "Option A."}]}]}"#)
+                .create_async()
+                .await;
+            let failing = server
+                .mock("POST", "/v1/responses")
+                .match_body(Matcher::Regex("faster".to_string()))
+                .with_status(400)
+                .with_body(r#"{"error": {"message": "bad request"}}"#)
+                .create_async()
+                .await;
+
+            let cli = Cli::try_parse_from([
+                "grok-ask",
+                "branch",
+                "--from",
+                "resp_parent",
+                "Which is cheaper?",
+                "Which is faster?",
+            ])
+            .unwrap();
+            let Some(Commands::Branch { queries, .. }) = &cli.command else {
+                panic!("expected branch");
             };
+            let mut options = resolve_request(&cli).unwrap().options;
+            assert_eq!(options.previous_response_id.as_deref(), Some("resp_parent"));
+            options.endpoint = Some(server.url());
+            options.api_key = Some("test-key".to_string());
+            options.retry = RetryPolicy::none();
 
-            let json = serde_json::to_string(&request).unwrap();
-            assert!(!json.contains("\"tools\""));
-            assert!(!json.contains("\"max_output_tokens\""));
-            assert!(!json.contains("\"previous_response_id\""));
+            let results = run_batch(&reqwest::Client::new(), queries, &options, 2).await;
+            let text = batch_text(&results, &OutputFormat::Text, &FormatOptions::default());
+            assert!(text.starts_with("## Which is cheaper?\n\nOption A."));
+            assert!(text.contains("## Which is faster?\n\nError: "));
+            ok.assert_async().await;
+            failing.assert_async().await;
+
+            assert!(Cli::try_parse_from(["grok-ask", "branch", "--from", "r"]).is_err());
+        }
+
+        #[tokio::test]
+        async fn test_branches_stop_at_cost_budget() {
+            let mut server = mockito::Server::new_async().await;
+            // 1000 in / 1000 out costs $0.0007 at default pricing
+            let answered = server
+                .mock("POST", "/v1/responses")
+                .with_body(r#"{"id": "r1", "status": "completed", "output": [], "usage": {"input_tokens": 1000, "output_tokens": 1000}}"#)
+                .expect(1)
+                .create_async()
+                .await;
+
+            let _home = scratch_home("grok-ask-branch-budget-home").await;
+            let path = env::temp_dir().join("grok-ask-branch-budget-test.txt");
+            let cli = Cli::try_parse_from([
+                "grok-ask",
+                "--max-total-cost",
+                "0.001",
+                "--output-file",
+                path.to_str().unwrap(),
+                "branch",
+                "--from",
+                "resp_parent",
+                "--concurrency",
+                "1",
+                "a",
+                "b",
+                "c",
+            ])
+            .unwrap();
+            let mut options = resolve_request(&cli).unwrap().options;
+            options.endpoint = Some(server.url());
+            options.api_key = Some("test-key".to_string());
+            options.retry = RetryPolicy::none();
+            options.spinner = false;
+
+            let queries = ["a", "b", "c"].map(String::from);
+            run_grouped(&cli, &options, &queries, 1, "branch", None)
+                .await
+                .unwrap();
+            let output = fs::read_to_string(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert!(output.starts_with("## a\n"));
+            assert!(!output.contains("## b"));
+            answered.assert_async().await;
+        }
+    }
+
+    // Test chat --web and ask --no-web
+    mod web_toggle {
+        use super::*;
+        use grok_ask::Tool;
+
+        fn tools(args: &[&str]) -> Vec<Tool> {
+            let cli = Cli::try_parse_from(args).unwrap();
+            let resolved = resolve_request(&cli).unwrap();
+            build_request(&resolved.query, &resolved.options).tools
+        }
+
+        #[test]
+        fn test_web_search_toggled_per_invocation() {
+            assert!(tools(&["grok-ask", "chat", "hi"]).is_empty());
+            for args in [
+                &["grok-ask", "chat", "hi", "--web"][..],
+                &["grok-ask", "--chat", "hi", "--web"],
+            ] {
+                let tools = tools(args);
+                assert_eq!(tools.len(), 1);
+                assert!(matches!(tools[0], Tool::WebSearch(_)));
+            }
+
+            assert_eq!(tools(&["grok-ask", "ask", "hi"]).len(), 1);
+            assert!(tools(&["grok-ask", "ask", "hi", "--no-web"]).is_empty());
+            assert!(tools(&["grok-ask", "--ask", "hi", "--no-web"]).is_empty());
+
+            assert!(Cli::try_parse_from(["grok-ask", "--ask", "hi", "--web"]).is_err());
+            assert!(Cli::try_parse_from(["grok-ask", "think", "hi", "--no-web"]).is_err());
+        }
+    }
+
+    // Test --country
+    mod country {
+        use super::*;
+
+        #[test]
+        fn test_country_code_validated_and_uppercased() {
+            let cli =
+                Cli::try_parse_from(["grok-ask", "search", "news", "--country", "de"]).unwrap();
+            assert_eq!(cli.country.as_deref(), Some("DE"));
+            let resolved = resolve_request(&cli).unwrap();
+            assert_eq!(resolved.options.country.as_deref(), Some("DE"));
+            assert!(check_country(cli.country.as_deref(), resolved.options.use_web_search).is_ok());
+
+            for bad in ["DEU", "d", "1A", ""] {
+                assert!(
+                    Cli::try_parse_from(["grok-ask", "ask", "q", "--country", bad]).is_err(),
+                    "{bad}"
+                );
+            }
+
+            let cli = Cli::try_parse_from(["grok-ask", "chat", "q", "--country", "US"]).unwrap();
+            let resolved = resolve_request(&cli).unwrap();
+            assert!(
+                check_country(cli.country.as_deref(), resolved.options.use_web_search).is_err()
+            );
+        }
+    }
+
+    // Test web search --enable-images
+    mod web_images {
+        use super::*;
+
+        #[test]
+        fn test_enable_images_reaches_web_search() {
+            for args in [
+                &["grok-ask", "search", "q", "--enable-images"][..],
+                &["grok-ask", "ask", "q", "--enable-images"],
+                &["grok-ask", "--ask", "q", "--enable-images"],
+            ] {
+                let cli = Cli::try_parse_from(args).unwrap();
+                assert!(
+                    resolve_request(&cli).unwrap().options.web_images,
+                    "{:?}",
+                    args
+                );
+            }
+            let cli = Cli::try_parse_from(["grok-ask", "ask", "q"]).unwrap();
+            assert!(!resolve_request(&cli).unwrap().options.web_images);
+            let cli = Cli::try_parse_from(["grok-ask", "--x-ask", "q", "--enable-images"]).unwrap();
+            assert!(!resolve_request(&cli).unwrap().options.web_images);
+            assert!(
+                Cli::try_parse_from(["grok-ask", "ask", "q", "--no-web", "--enable-images"])
+                    .is_err()
+            );
+        }
+    }
+
+    // Test --example and --messages-file
+    mod priming {
+        use super::*;
+
+        #[test]
+        fn test_messages_file_then_examples_precede_query() {
+            let path = std::env::temp_dir().join("grok-ask-messages-file.json");
+            fs::write(
+                &path,
+                r#"[{"role": "system", "content": "Answer in one word."}]"#,
+            )
+            .unwrap();
+            let cli = Cli::try_parse_from([
+                "grok-ask",
+                "--messages-file",
+                path.to_str().unwrap(),
+                "ask",
+                "Capital of Italy?",
+                "--example",
+                "Capital of France?::Paris",
+                "--example",
+                "Capital of Spain?::Madrid",
+            ])
+            .unwrap();
+            let mut resolved = resolve_request(&cli).unwrap();
+            resolved.options.prior_messages = prior_messages(&cli).unwrap();
+            let request = build_request(&resolved.query, &resolved.options);
+            let turns: Vec<String> = serde_json::to_value(&request).unwrap()["input"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|m| format!("{}: {}", m["role"].as_str().unwrap(), m["content"]))
+                .collect();
+            assert_eq!(turns.len(), 7);
+            assert_eq!(turns[1], "system: \"Answer in one word.\"");
+            assert_eq!(turns[2], "user: \"Capital of France?\"");
+            assert_eq!(turns[5], "assistant: \"Madrid\"");
+            assert_eq!(turns[6], "user: \"Capital of Italy?\"");
+            fs::remove_file(path).unwrap();
+        }
+
+        #[test]
+        fn test_malformed_example_rejected() {
+            assert!(Cli::try_parse_from(["grok-ask", "ask", "q", "--example", "no pair"]).is_err());
         }
     }
 
-    // Test XSearchConfig
-    mod x_search_config {
+    // Test count-tokens
+    mod count_tokens {
         use super::*;
 
         #[test]
-        fn test_default_config() {
-            let config = XSearchConfig::default();
-            assert!(config.allowed_handles.is_none());
-            assert!(config.excluded_handles.is_none());
-            assert!(config.from_date.is_none());
-            assert!(config.to_date.is_none());
-            assert!(!config.enable_images);
-            assert!(!config.enable_video);
+        fn test_system_file_counted_with_prompt() {
+            let path = std::env::temp_dir().join("grok-ask-count-tokens-system.txt");
+            fs::write(&path, "x".repeat(80)).unwrap();
+            let cli = Cli::try_parse_from([
+                "grok-ask",
+                "count-tokens",
+                "12345678",
+                "--system-file",
+                path.to_str().unwrap(),
+            ])
+            .unwrap();
+            let resolved = resolve_request(&cli).unwrap();
+            let estimate = estimate_input_tokens(&resolved.query, &resolved.options);
+            assert_eq!(estimate.system_tokens, 20);
+            assert_eq!(estimate.query_tokens, 2);
+            assert_eq!(estimate.total_tokens, 22);
+            fs::remove_file(path).unwrap();
         }
 
         #[test]
-        fn test_config_with_handles() {
-            let config = XSearchConfig {
-                allowed_handles: Some(vec!["user1".to_string(), "user2".to_string()]),
-                excluded_handles: None,
-                from_date: None,
-                to_date: None,
-                enable_images: false,
-                enable_video: false,
+        fn test_target_command_instruction_counted() {
+            let estimate = |args: &[&str]| {
+                let resolved = resolve_request(&Cli::try_parse_from(args).unwrap()).unwrap();
+                estimate_input_tokens(&resolved.query, &resolved.options)
             };
+            let chat = estimate(&["grok-ask", "count-tokens", "q"]);
+            assert_eq!(chat.system_tokens, 0);
+            let think = estimate(&["grok-ask", "count-tokens", "q", "--for", "think"]);
+            assert_eq!(
+                think.system_tokens,
+                grok_ask::estimate_tokens(THINK_INSTRUCTION)
+            );
+            let unknown = Cli::try_parse_from(["grok-ask", "count-tokens", "q", "--for", "nope"]);
+            assert!(unknown.is_err());
+        }
+    }
+
+    // Test search --max-results
+    mod max_results {
+        use super::*;
 
+        #[test]
+        fn test_max_results_limits_search_and_sources() {
+            let cli =
+                Cli::try_parse_from(["grok-ask", "search", "q", "--max-results", "3"]).unwrap();
+            assert_eq!(
+                resolve_request(&cli).unwrap().options.max_search_results,
+                Some(3)
+            );
+            assert_eq!(format_options(&cli).unwrap().max_sources, Some(3));
+
+            let cli = Cli::try_parse_from(["grok-ask", "ask", "q"]).unwrap();
             assert_eq!(
-                config.allowed_handles,
-                Some(vec!["user1".to_string(), "user2".to_string()])
+                resolve_request(&cli).unwrap().options.max_search_results,
+                None
             );
+            assert_eq!(format_options(&cli).unwrap().max_sources, None);
+        }
+    }
+
+    // Test --then
+    mod then {
+        use super::*;
+        use mockito::Matcher;
+
+        fn cli(out: &Path) -> Cli {
+            Cli::try_parse_from([
+                "grok-ask",
+                "chat",
+                "Name a river.",
+                "--then",
+                "How long is it?",
+                "--output-file",
+                out.to_str().unwrap(),
+            ])
+            .unwrap()
+        }
+
+        #[tokio::test]
+        async fn test_second_query_follows_the_first() {
+            let _home = scratch_home("grok-ask-then-home").await;
+            let mut server = mockito::Server::new_async().await;
+            let first = server
+                .mock("POST", "/v1/responses")
+                .match_body(Matcher::Regex("Name a river".to_string()))
+                .with_body(r#"{"id": "resp_first", "status": "completed", "output": [{"type": "message", "content": [{"type": "output_text", "text": "The Nile."}]}]}"#)
+                .create_async()
+                .await;
+            let second = server
+                .mock("POST", "/v1/responses")
+                .match_body(Matcher::AllOf(vec![
+                    Matcher::Regex(r#""previous_response_id":"resp_first""#.to_string()),
+                    Matcher::Regex("How long".to_string()),
+                ]))
+                .with_body(r#"{"id": "resp_second", "status": "completed", "output": [{"type": "message", "content": [{"type": "output_text", "text": "About 6,650 km."}]}]}"#)
+                .create_async()
+                .await;
+
+            let out = std::env::temp_dir().join("grok-ask-then-test.txt");
+            let cli = cli(&out);
+            let Resolved {
+                mode,
+                query,
+                mut options,
+            } = resolve_request(&cli).unwrap();
+            options.endpoint = Some(server.url());
+            options.api_key = Some("test-key".to_string());
+            options.retry = RetryPolicy::none();
+            options.spinner = false;
+
+            let then = cli.then.as_deref().unwrap();
+            run_then(&cli, mode, &[query], then, &options, None)
+                .await
+                .unwrap();
+            let output = fs::read_to_string(&out).unwrap();
+            assert!(output.starts_with("About 6,650 km."));
+            assert!(!output.contains("The Nile."));
+            first.assert_async().await;
+            second.assert_async().await;
+            fs::remove_file(out).unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_failed_first_query_stops_the_chain() {
+            let _home = scratch_home("grok-ask-then-failed-home").await;
+            let mut server = mockito::Server::new_async().await;
+            server
+                .mock("POST", "/v1/responses")
+                .match_body(Matcher::Regex("Name a river".to_string()))
+                .with_status(400)
+                .with_body(r#"{"error": {"message": "bad request"}}"#)
+                .create_async()
+                .await;
+            let second = server
+                .mock("POST", "/v1/responses")
+                .match_body(Matcher::Regex("How long".to_string()))
+                .expect(0)
+                .create_async()
+                .await;
+
+            let out = std::env::temp_dir().join("grok-ask-then-failed-test.txt");
+            let cli = cli(&out);
+            let mut options = resolve_request(&cli).unwrap().options;
+            options.endpoint = Some(server.url());
+            options.api_key = Some("test-key".to_string());
+            options.retry = RetryPolicy::none();
+            options.spinner = false;
+
+            let queries = ["Name a river.".to_string()];
+            let then = "How long is it?";
+            let result = run_then(&cli, Mode::Chat, &queries, then, &options, None);
+            assert!(result.await.is_err());
+            second.assert_async().await;
+            assert!(!out.exists());
         }
 
         #[test]
-        fn test_config_with_dates() {
-            let config = XSearchConfig {
-                allowed_handles: None,
-                excluded_handles: None,
-                from_date: Some("2025-01-01".to_string()),
-                to_date: Some("2025-01-15".to_string()),
-                enable_images: false,
-                enable_video: false,
+        fn test_then_needs_a_stored_query() {
+            let cli = Cli::try_parse_from(["grok-ask", "--no-store", "ask", "q", "--then", "more"])
+                .unwrap();
+            assert!(check_then(&cli).is_err());
+            let cli =
+                Cli::try_parse_from(["grok-ask", "batch", "f.txt", "--then", "more"]).unwrap();
+            assert!(check_then(&cli).is_err());
+            let cli = Cli::try_parse_from(["grok-ask", "--ask", "q", "--then", "more"]).unwrap();
+            assert!(check_then(&cli).is_ok());
+        }
+    }
+
+    // Test Ctrl-C cancellation
+    mod cancel {
+        use super::*;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        /// Stands in for the spinner: records that it was cleaned up
+        struct Guard(Arc<AtomicBool>);
+
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        #[tokio::test]
+        async fn test_cancel_drops_in_flight_work() {
+            let dropped = Arc::new(AtomicBool::new(false));
+            let guard = Guard(Arc::clone(&dropped));
+            let work = async move {
+                let _guard = guard;
+                std::future::pending::<()>().await;
             };
+            let cancel = tokio::time::sleep(Duration::from_millis(10));
+            assert!(until_cancelled(work, cancel).await.is_none());
+            assert!(dropped.load(Ordering::SeqCst));
+            assert_eq!(Outcome::Cancelled as i32, 130);
+        }
 
-            assert_eq!(config.from_date, Some("2025-01-01".to_string()));
-            assert_eq!(config.to_date, Some("2025-01-15".to_string()));
+        #[tokio::test]
+        async fn test_finished_work_is_returned() {
+            let result = until_cancelled(async { 42 }, std::future::pending()).await;
+            assert_eq!(result, Some(42));
         }
+    }
+
+    // Test default_command
+    mod default_command {
+        use super::*;
 
         #[test]
-        fn test_config_with_media() {
-            let config = XSearchConfig {
-                allowed_handles: None,
-                excluded_handles: None,
-                from_date: None,
-                to_date: None,
-                enable_images: true,
-                enable_video: true,
-            };
+        fn test_bare_query_uses_configured_command() {
+            let dir = std::env::temp_dir().join(format!(
+                "grok-ask-default-command-test-{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("config.toml");
+
+            assert!(default_mode(&path)
+                .unwrap_err()
+                .to_string()
+                .contains("set default_command = \"ask\""));
+            fs::write(&path, "default_command = \"x-ask\"\n").unwrap();
+            assert_eq!(default_mode(&path).unwrap(), Mode::XAsk);
+            fs::write(&path, "default_command = \"browse\"\n").unwrap();
+            assert!(default_mode(&path).is_err());
+            fs::remove_dir_all(&dir).unwrap();
+
+            let mut cli = Cli::try_parse_from(["grok-ask", "what is rust"]).unwrap();
+            assert!(cli.command.is_none());
+            assert!(resolve_request(&cli).is_none());
+            cli.default_mode = Some(Mode::Ask);
+            let resolved = resolve_request(&cli).unwrap();
+            assert_eq!(resolved.mode, Mode::Ask);
+            assert_eq!(resolved.query, "what is rust");
+            assert!(resolved.options.use_web_search);
+        }
+
+        #[test]
+        fn test_subcommands_and_shorthands_still_parse() {
+            let cli = Cli::try_parse_from(["grok-ask", "ask", "q"]).unwrap();
+            assert!(cli.query.is_none());
+            assert!(matches!(cli.command, Some(Commands::Ask { .. })));
+            assert!(Cli::try_parse_from(["grok-ask", "--ask", "q", "other"]).is_err());
+
+            // A bare query before a subcommand is rejected rather than dropped
+            let cli = Cli::try_parse_from(["grok-ask", "--dry-run", "foo", "ask", "bar"]).unwrap();
+            assert!(check_query(&cli)
+                .unwrap_err()
+                .to_string()
+                .contains("Unexpected query `foo`"));
+            assert!(check_query(&Cli::try_parse_from(["grok-ask", "ask", "bar"]).unwrap()).is_ok());
+        }
+    }
 
-            assert!(config.enable_images);
-            assert!(config.enable_video);
+    // Test --stream
+    mod stream {
+        use super::*;
+
+        #[test]
+        fn test_stream_conflicts_with_buffered_output() {
+            for args in [
+                &["grok-ask", "--stream", "--output-file", "o", "ask", "q"][..],
+                &["grok-ask", "--stream", "ask", "q", "--forbid", "secret"],
+                &["grok-ask", "ask", "q", "--stream", "--then", "why?"],
+                &["grok-ask", "--stream", "x-search", "q", "--min-results=5"],
+            ] {
+                let cli = Cli::try_parse_from(args).unwrap();
+                assert!(check_stream(&cli)
+                    .unwrap_err()
+                    .to_string()
+                    .starts_with("--stream can't be combined with"));
+            }
+            let cli = Cli::try_parse_from(["grok-ask", "--stream", "ask", "q"]).unwrap();
+            assert!(check_stream(&cli).is_ok());
         }
     }
 
-    // Integration tests with mocked HTTP
-    mod integration {
+    // Test REPL interrupts
+    mod repl_interrupt {
         use super::*;
 
         #[tokio::test]
-        async fn test_api_request_format() {
-            // This test verifies the request structure without making real API calls
-            let messages = vec![
-                Message {
-                    role: "system".to_string(),
-                    content: "Be concise.".to_string(),
-                },
-                Message {
-                    role: "user".to_string(),
-                    content: "test query".to_string(),
-                },
-            ];
-
-            let request = GrokRequest {
-                model: MODEL.to_string(),
-                input: messages,
-                store: true,
-                max_output_tokens: Some(8192),
-                previous_response_id: None,
-                tools: vec![
-                    Tool::WebSearch(WebSearchTool {
-                        r#type: "web_search".to_string(),
-                        enable_image_understanding: None,
-                    }),
-                    Tool::XSearch(XSearchTool {
-                        r#type: "x_search".to_string(),
-                        allowed_x_handles: Some(vec!["elonmusk".to_string()]),
-                        excluded_x_handles: None,
-                        from_date: Some("2025-01-01".to_string()),
-                        to_date: None,
-                        enable_image_understanding: None,
-                        enable_video_understanding: None,
-                    }),
-                ],
+        async fn test_interrupt_drops_the_turn() {
+            let options = RequestOptions {
+                endpoint: Some("http://127.0.0.1:1".to_string()),
+                ..Default::default()
+            };
+            let turn = repl_turn("q", &options, false, std::future::ready(())).await;
+            assert!(turn.is_none());
+        }
+
+        #[tokio::test]
+        async fn test_finished_turn_returns_its_result() {
+            let options = RequestOptions {
+                endpoint: Some("http://127.0.0.1:1".to_string()),
+                ..Default::default()
+            };
+            for stream in [false, true] {
+                let turn = repl_turn("q", &options, stream, std::future::pending()).await;
+                assert!(turn.unwrap().is_err());
+            }
+        }
+    }
+
+    // Test --log-file
+    mod audit {
+        use super::*;
+
+        #[test]
+        fn test_requests_are_filed_under_their_command() {
+            let log = |args: &[&str]| {
+                let cli = Cli::try_parse_from(args).unwrap();
+                let mode = resolve_request(&cli).unwrap().mode;
+                audit_log(&cli, mode)
             };
+            let compare = log(&["grok-ask", "--log-file", "a.jsonl", "compare", "q"]).unwrap();
+            assert_eq!(compare.path, PathBuf::from("a.jsonl"));
+            assert_eq!(compare.command, "compare");
+            let x_ask = log(&["grok-ask", "--log-file", "a.jsonl", "x-ask", "q"]).unwrap();
+            assert_eq!(x_ask.command, "x-ask");
+        }
+    }
 
-            let json = serde_json::to_string_pretty(&request).unwrap();
+    // Test --suggest-followups
+    mod suggest_followups {
+        use super::*;
+        use mockito::Matcher;
 
-            // Verify structure
-            assert!(json.contains("grok-4-1-fast-non-reasoning"));
-            assert!(json.contains("Be concise."));
-            assert!(json.contains("test query"));
-            assert!(json.contains("web_search"));
-            assert!(json.contains("x_search"));
-            assert!(json.contains("elonmusk"));
-            assert!(json.contains("2025-01-01"));
+        #[test]
+        fn test_suggest_followups_needs_a_stored_answer() {
+            let cli =
+                Cli::try_parse_from(["grok-ask", "--no-store", "--suggest-followups", "ask", "q"])
+                    .unwrap();
+            assert!(check_suggest_followups(&cli).is_err());
+            let cli = Cli::try_parse_from(["grok-ask", "--suggest-followups", "ask", "q"]).unwrap();
+            assert!(check_suggest_followups(&cli).is_ok());
         }
 
         #[tokio::test]
-        async fn test_response_parsing() {
-            let json = r#"{
-                "id": "resp_test",
-                "status": "completed",
-                "output": [
-                    {
-                        "type": "x_search_result",
-                        "results": [
-                            {"url": "https://x.com/u/1", "title": "@user"}
-                        ]
-                    },
-                    {
-                        "type": "message",
-                        "content": [
-                            {"type": "output_text", "text": "Test response."}
-                        ]
-                    }
-                ],
-                "usage": {"input_tokens": 10, "output_tokens": 20}
-            }"#;
-
-            let response: GrokResponse = serde_json::from_str(json).unwrap();
+        async fn test_followups_keep_the_query_settings_and_count_usage() {
+            let _home = scratch_home("grok-ask-followups-home").await;
+            let mut server = mockito::Server::new_async().await;
+            let answer = server
+                .mock("POST", "/v1/responses")
+                .match_body(Matcher::Regex("Name a river".to_string()))
+                .with_body(r#"{"id": "resp_river", "status": "completed", "output": [{"type": "message", "content": [{"type": "output_text", "text": "The Nile."}]}], "usage": {"input_tokens": 10, "output_tokens": 20}}"#)
+                .create_async()
+                .await;
+            let followups = server
+                .mock("POST", "/v1/responses")
+                .match_header("authorization", "Bearer test-key")
+                .match_body(Matcher::AllOf(vec![
+                    Matcher::Regex(r#""previous_response_id":"resp_river""#.to_string()),
+                    Matcher::Regex(r#""model":"grok-test""#.to_string()),
+                    Matcher::Regex("Suggest 3 related questions".to_string()),
+                ]))
+                .with_body(r#"{"id": "resp_next", "status": "completed", "output": [{"type": "message", "content": [{"type": "output_text", "text": "1. How long is it?"}]}], "usage": {"input_tokens": 5, "output_tokens": 7}}"#)
+                .expect(1)
+                .create_async()
+                .await;
 
-            assert_eq!(response.id, Some("resp_test".to_string()));
-            assert_eq!(response.status, Some("completed".to_string()));
+            let cli = Cli::try_parse_from([
+                "grok-ask",
+                "--model",
+                "grok-test",
+                "--suggest-followups",
+                "--show-cost",
+                "ask",
+                "Name a river.",
+            ])
+            .unwrap();
+            let Some(Resolved {
+                mode,
+                query,
+                mut options,
+            }) = resolve_request(&cli)
+            else {
+                panic!("expected a query");
+            };
+            options.endpoint = Some(server.url());
+            options.api_key = Some("test-key".to_string());
+            options.retry = RetryPolicy::none();
+            options.spinner = false;
 
-            let output = format_response(&response, &OutputFormat::Text);
-            assert!(output.contains("Test response."));
-            assert!(output.contains("[@user](https://x.com/u/1)"));
+            let answered = run_query(&cli, mode, &query, &options).await.unwrap();
+            assert!(answered.output.contains("How long is it?"));
+            assert!(answered.output.contains("Tokens: 15 in / 27 out"));
+            let usage = answered.usage.unwrap();
+            assert_eq!(usage.input_tokens, Some(15));
+            assert_eq!(usage.output_tokens, Some(27));
+            answer.assert_async().await;
+            followups.assert_async().await;
         }
     }
 }
@@ -0,0 +1,116 @@
+//! Run manifests: what was asked, of which model, with which settings.
+//!
+//! A manifest holds everything needed to reproduce or cite a single run:
+//! the exact request body (model, parameters, tools) plus the id of the
+//! stored response it produced.
+
+use crate::{unix_now, GrokRequest, GrokResponse};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub grok_ask_version: String,
+    /// Unix seconds when the answer was received
+    pub created_at: u64,
+    pub query: String,
+    pub model: String,
+    pub max_output_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub previous_response_id: Option<String>,
+    pub tools: Value,
+    /// The request body exactly as sent
+    pub request: Value,
+    pub response_id: Option<String>,
+    pub status: Option<String>,
+}
+
+impl Manifest {
+    pub fn new(query: &str, request: &GrokRequest, response: &GrokResponse) -> Result<Self> {
+        Ok(Self {
+            grok_ask_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: unix_now(),
+            query: query.to_string(),
+            model: request.model.clone(),
+            max_output_tokens: request.max_output_tokens,
+            temperature: request.temperature,
+            previous_response_id: request.previous_response_id.clone(),
+            tools: serde_json::to_value(&request.tools)?,
+            request: serde_json::to_value(request)?,
+            response_id: response.id.clone(),
+            status: response.status.clone(),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let data = serde_json::to_string_pretty(self)?;
+        fs::write(path, data + "\n")
+            .with_context(|| format!("Failed to write manifest {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_request, RequestOptions, XSearchConfig};
+
+    #[test]
+    fn test_manifest_contains_all_fields() {
+        let options = RequestOptions {
+            system_instruction: Some("Be brief.".to_string()),
+            max_tokens: 2048,
+            use_x_search: true,
+            x_search_config: Some(XSearchConfig {
+                allowed_handles: Some(vec!["xai".to_string()]),
+                ..Default::default()
+            }),
+            previous_response_id: Some("resp_prev".to_string()),
+            ..Default::default()
+        };
+        let request = build_request("What's new?", &options);
+        let response = GrokResponse {
+            id: Some("resp_new".to_string()),
+            status: Some("completed".to_string()),
+            finish_reason: None,
+            incomplete_details: None,
+            output: None,
+            usage: None,
+            error: None,
+            meta: None,
+        };
+
+        let manifest = Manifest::new("What's new?", &request, &response).unwrap();
+        let json = serde_json::to_value(&manifest).unwrap();
+
+        for field in [
+            "grok_ask_version",
+            "created_at",
+            "query",
+            "model",
+            "max_output_tokens",
+            "temperature",
+            "previous_response_id",
+            "tools",
+            "request",
+            "response_id",
+            "status",
+        ] {
+            assert!(json.get(field).is_some(), "missing {field}");
+        }
+        assert_eq!(json["query"], "What's new?");
+        assert_eq!(json["model"], crate::MODEL);
+        assert_eq!(json["max_output_tokens"], 2048);
+        assert_eq!(json["previous_response_id"], "resp_prev");
+        assert_eq!(json["tools"][0]["type"], "x_search");
+        assert_eq!(json["tools"][0]["allowed_x_handles"][0], "xai");
+        assert_eq!(json["request"]["input"][0]["content"], "Be brief.");
+        assert_eq!(json["response_id"], "resp_new");
+        assert!(json["created_at"].as_u64().unwrap() > 0);
+    }
+}
@@ -0,0 +1,101 @@
+//! The HTTP status and rate-limit headers of a response, for debugging.
+//!
+//! Only headers that say something about rate limits or identify the request
+//! are kept, and any whose name or value looks like a credential is redacted,
+//! so the dump is safe to paste into a bug report.
+
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Header name fragments that never have their values shown
+const SENSITIVE_NAMES: [&str; 5] = ["authorization", "cookie", "api-key", "apikey", "secret"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ResponseMeta {
+    pub status: u16,
+    /// Selected headers, by lowercase name
+    pub headers: BTreeMap<String, String>,
+}
+
+impl ResponseMeta {
+    /// Captures the status and selected headers; call before reading the body
+    pub fn new(status: u16, headers: &HeaderMap) -> Self {
+        let headers = headers
+            .iter()
+            .filter(|(name, _)| is_selected(name.as_str()))
+            .map(|(name, value)| {
+                let value = value.to_str().unwrap_or("[non-UTF-8]");
+                (name.as_str().to_string(), redact(name.as_str(), value))
+            })
+            .collect();
+        Self { status, headers }
+    }
+
+    /// The status and headers as lines for stderr
+    pub fn describe(&self) -> String {
+        let mut out = format!("Response headers (HTTP {}):\n", self.status);
+        if self.headers.is_empty() {
+            out.push_str("  (no rate-limit headers)\n");
+        }
+        for (name, value) in &self.headers {
+            out.push_str(&format!("  {}: {}\n", name, value));
+        }
+        out
+    }
+}
+
+/// Rate-limit headers, `retry-after`, and request ids
+fn is_selected(name: &str) -> bool {
+    name.starts_with("x-ratelimit-")
+        || name.starts_with("ratelimit")
+        || name == "retry-after"
+        || name.ends_with("request-id")
+}
+
+fn redact(name: &str, value: &str) -> String {
+    let looks_like_key = value.starts_with("Bearer ") || value.starts_with("xai-");
+    if looks_like_key || SENSITIVE_NAMES.iter().any(|s| name.contains(s)) {
+        REDACTED.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_selected_headers_kept_and_sensitive_redacted() {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("x-ratelimit-remaining-requests", "42"),
+            ("x-ratelimit-remaining-tokens", "9000"),
+            ("retry-after", "12"),
+            ("x-request-id", "req_abc"),
+            ("x-ratelimit-api-key", "xai-should-not-show"),
+            ("set-cookie", "session=1"),
+            ("content-type", "application/json"),
+        ] {
+            headers.insert(name, HeaderValue::from_static(value));
+        }
+
+        let meta = ResponseMeta::new(429, &headers);
+        assert_eq!(meta.status, 429);
+        assert_eq!(meta.headers["x-ratelimit-remaining-tokens"], "9000");
+        assert_eq!(meta.headers["retry-after"], "12");
+        assert_eq!(meta.headers["x-request-id"], "req_abc");
+        assert_eq!(meta.headers["x-ratelimit-api-key"], REDACTED);
+        assert!(!meta.headers.contains_key("set-cookie"));
+        assert!(!meta.headers.contains_key("content-type"));
+
+        let text = meta.describe();
+        assert!(text.starts_with("Response headers (HTTP 429):\n"));
+        assert!(text.contains("  x-ratelimit-remaining-requests: 42\n"));
+        assert!(!text.contains("xai-should-not-show"));
+    }
+}
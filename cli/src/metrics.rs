@@ -0,0 +1,140 @@
+//! Prometheus text-format metrics for batch runs.
+
+use crate::batch::BatchResult;
+use std::fmt::Write;
+
+/// Upper bounds, in seconds, of the latency histogram buckets
+pub const LATENCY_BUCKETS: [f64; 8] = [0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Totals gathered from one batch run
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchMetrics {
+    pub requests: u64,
+    pub errors: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Request latencies in seconds
+    pub latencies: Vec<f64>,
+}
+
+impl BatchMetrics {
+    pub fn from_results(results: &[BatchResult]) -> Self {
+        let usage = results
+            .iter()
+            .filter_map(|result| result.outcome.as_ref().ok())
+            .filter_map(|response| response.usage.as_ref());
+        let (input_tokens, output_tokens) = usage.fold((0, 0), |(input, output), usage| {
+            (
+                input + u64::from(usage.input_tokens.unwrap_or(0)),
+                output + u64::from(usage.output_tokens.unwrap_or(0)),
+            )
+        });
+
+        Self {
+            requests: results.len() as u64,
+            errors: results.iter().filter(|r| r.is_err()).count() as u64,
+            input_tokens,
+            output_tokens,
+            latencies: results.iter().map(|r| r.latency.as_secs_f64()).collect(),
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "grok_ask_requests_total",
+            "Queries sent in the batch.",
+            &[("", self.requests)],
+        );
+        counter(
+            &mut out,
+            "grok_ask_request_errors_total",
+            "Queries that failed or returned an API error.",
+            &[("", self.errors)],
+        );
+        counter(
+            &mut out,
+            "grok_ask_tokens_total",
+            "Tokens used by successful queries.",
+            &[
+                ("direction=\"input\"", self.input_tokens),
+                ("direction=\"output\"", self.output_tokens),
+            ],
+        );
+
+        let name = "grok_ask_request_duration_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Time taken by each query, including retries.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for bound in LATENCY_BUCKETS {
+            let count = self.latencies.iter().filter(|&&l| l <= bound).count();
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{le=\"+Inf\"}} {}",
+            name,
+            self.latencies.len()
+        );
+        let _ = writeln!(out, "{}_sum {}", name, self.latencies.iter().sum::<f64>());
+        let _ = writeln!(out, "{}_count {}", name, self.latencies.len());
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, samples: &[(&str, u64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prometheus_serialization() {
+        let metrics = BatchMetrics {
+            requests: 3,
+            errors: 1,
+            input_tokens: 120,
+            output_tokens: 480,
+            latencies: vec![0.4, 1.5, 45.0],
+        };
+        let text = metrics.to_prometheus();
+
+        assert!(text.contains(
+            "# HELP grok_ask_requests_total Queries sent in the batch.\n\
+             # TYPE grok_ask_requests_total counter\n\
+             grok_ask_requests_total 3\n"
+        ));
+        assert!(text.contains("grok_ask_request_errors_total 1\n"));
+        assert!(text.contains("grok_ask_tokens_total{direction=\"input\"} 120\n"));
+        assert!(text.contains("grok_ask_tokens_total{direction=\"output\"} 480\n"));
+        assert!(text.contains("# TYPE grok_ask_request_duration_seconds histogram\n"));
+        assert!(text.contains("grok_ask_request_duration_seconds_bucket{le=\"0.5\"} 1\n"));
+        assert!(text.contains("grok_ask_request_duration_seconds_bucket{le=\"2\"} 2\n"));
+        assert!(text.contains("grok_ask_request_duration_seconds_bucket{le=\"30\"} 2\n"));
+        assert!(text.contains("grok_ask_request_duration_seconds_bucket{le=\"60\"} 3\n"));
+        assert!(text.contains("grok_ask_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("grok_ask_request_duration_seconds_sum 46.9\n"));
+        assert!(text.contains("grok_ask_request_duration_seconds_count 3\n"));
+
+        // Every non-comment line is `name{labels} value`
+        for line in text.lines().filter(|l| !l.starts_with('#')) {
+            let (_, value) = line.rsplit_once(' ').unwrap();
+            assert!(value.parse::<f64>().is_ok(), "{}", line);
+        }
+    }
+}
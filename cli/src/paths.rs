@@ -0,0 +1,78 @@
+//! OS-specific locations for config, cache, and thread state.
+//!
+//! Defaults follow each platform's conventions (XDG on Linux,
+//! `~/Library/Application Support` on macOS, `%APPDATA%` on Windows).
+//! Setting `GROK_ASK_HOME` puts everything under that one directory instead.
+
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use std::env;
+use std::path::{Path, PathBuf};
+
+pub const HOME_ENV: &str = "GROK_ASK_HOME";
+
+/// Resolved state directories
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatePaths {
+    pub config_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    pub data_dir: PathBuf,
+}
+
+impl StatePaths {
+    /// Resolves paths from `GROK_ASK_HOME`, falling back to the platform defaults
+    pub fn resolve() -> Result<Self> {
+        match env::var_os(HOME_ENV).filter(|v| !v.is_empty()) {
+            Some(home) => Ok(Self::under(Path::new(&home))),
+            None => {
+                let dirs = ProjectDirs::from("", "", "grok-ask")
+                    .context("Could not determine a home directory for grok-ask state")?;
+                Ok(Self {
+                    config_dir: dirs.config_dir().to_path_buf(),
+                    cache_dir: dirs.cache_dir().to_path_buf(),
+                    data_dir: dirs.data_dir().to_path_buf(),
+                })
+            }
+        }
+    }
+
+    /// Lays out all state under a single root directory
+    pub fn under(home: &Path) -> Self {
+        Self {
+            config_dir: home.to_path_buf(),
+            cache_dir: home.join("cache"),
+            data_dir: home.join("data"),
+        }
+    }
+
+    pub fn config_file(&self) -> PathBuf {
+        self.config_dir.join("config.toml")
+    }
+
+    pub fn threads_dir(&self) -> PathBuf {
+        self.data_dir.join("threads")
+    }
+
+    /// Which tools produced each recent response id
+    pub fn tool_contexts_file(&self) -> PathBuf {
+        self.data_dir.join("tool-contexts.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_home_override_redirects_all_paths() {
+        let home = env::temp_dir().join("grok-ask-paths-test");
+        env::set_var(HOME_ENV, &home);
+        let paths = StatePaths::resolve().unwrap();
+        env::remove_var(HOME_ENV);
+
+        assert_eq!(paths, StatePaths::under(&home));
+        assert!(paths.config_file().starts_with(&home));
+        assert!(paths.cache_dir.starts_with(&home));
+        assert!(paths.threads_dir().starts_with(&home));
+    }
+}
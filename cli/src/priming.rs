@@ -0,0 +1,107 @@
+//! Few-shot priming: earlier turns placed between the system instruction and
+//! the query, from `--example USER::ASSISTANT` pairs or a JSON messages file.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Roles a priming message may take
+pub const ROLES: [&str; 3] = ["system", "user", "assistant"];
+
+/// A message sent before the query
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PriorMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl PriorMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+}
+
+/// Splits `USER::ASSISTANT` into a user message and the reply it primes
+pub fn parse_example(value: &str) -> Result<[PriorMessage; 2]> {
+    let Some((user, assistant)) = value.split_once("::") else {
+        bail!("expected USER::ASSISTANT, e.g. \"2+2::4\"");
+    };
+    let (user, assistant) = (user.trim(), assistant.trim());
+    if user.is_empty() || assistant.is_empty() {
+        bail!("both sides of USER::ASSISTANT must be non-empty");
+    }
+    Ok([
+        PriorMessage::new("user", user),
+        PriorMessage::new("assistant", assistant),
+    ])
+}
+
+/// Reads a JSON array of `{role, content}` messages, checking every role
+pub fn load_messages(path: &Path) -> Result<Vec<PriorMessage>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read messages {}", path.display()))?;
+    let messages: Vec<PriorMessage> = serde_json::from_str(&text).with_context(|| {
+        format!(
+            "{} must be a JSON array of {{\"role\", \"content\"}} objects",
+            path.display()
+        )
+    })?;
+    for (i, message) in messages.iter().enumerate() {
+        if !ROLES.contains(&message.role.as_str()) {
+            bail!(
+                "Message {} in {} has role `{}`; expected one of {}",
+                i + 1,
+                path.display(),
+                message.role,
+                ROLES.join(", ")
+            );
+        }
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_example() {
+        assert_eq!(
+            parse_example("Capital of France? :: Paris").unwrap(),
+            [
+                PriorMessage::new("user", "Capital of France?"),
+                PriorMessage::new("assistant", "Paris"),
+            ]
+        );
+        // Only the first separator splits, so answers may contain `::`
+        assert_eq!(parse_example("Path?::a::b").unwrap()[1].content, "a::b");
+        assert!(parse_example("no separator").is_err());
+        assert!(parse_example("question::").is_err());
+    }
+
+    #[test]
+    fn test_load_messages_checks_roles() {
+        let dir = std::env::temp_dir().join("grok-ask-priming-test");
+        fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("good.json");
+        fs::write(
+            &good,
+            r#"[{"role": "user", "content": "hi"}, {"role": "assistant", "content": "HI"}]"#,
+        )
+        .unwrap();
+        assert_eq!(load_messages(&good).unwrap().len(), 2);
+
+        let bad = dir.join("bad.json");
+        fs::write(&bad, r#"[{"role": "tool", "content": "x"}]"#).unwrap();
+        let err = load_messages(&bad).unwrap_err().to_string();
+        assert!(err.contains("has role `tool`; expected one of system, user, assistant"));
+
+        fs::write(&bad, r#"{"role": "user"}"#).unwrap();
+        assert!(load_messages(&bad).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//! State and commands for the interactive `repl` loop.
+//!
+//! Each turn is sent as a `chat` request (or an `ask`-style web search while
+//! `/web` is on) threaded onto the previous turn's response id.
+
+use crate::RequestOptions;
+
+pub const HELP: &str = "Commands: /web (toggle web search), /reset (new thread), /model NAME (switch model, no name for the default), /quit. Ctrl-C stops an answer and returns to the prompt.";
+
+/// One line of REPL input
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplInput {
+    Query(String),
+    ToggleWeb,
+    Reset,
+    Model(Option<String>),
+    Help,
+    Quit,
+    Empty,
+    Unknown(String),
+}
+
+impl ReplInput {
+    pub fn parse(line: &str) -> Self {
+        let line = line.trim();
+        let Some(command) = line.strip_prefix('/') else {
+            return if line.is_empty() {
+                ReplInput::Empty
+            } else {
+                ReplInput::Query(line.to_string())
+            };
+        };
+
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (command, ""),
+        };
+        match name {
+            "web" => ReplInput::ToggleWeb,
+            "reset" => ReplInput::Reset,
+            "model" => ReplInput::Model((!arg.is_empty()).then(|| arg.to_string())),
+            "help" => ReplInput::Help,
+            "quit" | "exit" => ReplInput::Quit,
+            _ => ReplInput::Unknown(name.to_string()),
+        }
+    }
+}
+
+/// Settings carried between turns
+#[derive(Debug, Default, Clone)]
+pub struct ReplState {
+    pub web: bool,
+    pub model: Option<String>,
+    pub previous_response_id: Option<String>,
+}
+
+impl ReplState {
+    /// Applies a command, returning the message to show for it
+    pub fn apply(&mut self, input: &ReplInput) -> Option<String> {
+        match input {
+            ReplInput::ToggleWeb => {
+                self.web = !self.web;
+                Some(format!(
+                    "Web search {}",
+                    if self.web { "on" } else { "off" }
+                ))
+            }
+            ReplInput::Reset => {
+                self.previous_response_id = None;
+                Some("Started a new thread".to_string())
+            }
+            ReplInput::Model(model) => {
+                self.model = model.clone();
+                Some(format!("Model: {}", model.as_deref().unwrap_or("default")))
+            }
+            ReplInput::Help => Some(HELP.to_string()),
+            ReplInput::Unknown(name) => Some(format!("Unknown command /{}. {}", name, HELP)),
+            ReplInput::Query(_) | ReplInput::Quit | ReplInput::Empty => None,
+        }
+    }
+
+    /// Request options for the next turn, layered over `base`
+    pub fn options(&self, base: &RequestOptions) -> RequestOptions {
+        RequestOptions {
+            previous_response_id: self.previous_response_id.clone(),
+            use_web_search: self.web,
+            model: self.model.clone().or_else(|| base.model.clone()),
+            ..base.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input() {
+        assert_eq!(
+            ReplInput::parse("  what is xAI? \n"),
+            ReplInput::Query("what is xAI?".to_string())
+        );
+        assert_eq!(ReplInput::parse(""), ReplInput::Empty);
+        assert_eq!(ReplInput::parse("/web"), ReplInput::ToggleWeb);
+        assert_eq!(ReplInput::parse("/reset"), ReplInput::Reset);
+        assert_eq!(
+            ReplInput::parse("/model grok-4"),
+            ReplInput::Model(Some("grok-4".to_string()))
+        );
+        assert_eq!(ReplInput::parse("/model"), ReplInput::Model(None));
+        assert_eq!(ReplInput::parse("/quit"), ReplInput::Quit);
+        assert_eq!(ReplInput::parse("/exit"), ReplInput::Quit);
+        assert_eq!(
+            ReplInput::parse("/nope"),
+            ReplInput::Unknown("nope".to_string())
+        );
+    }
+
+    #[test]
+    fn test_state_threads_turns() {
+        let base = RequestOptions {
+            max_tokens: 8192,
+            ..Default::default()
+        };
+        let mut state = ReplState::default();
+        let options = state.options(&base);
+        assert!(!options.use_web_search);
+        assert!(options.previous_response_id.is_none());
+
+        state.previous_response_id = Some("resp_1".to_string());
+        state.apply(&ReplInput::ToggleWeb);
+        state.apply(&ReplInput::Model(Some("grok-4".to_string())));
+        let options = state.options(&base);
+        assert!(options.use_web_search);
+        assert_eq!(options.previous_response_id.as_deref(), Some("resp_1"));
+        assert_eq!(options.model.as_deref(), Some("grok-4"));
+        assert_eq!(options.max_tokens, 8192);
+
+        assert_eq!(
+            state.apply(&ReplInput::Reset).as_deref(),
+            Some("Started a new thread")
+        );
+        assert!(state.options(&base).previous_response_id.is_none());
+    }
+}
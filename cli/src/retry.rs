@@ -0,0 +1,104 @@
+//! Retrying rate-limited and transiently failing requests.
+
+use std::time::Duration;
+
+/// Rate limits and gateway/server errors that are usually gone on a retry
+pub const DEFAULT_RETRY_CODES: &[u16] = &[429, 500, 502, 503, 504];
+
+/// Which statuses to retry, how many times, and how long to wait before the
+/// first retry. Each later retry waits twice as long as the one before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_delay: Duration,
+    pub retry_codes: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay: Duration::from_secs(1),
+            retry_codes: DEFAULT_RETRY_CODES.to_vec(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Whether a response with this HTTP status should be retried
+    pub fn should_retry(&self, status: u16) -> bool {
+        self.retry_codes.contains(&status)
+    }
+
+    /// Wait before retry number `attempt` (1-based); a server-sent
+    /// `Retry-After` takes precedence over the backoff schedule
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        retry_after.unwrap_or_else(|| {
+            self.initial_delay
+                .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        })
+    }
+}
+
+/// Parses a `Retry-After` header given in seconds; HTTP dates are ignored
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(1, None), Duration::from_secs(1));
+        assert_eq!(policy.delay(2, None), Duration::from_secs(2));
+        assert_eq!(policy.delay(3, None), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_retry_after_overrides_backoff() {
+        let policy = RetryPolicy::default();
+        assert_eq!(
+            policy.delay(3, Some(Duration::from_secs(30))),
+            Duration::from_secs(30)
+        );
+        assert_eq!(parse_retry_after(" 12 "), Some(Duration::from_secs(12)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[test]
+    fn test_retryable_statuses() {
+        let policy = RetryPolicy::default();
+        for status in [429, 500, 502, 503, 504] {
+            assert!(policy.should_retry(status), "{status} should be retried");
+        }
+        for status in [200, 400, 401, 404, 501] {
+            assert!(
+                !policy.should_retry(status),
+                "{status} should not be retried"
+            );
+        }
+    }
+
+    #[test]
+    fn test_configured_codes_drive_should_retry() {
+        let policy = RetryPolicy {
+            retry_codes: vec![429, 500, 529],
+            ..Default::default()
+        };
+        assert!(policy.should_retry(429));
+        assert!(policy.should_retry(529));
+        assert!(!policy.should_retry(503));
+        assert!(!policy.should_retry(504));
+    }
+}
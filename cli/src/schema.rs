@@ -0,0 +1,180 @@
+//! Structured output: asking for answers that conform to a JSON schema, and
+//! checking that they do.
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// The `text` request field, carrying the schema the answer must follow
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TextConfig {
+    pub format: SchemaFormat,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SchemaFormat {
+    pub r#type: String,
+    pub name: String,
+    pub schema: Value,
+    pub strict: bool,
+}
+
+/// A named JSON schema loaded from a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonSchema {
+    pub name: String,
+    pub schema: Value,
+}
+
+impl JsonSchema {
+    /// Reads a schema file, named after its file stem, and checks it compiles
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read schema {}", path.display()))?;
+        let schema: Value = serde_json::from_str(&text)
+            .with_context(|| format!("{} is not valid JSON", path.display()))?;
+        if let Err(err) = jsonschema::validator_for(&schema) {
+            bail!("{} is not a valid JSON schema: {}", path.display(), err);
+        }
+        let name = path
+            .file_stem()
+            .map(|stem| schema_name(&stem.to_string_lossy()))
+            .unwrap_or_else(|| "response".to_string());
+        Ok(Self { name, schema })
+    }
+
+    pub fn text_config(&self) -> TextConfig {
+        TextConfig {
+            format: SchemaFormat {
+                r#type: "json_schema".to_string(),
+                name: self.name.clone(),
+                schema: self.schema.clone(),
+                strict: true,
+            },
+        }
+    }
+
+    /// Parses the answer as JSON, allowing a surrounding code fence, and
+    /// fails with every violation if it doesn't conform
+    pub fn validate_answer(&self, answer: &str) -> Result<Value> {
+        let value: Value = serde_json::from_str(strip_fence(answer))
+            .context("The answer is not valid JSON, so it can't match the schema")?;
+        let validator = jsonschema::validator_for(&self.schema)
+            .map_err(|err| anyhow::anyhow!("Invalid schema: {}", err))?;
+        let errors: Vec<String> = validator
+            .iter_errors(&value)
+            .map(|err| {
+                let path = err.instance_path().to_string();
+                let path = if path.is_empty() { "/" } else { &path };
+                format!("  {}: {}", path, err)
+            })
+            .collect();
+        if !errors.is_empty() {
+            bail!(
+                "The answer doesn't match the {} schema:\n{}",
+                self.name,
+                errors.join("\n")
+            );
+        }
+        Ok(value)
+    }
+}
+
+/// The API only accepts letters, digits, `_` and `-` in a format name
+fn schema_name(stem: &str) -> String {
+    stem.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn strip_fence(text: &str) -> &str {
+    let text = text.trim();
+    match text.strip_prefix("```") {
+        Some(rest) => {
+            let rest = rest.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+            rest.strip_suffix("```").unwrap_or(rest).trim()
+        }
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn person() -> JsonSchema {
+        JsonSchema {
+            name: "person".to_string(),
+            schema: json!({
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string"},
+                    "age": {"type": "integer", "minimum": 0}
+                },
+                "required": ["name", "age"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    #[test]
+    fn test_text_config_serialization() {
+        let json = serde_json::to_value(person().text_config()).unwrap();
+        assert_eq!(json["format"]["type"], "json_schema");
+        assert_eq!(json["format"]["name"], "person");
+        assert_eq!(json["format"]["strict"], true);
+        assert_eq!(json["format"]["schema"]["required"], json!(["name", "age"]));
+    }
+
+    #[test]
+    fn test_validate_answer() {
+        let schema = person();
+        assert_eq!(
+            schema
+                .validate_answer("```json\n{\"name\": \"Ada\", \"age\": 36}\n```")
+                .unwrap(),
+            json!({"name": "Ada", "age": 36})
+        );
+
+        let err = schema
+            .validate_answer(r#"{"name": "Ada", "age": -1}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("The answer doesn't match the person schema:\n  /age: "));
+
+        let err = schema
+            .validate_answer(r#"{"age": 3}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("  /: \"name\" is a required property"),
+            "{}",
+            err
+        );
+
+        assert!(schema.validate_answer("Ada is 36.").is_err());
+    }
+
+    #[test]
+    fn test_load_checks_the_schema() {
+        let dir = std::env::temp_dir().join("grok-ask-schema-test");
+        fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("my person.json");
+        fs::write(&good, person().schema.to_string()).unwrap();
+        assert_eq!(JsonSchema::load(&good).unwrap().name, "my_person");
+
+        let bad = dir.join("bad.json");
+        fs::write(&bad, r#"{"type": "nonsense"}"#).unwrap();
+        assert!(JsonSchema::load(&bad).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
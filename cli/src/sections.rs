@@ -0,0 +1,86 @@
+//! Splitting a markdown answer into sections by its `##` headings.
+
+use serde_json::{Map, Value};
+
+/// Maps each `##` heading to the trimmed text under it.
+///
+/// Text before the first heading is kept under the empty key, repeated
+/// headings have their bodies joined, and `##` lines inside code fences
+/// are treated as body text.
+pub fn split_by_headings(markdown: &str) -> Map<String, Value> {
+    let mut sections: Vec<(String, String)> = vec![(String::new(), String::new())];
+    let mut in_fence = false;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        match heading(line).filter(|_| !in_fence) {
+            Some(title) => sections.push((title.to_string(), String::new())),
+            None => {
+                let body = &mut sections.last_mut().expect("never empty").1;
+                body.push_str(line);
+                body.push('\n');
+            }
+        }
+    }
+
+    let mut map = Map::new();
+    for (title, body) in sections {
+        let body = body.trim();
+        if title.is_empty() && body.is_empty() {
+            continue;
+        }
+        match map.get_mut(&title) {
+            Some(Value::String(existing)) if !body.is_empty() => {
+                if !existing.is_empty() {
+                    existing.push_str("\n\n");
+                }
+                existing.push_str(body);
+            }
+            Some(_) => {}
+            None => {
+                map.insert(title, Value::String(body.to_string()));
+            }
+        }
+    }
+    map
+}
+
+/// The title of a level-2 ATX heading, without any closing `#`s
+fn heading(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("## ")?;
+    let title = rest.trim().trim_end_matches('#').trim_end();
+    (!title.is_empty()).then_some(title)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_two_headings_become_a_map() {
+        let markdown = "## Summary\nxAI builds Grok.\n\n## Products\n- Grok\n- API\n";
+        assert_eq!(
+            Value::Object(split_by_headings(markdown)),
+            json!({
+                "Summary": "xAI builds Grok.",
+                "Products": "- Grok\n- API",
+            })
+        );
+    }
+
+    #[test]
+    fn test_preamble_fences_and_repeats() {
+        let markdown = "Intro.\n## A\none\n```\n## not a heading\n```\n### Sub\n## A ##\ntwo\n";
+        let sections = split_by_headings(markdown);
+        assert_eq!(sections[""], "Intro.");
+        assert_eq!(
+            sections["A"],
+            "one\n```\n## not a heading\n```\n### Sub\n\ntwo"
+        );
+        assert_eq!(sections.len(), 2);
+    }
+}
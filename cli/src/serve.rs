@@ -0,0 +1,269 @@
+//! A localhost websocket endpoint for `grok-ask serve`.
+//!
+//! Each text message is a query, either bare or as
+//! `{"query": "...", "response_id": "..."}`. The reply is a sequence of JSON
+//! frames: `delta` chunks of answer text as they stream in, then `sources`,
+//! then `done` with the response id to thread the next query onto (or an
+//! `error`). Connections are served one at a time.
+//!
+//! Browsers let any page open a websocket to localhost, so handshakes whose
+//! `Origin` isn't a loopback address are refused; clients that send no
+//! `Origin` (scripts, CLI tools) are accepted.
+
+use crate::stream::send_streaming;
+use crate::{Answer, RequestOptions, Source, Usage};
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+
+/// One message sent back to the client
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Frame {
+    Delta {
+        text: String,
+    },
+    Sources {
+        sources: Vec<Source>,
+    },
+    Done {
+        response_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        usage: Option<Usage>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Frame {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("frames always serialize")
+    }
+}
+
+/// A query received from the client
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct ServeRequest {
+    pub query: String,
+    #[serde(default)]
+    pub response_id: Option<String>,
+}
+
+impl ServeRequest {
+    /// Accepts the JSON form, or treats the whole message as the query
+    pub fn parse(message: &str) -> Self {
+        serde_json::from_str(message).unwrap_or_else(|_| Self {
+            query: message.trim().to_string(),
+            response_id: None,
+        })
+    }
+}
+
+/// The frames that close an answer whose text has already been streamed
+pub fn closing_frames(answer: &Answer) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    if !answer.sources.is_empty() {
+        frames.push(Frame::Sources {
+            sources: answer.sources.clone(),
+        });
+    }
+    frames.push(Frame::Done {
+        response_id: answer.response_id.clone(),
+        usage: answer.usage.clone(),
+    });
+    frames
+}
+
+/// Whether a handshake's `Origin` names a loopback host, e.g.
+/// `http://localhost:3000` or `http://127.0.0.1`
+pub fn is_loopback_origin(origin: &str) -> bool {
+    let Some((_, rest)) = origin.split_once("://") else {
+        return false;
+    };
+    let authority = rest.split('/').next().unwrap_or_default();
+    let host = match authority.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Refuses the handshake when a browser opened it from a non-local page
+// The signature is tungstenite's handshake callback, so the error can't be boxed
+#[allow(clippy::result_large_err)]
+fn check_origin(request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+    let origin = request
+        .headers()
+        .get("origin")
+        .map(|value| value.to_str().unwrap_or_default());
+    match origin {
+        Some(origin) if !is_loopback_origin(origin) => {
+            let mut refusal = ErrorResponse::new(Some(format!("Origin {} not allowed", origin)));
+            *refusal.status_mut() = StatusCode::FORBIDDEN;
+            Err(refusal)
+        }
+        _ => Ok(response),
+    }
+}
+
+/// Serves queries on `127.0.0.1:port` until the process is stopped
+pub async fn serve(port: u16, options: &RequestOptions) -> Result<()> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    eprintln!("Listening on ws://{}", listener.local_addr()?);
+
+    let client = options.http_client();
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                eprintln!("Failed to accept a connection: {}", err);
+                continue;
+            }
+        };
+        let mut socket = match tokio_tungstenite::accept_hdr_async(stream, check_origin).await {
+            Ok(socket) => socket,
+            Err(err) => {
+                eprintln!("Rejected connection from {}: {}", peer, err);
+                continue;
+            }
+        };
+
+        while let Some(message) = socket.next().await {
+            let text = match message {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) | Err(_) => break,
+                Ok(_) => continue,
+            };
+            let request = ServeRequest::parse(&text);
+            let options = RequestOptions {
+                previous_response_id: request.response_id.or(options.previous_response_id.clone()),
+                ..options.clone()
+            };
+
+            // Deltas are forwarded while the request is still streaming
+            let (deltas, mut received) = mpsc::unbounded_channel();
+            let client = &client;
+            let answer = async move {
+                send_streaming(client, &request.query, &options, |update| {
+                    if !update.text.is_empty() {
+                        let _ = deltas.send(update.text.clone());
+                    }
+                })
+                .await
+            };
+            let forward = async {
+                while let Some(text) = received.recv().await {
+                    let frame = Frame::Delta { text };
+                    if socket.send(Message::Text(frame.to_json())).await.is_err() {
+                        return false;
+                    }
+                }
+                true
+            };
+            let (response, connected) = tokio::join!(answer, forward);
+            if !connected {
+                break;
+            }
+
+            let frames = match response {
+                Ok(response) => match Answer::from_response(&response) {
+                    Ok(answer) => closing_frames(&answer),
+                    Err(err) => vec![Frame::Error {
+                        message: err.to_string(),
+                    }],
+                },
+                Err(err) => vec![Frame::Error {
+                    message: format!("{:#}", err),
+                }],
+            };
+            for frame in frames {
+                if socket.send(Message::Text(frame.to_json())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_serialization() {
+        let answer = Answer {
+            text: "Grok is by xAI.".to_string(),
+            sources: vec![Source {
+                title: "xAI".to_string(),
+                url: "https://x.ai".to_string(),
+            }],
+            response_id: Some("resp_1".to_string()),
+            usage: None,
+        };
+        let frames: Vec<String> = closing_frames(&answer).iter().map(Frame::to_json).collect();
+        assert_eq!(
+            frames,
+            vec![
+                r#"{"type":"sources","sources":[{"title":"xAI","url":"https://x.ai"}]}"#,
+                r#"{"type":"done","response_id":"resp_1"}"#,
+            ]
+        );
+        assert_eq!(
+            Frame::Delta {
+                text: "Grok is".to_string()
+            }
+            .to_json(),
+            r#"{"type":"delta","text":"Grok is"}"#
+        );
+        assert_eq!(
+            Frame::Error {
+                message: "boom".to_string()
+            }
+            .to_json(),
+            r#"{"type":"error","message":"boom"}"#
+        );
+    }
+
+    #[test]
+    fn test_request_parsing() {
+        assert_eq!(
+            ServeRequest::parse(r#"{"query": "more", "response_id": "resp_1"}"#),
+            ServeRequest {
+                query: "more".to_string(),
+                response_id: Some("resp_1".to_string()),
+            }
+        );
+        assert_eq!(ServeRequest::parse("  What is xAI? ").query, "What is xAI?");
+    }
+
+    #[test]
+    fn test_only_loopback_origins_allowed() {
+        for origin in [
+            "http://localhost:3000",
+            "http://127.0.0.1",
+            "https://[::1]:8080/app",
+            "http://LOCALHOST",
+        ] {
+            assert!(is_loopback_origin(origin), "{}", origin);
+        }
+        for origin in [
+            "https://evil.example",
+            "http://localhost.evil.example",
+            "http://127.0.0.1.evil.example",
+            "null",
+        ] {
+            assert!(!is_loopback_origin(origin), "{}", origin);
+        }
+    }
+}
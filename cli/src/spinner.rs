@@ -0,0 +1,83 @@
+//! An elapsed-time spinner on stderr while a request is in flight.
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const TICK: Duration = Duration::from_millis(100);
+
+/// Whether to show the spinner: only on a terminal, and never alongside
+/// streamed text, JSON output, or `--verbose` logging
+pub fn spinner_enabled(stderr_is_tty: bool, stream: bool, json: bool, verbose: bool) -> bool {
+    stderr_is_tty && !stream && !json && !verbose
+}
+
+/// One frame of the spinner, e.g. `⠋ Thinking… 12s`
+pub fn frame_line(tick: usize, label: &str, elapsed: Duration) -> String {
+    format!(
+        "{} {}\u{2026} {}s",
+        FRAMES[tick % FRAMES.len()],
+        label,
+        elapsed.as_secs()
+    )
+}
+
+/// Redraws the spinner until dropped, then erases it
+pub struct Spinner {
+    stopped: Arc<Mutex<bool>>,
+}
+
+impl Spinner {
+    /// Starts drawing on a background task; must be called inside a tokio runtime
+    pub fn start(label: &'static str) -> Self {
+        let stopped = Arc::new(Mutex::new(false));
+        let flag = Arc::clone(&stopped);
+        let start = Instant::now();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TICK);
+            for tick in 0.. {
+                interval.tick().await;
+                // Holding the lock while drawing keeps a frame from landing after the erase
+                let stopped = flag.lock().unwrap_or_else(|e| e.into_inner());
+                if *stopped {
+                    break;
+                }
+                let mut stderr = io::stderr().lock();
+                let _ = write!(stderr, "\r{}", frame_line(tick, label, start.elapsed()));
+                let _ = stderr.flush();
+            }
+        });
+        Self { stopped }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        let mut stopped = self.stopped.lock().unwrap_or_else(|e| e.into_inner());
+        *stopped = true;
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_and_gating() {
+        assert_eq!(
+            frame_line(0, "Thinking", Duration::from_millis(12_400)),
+            "⠋ Thinking… 12s"
+        );
+        assert_eq!(frame_line(11, "Thinking", Duration::ZERO), "⠙ Thinking… 0s");
+
+        assert!(spinner_enabled(true, false, false, false));
+        assert!(!spinner_enabled(false, false, false, false));
+        assert!(!spinner_enabled(true, true, false, false));
+        assert!(!spinner_enabled(true, false, true, false));
+        assert!(!spinner_enabled(true, false, false, true));
+    }
+}
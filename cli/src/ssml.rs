@@ -0,0 +1,125 @@
+//! Speech-friendly rendering of an answer as SSML.
+//!
+//! Markdown formatting is dropped, links keep only their text, and numeric
+//! citations like `[1]` become `<sub alias="">` spans so a synthesizer skips
+//! them while the markup still records where they were.
+
+/// Wraps a markdown answer in `<speak>`, one `<p>` per paragraph
+pub fn to_ssml(markdown: &str) -> String {
+    let paragraphs: Vec<String> = markdown
+        .split("\n\n")
+        .map(render_paragraph)
+        .filter(|p| !p.is_empty())
+        .map(|p| format!("<p>{}</p>", p))
+        .collect();
+    format!("<speak>\n{}\n</speak>\n", paragraphs.join("\n"))
+}
+
+fn render_paragraph(paragraph: &str) -> String {
+    let text = paragraph
+        .lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches('#')
+                .trim_start_matches('>')
+                .trim_start()
+        })
+        .map(|line| {
+            line.strip_prefix("- ")
+                .or_else(|| line.strip_prefix("* "))
+                .unwrap_or(line)
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    render_inline(&text)
+}
+
+fn render_inline(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '[' {
+            if let Some((label, after)) = split_link(rest) {
+                let citation = label.trim_matches(['[', ']']);
+                if !citation.is_empty() && citation.chars().all(|c| c.is_ascii_digit()) {
+                    out.push_str(&format!("<sub alias=\"\">[{}]</sub>", citation));
+                } else {
+                    out.push_str(&render_inline(label));
+                }
+                rest = after;
+                continue;
+            }
+        }
+        match c {
+            '*' | '`' => {}
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    out
+}
+
+/// Splits `[label](url)` or `[label]` at the start of `text` into the label
+/// and whatever follows; nested brackets (as in `[[1]](url)`) are allowed
+fn split_link(text: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    let close = text.char_indices().find_map(|(i, c)| {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        None
+    })?;
+    let label = &text[1..close];
+    let after = &text[close + 1..];
+    match after
+        .strip_prefix('(')
+        .and_then(|url| url.find(')').map(|end| &url[end + 1..]))
+    {
+        Some(after_url) => Some((label, after_url)),
+        None => Some((label, after)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strips_markdown_and_wraps_in_speak() {
+        let answer =
+            "## Summary\n\n**xAI** builds [Grok](https://x.ai/grok) & more.\n\n- One\n- `Two`";
+        assert_eq!(
+            to_ssml(answer),
+            "<speak>\n<p>Summary</p>\n<p>xAI builds Grok &amp; more.</p>\n<p>One Two</p>\n</speak>\n"
+        );
+    }
+
+    #[test]
+    fn test_citations_become_omittable_spans() {
+        let ssml = to_ssml("Grok launched in 2023 [1] and grew [[2]](https://example.com).");
+        assert_eq!(
+            ssml,
+            "<speak>\n<p>Grok launched in 2023 <sub alias=\"\">[1]</sub> and grew <sub alias=\"\">[2]</sub>.</p>\n</speak>\n"
+        );
+        assert!(!ssml.contains("https://"));
+    }
+
+    #[test]
+    fn test_unclosed_bracket_is_kept() {
+        assert_eq!(to_ssml("a [b"), "<speak>\n<p>a [b</p>\n</speak>\n");
+    }
+}
@@ -0,0 +1,290 @@
+//! Streaming an answer as server-sent events.
+//!
+//! With `stream: true` the API sends the answer text as
+//! `response.output_text.delta` events and finishes with the whole response.
+//! Sources usually only arrive in that final block, but citations and search
+//! results that stream in earlier are reported as soon as each one is
+//! confirmed, deduplicated the same way as [`collect_sources`].
+//!
+//! [`collect_sources`]: crate::collect_sources
+
+use crate::{
+    auth_header, build_request, describe_request, effective_timeout, endpoint_url, request_error,
+    Annotation, ApiStatusError, GrokResponse, Output, RequestOptions, SourceSet,
+};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+/// Splits a response body into events, holding back any incomplete one
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseDecoder {
+    /// Feeds the next chunk of the body, returning the data of each event it completes
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend(chunk.iter().filter(|&&b| b != b'\r'));
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            events.extend(event_data(&String::from_utf8_lossy(&block)));
+        }
+        events
+    }
+
+    /// The data of a final event that wasn't followed by a blank line
+    pub fn finish(&mut self) -> Option<String> {
+        let block = String::from_utf8_lossy(&std::mem::take(&mut self.buffer)).into_owned();
+        event_data(&block)
+    }
+}
+
+/// The joined `data:` lines of one event block, ignoring `[DONE]` markers
+fn event_data(block: &str) -> Option<String> {
+    let lines: Vec<&str> = block
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+    let data = lines.join("\n");
+    (!lines.is_empty() && data != "[DONE]").then_some(data)
+}
+
+/// One streamed event; only the fields this client uses
+#[derive(Deserialize, Debug, Clone)]
+pub struct StreamEvent {
+    pub r#type: String,
+    #[serde(default)]
+    pub delta: Option<String>,
+    #[serde(default)]
+    pub annotation: Option<Annotation>,
+    #[serde(default)]
+    pub item: Option<Output>,
+    #[serde(default)]
+    pub response: Option<GrokResponse>,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// What an event added: answer text and sources not seen before
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StreamUpdate {
+    pub text: String,
+    pub sources: Vec<(String, String)>,
+}
+
+/// Everything received so far on one stream
+#[derive(Debug, Default)]
+pub struct StreamState {
+    sources: SourceSet,
+    response: Option<GrokResponse>,
+    error: Option<String>,
+}
+
+impl StreamState {
+    pub fn apply(&mut self, event: &StreamEvent) -> StreamUpdate {
+        let mut update = StreamUpdate::default();
+        match event.r#type.as_str() {
+            "response.output_text.delta" => {
+                update.text = event.delta.clone().unwrap_or_default();
+            }
+            "response.output_text.annotation.added" => {
+                if let Some(ann) = &event.annotation {
+                    update.sources.extend(self.sources.insert_annotation(ann));
+                }
+            }
+            "response.output_item.done" => {
+                if let Some(item) = &event.item {
+                    update.sources = self.sources.extend_from_output(item);
+                }
+            }
+            "response.completed" | "response.incomplete" | "response.failed" => {
+                if let Some(response) = &event.response {
+                    for out in response.output.iter().flatten() {
+                        update.sources.extend(self.sources.extend_from_output(out));
+                    }
+                    self.response = Some(response.clone());
+                }
+            }
+            "error" => {
+                self.error = Some(
+                    event
+                        .message
+                        .clone()
+                        .unwrap_or_else(|| "Unknown error".to_string()),
+                );
+            }
+            _ => {}
+        }
+        update
+    }
+
+    /// The final response, once the stream has closed
+    pub fn finish(self) -> Result<GrokResponse> {
+        if let Some(error) = self.error {
+            bail!("Stream failed: {}", error);
+        }
+        self.response
+            .context("Stream ended before the response completed")
+    }
+}
+
+/// Sends `query` as a streaming request, passing each update to `on_update`
+/// as it arrives and returning the final response. Streams aren't retried or
+/// recorded to cassettes.
+pub async fn send_streaming(
+    client: &reqwest::Client,
+    query: &str,
+    options: &RequestOptions,
+    mut on_update: impl FnMut(&StreamUpdate),
+) -> Result<GrokResponse> {
+    let mut request = build_request(query, options);
+    request.stream = Some(true);
+
+    let api_key = options.resolve_api_key()?;
+    let timeout = effective_timeout(options.timeout);
+    let endpoint = endpoint_url(options.endpoint.as_deref());
+    let (auth_name, auth_value) = auth_header(
+        options.auth_scheme,
+        options.auth_header.as_deref(),
+        &api_key,
+    );
+    if options.verbose {
+        eprint!("{}", describe_request(&endpoint, &auth_name, &request));
+    }
+
+    let mut builder = client
+        .post(&endpoint)
+        .header(&auth_name, &auth_value)
+        .header("Content-Type", "application/json")
+        .header("Accept", "text/event-stream")
+        .json(&request);
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    let mut response = builder
+        .send()
+        .await
+        .map_err(|e| request_error(e, timeout, "Failed to send request"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(ApiStatusError {
+            status,
+            body: body.trim().to_string(),
+        }
+        .into());
+    }
+
+    let mut decoder = SseDecoder::default();
+    let mut state = StreamState::default();
+    let mut handle = |data: String, state: &mut StreamState| -> Result<()> {
+        if options.verbose {
+            eprintln!("Event: {}", data);
+        }
+        let event: StreamEvent =
+            serde_json::from_str(&data).context("Failed to parse stream event")?;
+        on_update(&state.apply(&event));
+        Ok(())
+    };
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| request_error(e, timeout, "Failed to read response"))?
+    {
+        for data in decoder.push(&chunk) {
+            handle(data, &mut state)?;
+        }
+    }
+    if let Some(data) = decoder.finish() {
+        handle(data, &mut state)?;
+    }
+    let response = state.finish()?;
+    if let Some(log) = &options.audit_log {
+        log.record(query, &request, &response)?;
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(body: &str, chunk_size: usize) -> Vec<StreamEvent> {
+        let mut decoder = SseDecoder::default();
+        let mut data = Vec::new();
+        for chunk in body.as_bytes().chunks(chunk_size) {
+            data.extend(decoder.push(chunk));
+        }
+        data.extend(decoder.finish());
+        data.iter()
+            .map(|d| serde_json::from_str(d).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_decoder_reassembles_split_events() {
+        let body = "event: response.output_text.delta\r\ndata: {\"type\":\"response.output_text.delta\",\"delta\":\"Hi\"}\r\n\r\n: keep-alive\n\ndata: {\"type\":\"response.output_text.delta\",\n\
+                    data: \"delta\":\" there\"}\n\ndata: [DONE]\n\n";
+        for chunk_size in [1, 7, body.len()] {
+            let deltas: Vec<String> = events(body, chunk_size)
+                .into_iter()
+                .map(|e| e.delta.unwrap())
+                .collect();
+            assert_eq!(deltas, vec!["Hi", " there"], "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_sources_are_emitted_once_as_they_arrive() {
+        let body = concat!(
+            "data: {\"type\":\"response.output_text.delta\",\"delta\":\"xAI \"}\n\n",
+            "data: {\"type\":\"response.output_item.done\",\"item\":{\"type\":\"web_search_result\",\"results\":[",
+            "{\"url\":\"https://x.ai\",\"title\":\"xAI\"},{\"url\":\"https://docs.x.ai\"}]}}\n\n",
+            "data: {\"type\":\"response.output_text.annotation.added\",\"annotation\":{\"url\":\"https://x.ai\",\"title\":\"Again\"}}\n\n",
+            "data: {\"type\":\"response.output_text.delta\",\"delta\":\"builds Grok.\"}\n\n",
+            "data: {\"type\":\"response.output_text.annotation.added\",\"annotation\":{\"url\":\"https://grok.com\",\"title\":\"Grok\"}}\n\n",
+            "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[",
+            "{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"xAI builds Grok.\",\"annotations\":[",
+            "{\"url\":\"https://grok.com\",\"title\":\"Grok\"},{\"url\":\"https://x.com/xai\",\"title\":\"X\"}]}]}]}}\n\n",
+        );
+
+        let mut state = StreamState::default();
+        let updates: Vec<StreamUpdate> = events(body, 16)
+            .iter()
+            .map(|event| state.apply(event))
+            .collect();
+        let sources: Vec<Vec<&str>> = updates
+            .iter()
+            .map(|u| u.sources.iter().map(|(_, url)| url.as_str()).collect())
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                vec![],
+                vec!["https://x.ai", "https://docs.x.ai"],
+                vec![],
+                vec![],
+                vec!["https://grok.com"],
+                vec!["https://x.com/xai"],
+            ]
+        );
+        assert_eq!(updates[1].sources[1].0, "Web Result");
+        let text: String = updates.iter().map(|u| u.text.as_str()).collect();
+        assert_eq!(text, "xAI builds Grok.");
+        assert_eq!(state.finish().unwrap().id.as_deref(), Some("resp_1"));
+    }
+
+    #[test]
+    fn test_stream_without_final_response_fails() {
+        let mut state = StreamState::default();
+        state.apply(&events("data: {\"type\":\"error\",\"message\":\"overloaded\"}", 8)[0]);
+        assert_eq!(
+            state.finish().unwrap_err().to_string(),
+            "Stream failed: overloaded"
+        );
+        assert!(StreamState::default().finish().is_err());
+    }
+}
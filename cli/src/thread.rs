@@ -0,0 +1,181 @@
+//! Named conversation threads persisted between runs.
+//!
+//! A thread is a JSON file under the threads directory recording each turn's
+//! query, response id, and token usage, so a conversation can be resumed
+//! from its last response and its total cost tallied later.
+
+use crate::{unix_now, GrokResponse, Usage};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Turn {
+    pub query: String,
+    pub response_id: String,
+    pub usage: Option<Usage>,
+    pub created_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Thread {
+    pub turns: Vec<Turn>,
+}
+
+/// Token totals across a thread's turns
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadUsage {
+    pub turns: usize,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+/// Path of a thread's file; names are limited to letters, digits, `-` and `_`
+pub fn thread_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        bail!(
+            "Invalid thread name `{}`: use letters, digits, - and _",
+            name
+        );
+    }
+    Ok(dir.join(format!("{}.json", name)))
+}
+
+impl Thread {
+    /// Loads a thread, starting empty if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read thread {}", path.display()))?;
+        serde_json::from_str(&data)
+            .with_context(|| format!("Invalid thread file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let data = serde_json::to_string_pretty(self)?;
+        fs::write(path, data).with_context(|| format!("Failed to write thread {}", path.display()))
+    }
+
+    /// The response the next turn should follow up on
+    pub fn last_response_id(&self) -> Option<&str> {
+        self.turns.last().map(|t| t.response_id.as_str())
+    }
+
+    /// Records a finished turn; responses without an id can't be resumed and are skipped
+    pub fn push(&mut self, query: &str, response: &GrokResponse) {
+        if let Some(id) = &response.id {
+            self.turns.push(Turn {
+                query: query.to_string(),
+                response_id: id.clone(),
+                usage: response.usage.clone(),
+                created_at: unix_now(),
+            });
+        }
+    }
+
+    pub fn usage(&self) -> ThreadUsage {
+        total_usage(&self.turns)
+    }
+}
+
+/// Sums token usage over `turns`; turns without usage count as zero
+pub fn total_usage(turns: &[Turn]) -> ThreadUsage {
+    turns
+        .iter()
+        .fold(ThreadUsage::default(), |mut total, turn| {
+            total.turns += 1;
+            if let Some(usage) = &turn.usage {
+                total.input_tokens += u64::from(usage.input_tokens.unwrap_or(0));
+                total.output_tokens += u64::from(usage.output_tokens.unwrap_or(0));
+            }
+            total
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn turn(id: &str, input: u32, output: u32) -> Turn {
+        Turn {
+            query: "q".to_string(),
+            response_id: id.to_string(),
+            usage: Some(Usage {
+                input_tokens: Some(input),
+                output_tokens: Some(output),
+            }),
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_total_usage_accumulates_turns() {
+        let mut turns = vec![turn("a", 100, 50), turn("b", 250, 75), turn("c", 400, 120)];
+        turns.push(Turn {
+            usage: None,
+            ..turn("d", 0, 0)
+        });
+
+        assert_eq!(
+            total_usage(&turns),
+            ThreadUsage {
+                turns: 4,
+                input_tokens: 750,
+                output_tokens: 245,
+            }
+        );
+        assert_eq!(total_usage(&[]), ThreadUsage::default());
+    }
+
+    #[test]
+    fn test_thread_round_trip() {
+        let dir = env::temp_dir().join("grok-ask-thread-test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = thread_path(&dir, "research").unwrap();
+
+        let mut thread = Thread::load(&path).unwrap();
+        assert!(thread.last_response_id().is_none());
+
+        let response = GrokResponse {
+            id: Some("resp_1".to_string()),
+            status: Some("completed".to_string()),
+            finish_reason: None,
+            incomplete_details: None,
+            output: None,
+            usage: Some(Usage {
+                input_tokens: Some(10),
+                output_tokens: Some(20),
+            }),
+            error: None,
+            meta: None,
+        };
+        thread.push("first", &response);
+        thread.save(&path).unwrap();
+
+        let reloaded = Thread::load(&path).unwrap();
+        assert_eq!(reloaded, thread);
+        assert_eq!(reloaded.last_response_id(), Some("resp_1"));
+        assert_eq!(reloaded.usage().output_tokens, 20);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_thread_names_validated() {
+        let dir = Path::new("threads");
+        assert!(thread_path(dir, "my-thread_2").is_ok());
+        assert!(thread_path(dir, "../escape").is_err());
+        assert!(thread_path(dir, "").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Which tools produced each response, so follow-ups can be checked against it.
+//!
+//! A follow-up with different tools than the response it continues (an
+//! `x-search` after a web `ask`, say) gets confusing answers, because the
+//! model's earlier tool results no longer match what it can call. Each
+//! response id is recorded with its tool set in a small JSON file, keeping
+//! only the most recent ones, and a follow-up whose tools differ is warned
+//! about. This is best-effort: ids from other machines or older runs are
+//! simply unknown.
+
+use crate::{GrokRequest, Tool};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// How many response ids are remembered
+pub const MAX_ENTRIES: usize = 500;
+
+/// The tools a request can call, e.g. `web_search+x_search`, or `none`
+pub fn tool_context(request: &GrokRequest) -> String {
+    let mut names: Vec<&str> = request
+        .tools
+        .iter()
+        .map(|tool| match tool {
+            Tool::WebSearch(tool) => tool.r#type.as_str(),
+            Tool::XSearch(tool) => tool.r#type.as_str(),
+        })
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join("+")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContextEntry {
+    pub response_id: String,
+    pub tools: String,
+}
+
+/// Recorded tool contexts, oldest first
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ToolContexts {
+    pub entries: Vec<ContextEntry>,
+}
+
+impl ToolContexts {
+    /// Loads the record, starting empty if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&data).with_context(|| format!("Invalid {}", path.display()))
+    }
+
+    /// Writes to a temporary file and renames it into place, so a concurrent
+    /// run or an interrupted write never leaves a torn record
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let data = serde_json::to_string(self)?;
+        let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temp, data).with_context(|| format!("Failed to write {}", temp.display()))?;
+        fs::rename(&temp, path).with_context(|| {
+            let _ = fs::remove_file(&temp);
+            format!("Failed to write {}", path.display())
+        })
+    }
+
+    pub fn get(&self, response_id: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.response_id == response_id)
+            .map(|e| e.tools.as_str())
+    }
+
+    /// Remembers a response's tools, dropping the oldest past [`MAX_ENTRIES`]
+    pub fn record(&mut self, response_id: &str, tools: &str) {
+        self.entries.retain(|e| e.response_id != response_id);
+        self.entries.push(ContextEntry {
+            response_id: response_id.to_string(),
+            tools: tools.to_string(),
+        });
+        let excess = self.entries.len().saturating_sub(MAX_ENTRIES);
+        self.entries.drain(..excess);
+    }
+
+    /// A warning if a follow-up to `response_id` would use different tools
+    pub fn mismatch_warning(&self, response_id: &str, tools: &str) -> Option<String> {
+        let previous = self.get(response_id)?;
+        (previous != tools).then(|| {
+            format!(
+                "Warning: {} was answered with tools: {}, but this follow-up uses: {}; the answer may not build on the earlier results",
+                response_id, previous, tools
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_request, RequestOptions};
+
+    #[test]
+    fn test_mismatched_follow_up_warns() {
+        let web = build_request(
+            "q",
+            &RequestOptions {
+                use_web_search: true,
+                ..Default::default()
+            },
+        );
+        let both = build_request(
+            "q",
+            &RequestOptions {
+                use_web_search: true,
+                use_x_search: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(tool_context(&web), "web_search");
+        assert_eq!(tool_context(&both), "web_search+x_search");
+        assert_eq!(
+            tool_context(&build_request("q", &RequestOptions::default())),
+            "none"
+        );
+
+        let mut contexts = ToolContexts::default();
+        contexts.record("resp_1", "web_search");
+        assert!(contexts.mismatch_warning("resp_1", "web_search").is_none());
+        assert!(contexts
+            .mismatch_warning("resp_unknown", "x_search")
+            .is_none());
+        let warning = contexts.mismatch_warning("resp_1", "x_search").unwrap();
+        assert!(warning.contains("tools: web_search, but this follow-up uses: x_search"));
+    }
+
+    #[test]
+    fn test_record_keeps_most_recent_and_round_trips() {
+        let mut contexts = ToolContexts::default();
+        for i in 0..MAX_ENTRIES + 5 {
+            contexts.record(&format!("resp_{}", i), "none");
+        }
+        contexts.record("resp_10", "x_search");
+        assert_eq!(contexts.entries.len(), MAX_ENTRIES);
+        assert!(contexts.get("resp_0").is_none());
+        assert_eq!(contexts.get("resp_10"), Some("x_search"));
+        assert_eq!(contexts.entries.last().unwrap().response_id, "resp_10");
+
+        let path = std::env::temp_dir()
+            .join("grok-ask-tool-context-test")
+            .join("contexts.json");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{\"entries\": [").unwrap();
+        assert!(ToolContexts::load(&path).is_err());
+        contexts.save(&path).unwrap();
+        assert_eq!(ToolContexts::load(&path).unwrap(), contexts);
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
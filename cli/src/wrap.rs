@@ -0,0 +1,130 @@
+//! Hard-wrapping for terminal output.
+
+/// Wraps every line to at most `width` characters, breaking at whitespace.
+/// Words longer than the width (typically URLs) are kept whole on their own line.
+/// Indented lines and list items keep their indentation on continuation lines,
+/// and lines in ``` fenced code blocks are left as they are.
+pub fn wrap_text(text: &str, width: usize) -> String {
+    if width == 0 {
+        return text.to_string();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut in_fence = false;
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let fence = line.trim_start().starts_with("```");
+        if in_fence || fence {
+            out.push_str(line);
+        } else {
+            wrap_line(line, width, &mut out);
+        }
+        in_fence ^= fence;
+    }
+    out
+}
+
+fn wrap_line(line: &str, width: usize, out: &mut String) {
+    let text = line.trim_start();
+    if text.is_empty() {
+        return;
+    }
+    let indent = &line[..line.len() - text.len()];
+    // Continuations line up with the item's text rather than its marker
+    let hanging = format!("{}{}", indent, " ".repeat(list_marker_len(text)));
+
+    out.push_str(indent);
+    let mut current = indent.chars().count();
+    let mut line_start = true;
+    for word in text.split_whitespace() {
+        let len = word.chars().count();
+        if !line_start && current + 1 + len > width {
+            out.push('\n');
+            out.push_str(&hanging);
+            current = hanging.chars().count();
+        } else if !line_start {
+            out.push(' ');
+            current += 1;
+        }
+        out.push_str(word);
+        current += len;
+        line_start = false;
+    }
+}
+
+/// Width of a leading `- `, `* `, `+ ` or `1. ` list marker, or 0
+fn list_marker_len(text: &str) -> usize {
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    let rest = &text[digits..];
+    let marker = match digits {
+        0 => ["- ", "* ", "+ "].iter().any(|m| rest.starts_with(m)),
+        _ => rest.starts_with(". ") || rest.starts_with(") "),
+    };
+    // The marker's digits, if any, plus its punctuation and space
+    if marker {
+        digits + 2
+    } else {
+        0
+    }
+}
+
+/// Length in characters of the longest line
+pub fn longest_line(text: &str) -> usize {
+    text.lines().map(|l| l.chars().count()).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wraps_single_long_line() {
+        let text = "one two three four five six";
+        assert_eq!(wrap_text(text, 10), "one two\nthree four\nfive six");
+    }
+
+    #[test]
+    fn test_preserves_existing_newlines() {
+        assert_eq!(wrap_text("ab cd\n\nef", 2), "ab\ncd\n\nef");
+    }
+
+    #[test]
+    fn test_keeps_overlong_words_whole() {
+        assert_eq!(
+            wrap_text("see https://example.com/long now", 10),
+            "see\nhttps://example.com/long\nnow"
+        );
+    }
+
+    #[test]
+    fn test_keeps_indentation_and_hangs_list_items() {
+        assert_eq!(
+            wrap_text("    indented code-ish text here", 16),
+            "    indented\n    code-ish\n    text here"
+        );
+        assert_eq!(
+            wrap_text("- first item runs long\n12. next one", 12),
+            "- first item\n  runs long\n12. next one"
+        );
+    }
+
+    #[test]
+    fn test_fenced_code_is_left_alone() {
+        let text = "Run this:\n```sh\ncargo build --workspace --all-targets\n```\nthen test it";
+        assert_eq!(
+            wrap_text(text, 10),
+            "Run this:\n```sh\ncargo build --workspace --all-targets\n```\nthen test\nit"
+        );
+    }
+
+    #[test]
+    fn test_longest_line_detection() {
+        let giant = "x".repeat(50_000);
+        assert_eq!(longest_line(&giant), 50_000);
+        assert_eq!(longest_line("short\nlines"), 5);
+        assert_eq!(longest_line(""), 0);
+        assert!(longest_line(&wrap_text(&"word ".repeat(10_000), 80)) <= 80);
+    }
+}